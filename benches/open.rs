@@ -0,0 +1,91 @@
+use std::io::Write;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const KEY: &str = "169841";
+
+/// Builds an archive containing `files` files spread over 100 directories.
+///
+/// With `interleaved` set, files are created round-robin across the directories and carry data,
+/// scattering the blocks of each directory throughout the archive. Otherwise the directories are
+/// filled one after another with empty files, storing their blocks back to back.
+fn build_archive(files: usize, interleaved: bool) -> Vec<u8> {
+    let mut archive = pk2::unsync::Pk2::create_new_in_memory(KEY).unwrap();
+    for i in 0..files {
+        let dir = if interleaved { i % 100 } else { i * 100 / files };
+        let mut file = archive.create_file(format!("/dir{dir}/file{i}.txt")).unwrap();
+        if interleaved {
+            file.write_all(&i.to_le_bytes()).unwrap();
+        }
+    }
+    archive.into()
+}
+
+fn open(c: &mut Criterion) {
+    let mut group = c.benchmark_group("open");
+    group.sample_size(10);
+    for files in [1_000, 50_000] {
+        for (layout, interleaved) in [("interleaved", true), ("contiguous", false)] {
+            let path = std::env::temp_dir().join(format!("pk2_bench_open_{layout}_{files}.pk2"));
+            std::fs::write(&path, build_archive(files, interleaved)).unwrap();
+            group.bench_with_input(BenchmarkId::new(layout, files), &path, |b, path| {
+                b.iter(|| pk2::unsync::readonly::Pk2::open_readonly(path, KEY).unwrap())
+            });
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+    group.finish();
+}
+
+/// Opens an archive with 50k files from disk with the default read-ahead, which only reads ahead
+/// on sequential reads, and with a fixed read-ahead window.
+fn read_ahead(c: &mut Criterion) {
+    let mut group = c.benchmark_group("read_ahead");
+    group.sample_size(10);
+    for (layout, interleaved) in [("interleaved", true), ("contiguous", false)] {
+        let path = std::env::temp_dir().join(format!("pk2_bench_read_ahead_{layout}.pk2"));
+        std::fs::write(&path, build_archive(50_000, interleaved)).unwrap();
+        for (name, window) in [("default", None), ("1MiB", Some(1 << 20))] {
+            group.bench_with_input(BenchmarkId::new(layout, name), &path, |b, path| {
+                b.iter(|| {
+                    let mut options = pk2::OpenOptions::new().index_sidecar(false);
+                    if let Some(window) = window {
+                        options = options.read_ahead(window);
+                    }
+                    pk2::unsync::Pk2::open_with(path, KEY, options).unwrap()
+                })
+            });
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+    group.finish();
+}
+
+/// Opens 500 small archives encrypted with the same key, deriving the cipher once and sharing it
+/// versus deriving it again for every archive.
+fn shared_cipher(c: &mut Criterion) {
+    let mut group = c.benchmark_group("shared_cipher");
+    group.sample_size(10);
+    let archive = build_archive(10, false);
+    let archives = vec![archive; 500];
+    group.bench_function("per_archive", |b| {
+        b.iter(|| {
+            for archive in &archives {
+                pk2::unsync::Pk2::open_in(std::io::Cursor::new(&archive[..]), KEY).unwrap();
+            }
+        })
+    });
+    group.bench_function("shared", |b| {
+        b.iter(|| {
+            let cipher = pk2::Blowfish::new(KEY.as_bytes()).unwrap();
+            for archive in &archives {
+                let stream = std::io::Cursor::new(&archive[..]);
+                pk2::unsync::Pk2::open_in_with_cipher(stream, &cipher).unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, open, read_ahead, shared_cipher);
+criterion_main!(benches);
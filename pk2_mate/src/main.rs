@@ -1,7 +1,8 @@
 use clap::{crate_authors, crate_description, crate_name, crate_version};
 use clap::{App, Arg, ArgMatches, SubCommand};
-use filetime::FileTime;
-use pk2::unsync::{DirEntry, Directory, Pk2};
+use pk2::unsync::{DirEntry, Directory, File, Pk2, Pk2Builder};
+use pk2::{ExtractOptions, OnConflict};
+use serde::{Deserialize, Serialize};
 
 use std::path::{Path, PathBuf};
 
@@ -10,16 +11,30 @@ fn main() {
         .version(crate_version!())
         .author(crate_authors!())
         .about(crate_description!())
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .global(true)
+                .help("If passed, only prints the changes mutating commands would make"),
+        )
         .subcommand(extract_app())
         .subcommand(repack_app())
         .subcommand(pack_app())
-        .subcommand(list_app());
+        .subcommand(patch_app())
+        .subcommand(lint_app())
+        .subcommand(find_app())
+        .subcommand(list_app())
+        .subcommand(info_app());
     let matches = app.get_matches();
     match matches.subcommand() {
         ("extract", Some(matches)) => extract(matches),
         ("repack", Some(matches)) => repack(matches),
         ("pack", Some(matches)) => pack(matches),
+        ("patch", Some(matches)) => patch(matches),
+        ("lint", Some(matches)) => lint(matches),
+        ("find", Some(matches)) => find(matches),
         ("list", Some(matches)) => list(matches),
+        ("info", Some(matches)) => info(matches),
         _ => println!("{}", matches.usage()),
     }
 }
@@ -33,6 +48,29 @@ fn key_arg() -> Arg<'static, 'static> {
         .default_value("169841")
 }
 
+fn key_file_arg() -> Arg<'static, 'static> {
+    Arg::with_name("key-file")
+        .long("key-file")
+        .takes_value(true)
+        .conflicts_with("key")
+        .help("Reads the blowfish key from the given file instead of the command line")
+}
+
+/// Returns the blowfish key, read from the `--key-file` if given.
+fn read_key(matches: &ArgMatches<'static>) -> Vec<u8> {
+    match matches.value_of_os("key-file") {
+        Some(path) => {
+            let mut key = std::fs::read(path)
+                .unwrap_or_else(|e| panic!("failed to read key file {:?}: {}", path, e));
+            while key.last().is_some_and(|&b| b == b'\n' || b == b'\r') {
+                key.pop();
+            }
+            key
+        }
+        None => matches.value_of("key").unwrap().as_bytes().to_vec(),
+    }
+}
+
 fn extract_app() -> App<'static, 'static> {
     SubCommand::with_name("extract")
         .version(crate_version!())
@@ -47,6 +85,7 @@ fn extract_app() -> App<'static, 'static> {
                 .help("Sets the archive to open"),
         )
         .arg(key_arg().help("Sets the blowfish key"))
+        .arg(key_file_arg())
         .arg(
             Arg::with_name("out")
                 .short("o")
@@ -60,52 +99,145 @@ fn extract_app() -> App<'static, 'static> {
                 .long("time")
                 .help("If passed, writes file times to the extracted files"),
         )
+        .arg(
+            Arg::with_name("flatten")
+                .short("f")
+                .long("flatten")
+                .help("If passed, extracts all files directly into the output path"),
+        )
+        .arg(
+            Arg::with_name("on-conflict")
+                .long("on-conflict")
+                .takes_value(true)
+                .possible_values(&["skip", "overwrite", "rename"])
+                .default_value("skip")
+                .help("Sets how files with the same name are handled when flattening"),
+        )
+        .arg(
+            Arg::with_name("depth")
+                .long("depth")
+                .takes_value(true)
+                .help("Sets how many directory levels are descended into, unlimited by default"),
+        )
+        .arg(
+            Arg::with_name("include")
+                .long("include")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Only extracts files whose path matches one of the given globs"),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .long("exclude")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Does not extract files whose path matches one of the given globs"),
+        )
+        .arg(
+            Arg::with_name("tar")
+                .long("tar")
+                .takes_value(true)
+                .conflicts_with("flatten")
+                .help("If passed, writes the files into a tar archive at the given path instead"),
+        )
+        .arg(
+            Arg::with_name("manifest")
+                .long("manifest")
+                .takes_value(true)
+                .conflicts_with("tar")
+                .help("If passed, writes a JSON manifest of all extracted files to the given path"),
+        )
+}
+
+/// An extracted file as recorded in the manifest written by `extract --manifest` and read by
+/// `pack --from-manifest`.
+#[derive(Serialize, Deserialize)]
+struct ManifestEntry {
+    /// The path of the file inside of the archive.
+    path: PathBuf,
+    /// The path of the extracted file relative to the output directory.
+    file: PathBuf,
+    size: u32,
+    /// The access time in nanoseconds since the unix epoch.
+    access_time: Option<u64>,
+    /// The creation time in nanoseconds since the unix epoch.
+    create_time: Option<u64>,
+    /// The modification time in nanoseconds since the unix epoch.
+    modify_time: Option<u64>,
+}
+
+fn unix_nanos(time: Option<std::time::SystemTime>) -> Option<u64> {
+    time?.duration_since(std::time::UNIX_EPOCH).ok().map(|it| it.as_nanos() as u64)
+}
+
+fn from_unix_nanos(nanos: u64) -> std::time::SystemTime {
+    std::time::UNIX_EPOCH + std::time::Duration::from_nanos(nanos)
 }
 
 fn extract(matches: &ArgMatches<'static>) {
-    let key = matches.value_of("key").unwrap().as_bytes();
+    let key = &read_key(matches)[..];
     let archive_path = matches.value_of_os("archive").map(Path::new).unwrap();
     let out_path = matches
         .value_of_os("out")
         .map(PathBuf::from)
         .unwrap_or_else(|| archive_path.with_extension(""));
-    let write_times = matches.is_present("time");
+    let globs = |name| matches.values_of(name).into_iter().flatten().map(str::to_owned).collect();
+    let options = ExtractOptions {
+        depth: matches.value_of("depth").map(|it| it.parse().unwrap()),
+        write_times: matches.is_present("time"),
+        flatten: matches.is_present("flatten"),
+        on_conflict: match matches.value_of("on-conflict").unwrap() {
+            "overwrite" => OnConflict::Overwrite,
+            "rename" => OnConflict::Rename,
+            _ => OnConflict::Skip,
+        },
+        include: globs("include"),
+        exclude: globs("exclude"),
+    };
     let archive = Pk2::open(archive_path, key)
         .unwrap_or_else(|_| panic!("failed to open archive at {:?}", archive_path));
-    let folder = archive.open_directory("/").unwrap();
+    if let Some(tar_path) = matches.value_of_os("tar").map(Path::new) {
+        println!("Exporting {:?} to {:?}.", archive_path, tar_path);
+        let out = std::fs::File::create(tar_path)
+            .unwrap_or_else(|_| panic!("failed to create tar archive at {:?}", tar_path));
+        let mut out = archive.export_tar("/", std::io::BufWriter::new(out)).unwrap();
+        std::io::Write::flush(&mut out).unwrap();
+        return;
+    }
     println!("Extracting {:?} to {:?}.", archive_path, out_path);
-    extract_files(folder, &out_path, write_times);
-}
-
-fn extract_files(folder: Directory<'_>, out_path: &Path, write_times: bool) {
-    use std::io::Read;
-    let _ = std::fs::create_dir(out_path);
-    let mut buf = Vec::new();
-    for entry in folder.entries() {
-        match entry {
-            DirEntry::File(mut file) => {
-                file.read_to_end(&mut buf).unwrap();
-                let file_path = out_path.join(file.name());
-                if let Err(e) = std::fs::write(&file_path, &buf) {
-                    eprintln!("Failed writing file at {:?}: {}", file_path, e);
-                } else if write_times {
-                    if let Some(time) = file.modify_time() {
-                        let _ =
-                            filetime::set_file_mtime(&file_path, FileTime::from_system_time(time));
-                    }
-                    if let Some(time) = file.access_time() {
-                        let _ =
-                            filetime::set_file_atime(&file_path, FileTime::from_system_time(time));
-                    }
+    let stats = archive
+        .extract_to("/", &out_path, &options)
+        .unwrap_or_else(|e| panic!("failed to extract archive to {:?}: {}", out_path, e));
+    if stats.skipped > 0 {
+        eprintln!("Skipped {} already existing files", stats.skipped);
+    }
+    if stats.rejected > 0 {
+        eprintln!("Skipped {} entries with names that escape the output path", stats.rejected);
+    }
+    for (path, e) in &stats.failed {
+        eprintln!("Failed writing file at {:?}: {}", path, e);
+    }
+    if let Some(manifest_path) = matches.value_of_os("manifest").map(Path::new) {
+        let manifest = stats
+            .extracted
+            .into_iter()
+            .map(|(path, file_path)| {
+                let file = archive.open_file(&path).unwrap();
+                ManifestEntry {
+                    file: file_path.strip_prefix(&out_path).unwrap_or(&file_path).to_owned(),
+                    path,
+                    size: file.size(),
+                    access_time: unix_nanos(file.access_time()),
+                    create_time: unix_nanos(file.create_time()),
+                    modify_time: unix_nanos(file.modify_time()),
                 }
-                buf.clear();
-            }
-            DirEntry::Directory(dir) => {
-                let dir_name = dir.name();
-                let path = out_path.join(dir_name);
-                extract_files(dir, &path, write_times);
-            }
-        }
+            })
+            .collect::<Vec<_>>();
+        let out = std::fs::File::create(manifest_path)
+            .unwrap_or_else(|_| panic!("failed to create manifest at {:?}", manifest_path));
+        serde_json::to_writer_pretty(std::io::BufWriter::new(out), &manifest).unwrap();
     }
 }
 
@@ -123,6 +255,7 @@ fn repack_app() -> App<'static, 'static> {
                 .help("Sets the archive to open"),
         )
         .arg(key_arg().help("Sets the blowfish key for the input archive"))
+        .arg(key_file_arg())
         .arg(
             Arg::with_name("packkey")
                 .short("p")
@@ -137,12 +270,24 @@ fn repack_app() -> App<'static, 'static> {
                 .takes_value(true)
                 .help("Sets the output path to repack to"),
         )
+        .arg(
+            Arg::with_name("keep-empty-dirs")
+                .long("keep-empty-dirs")
+                .help("If passed, recreates directories that contain no files"),
+        )
+        .arg(Arg::with_name("no-sort-entries").long("no-sort-entries").help(
+            "If passed, keeps the entry order of the input instead of sorting the entries of \
+                 each directory for reproducible output",
+        ))
+        .arg(Arg::with_name("order-file").long("order-file").takes_value(true).help(
+            "Places the data of the files listed in the given file, one path per line, first \
+                     and in the listed order",
+        ))
 }
 
 fn repack(matches: &ArgMatches<'static>) {
-    let key = matches.value_of("key").unwrap().as_bytes();
-    let packkey =
-        matches.value_of("packkey").or_else(|| matches.value_of("key")).unwrap().as_bytes();
+    let key = &read_key(matches)[..];
+    let packkey = matches.value_of("packkey").map(str::as_bytes).unwrap_or(key);
     let archive_path = matches.value_of_os("archive").map(Path::new).unwrap();
     let out_archive_path = matches
         .value_of_os("out")
@@ -150,33 +295,109 @@ fn repack(matches: &ArgMatches<'static>) {
         .unwrap_or_else(|| archive_path.with_extension("repack.pk2"));
     let in_archive = Pk2::open(archive_path, key)
         .unwrap_or_else(|_| panic!("failed to open archive at {:?}", archive_path));
-    let mut out_archive = pk2::Pk2::create_new(&out_archive_path, packkey)
-        .unwrap_or_else(|_| panic!("failed to create archive at {:?}", out_archive_path));
     let folder = in_archive.open_directory("/").unwrap();
+    let mut plan = Vec::new();
+    let mut empty_dirs = Vec::new();
+    let sort = !matches.is_present("no-sort-entries");
+    plan_repack(folder, "/".as_ref(), sort, &mut plan, &mut empty_dirs);
+    if let Some(order_path) = matches.value_of_os("order-file").map(Path::new) {
+        let order = std::fs::read_to_string(order_path)
+            .unwrap_or_else(|e| panic!("failed to read order file {:?}: {}", order_path, e));
+        prioritize_plan(&mut plan, &order);
+    }
+    if !matches.is_present("keep-empty-dirs") {
+        empty_dirs.clear();
+    }
+    if matches.is_present("dry-run") {
+        println!("Would repack {:?} into {:?}:", archive_path, out_archive_path);
+        for (path, file) in &plan {
+            println!("create {} ({} bytes)", path.display(), file.size());
+        }
+        for path in &empty_dirs {
+            println!("create directory {}", path.display());
+        }
+        return;
+    }
+    let mut builder = Pk2::builder(&out_archive_path, packkey)
+        .unwrap_or_else(|_| panic!("failed to create archive at {:?}", out_archive_path));
     println!("Repacking {:?} into {:?}.", archive_path, out_archive_path);
-    repack_files(&mut out_archive, folder, "/".as_ref());
+    builder.set_sort_entries(sort);
+    repack_files(&mut builder, &plan);
+    for path in &empty_dirs {
+        builder
+            .create_dir_all(path)
+            .unwrap_or_else(|e| panic!("failed to create directory {:?}: {}", path, e));
+    }
+    builder.finish().unwrap_or_else(|e| panic!("failed to write the file table: {}", e));
 }
 
-fn repack_files(out_archive: &mut Pk2, folder: Directory<'_>, path: &Path) {
-    use std::io::{Read, Write};
-    let mut buf = Vec::new();
-    for entry in folder.entries() {
+/// Collects the archive path and size of every file that is going to be repacked, as well as the
+/// paths of all directories without any entries. If `sort` is set, the entries of each directory
+/// are visited in name order.
+fn plan_repack<'pk2>(
+    folder: Directory<'pk2>,
+    path: &Path,
+    sort: bool,
+    plan: &mut Vec<(PathBuf, File<'pk2>)>,
+    empty_dirs: &mut Vec<PathBuf>,
+) {
+    let mut entries: Vec<_> = folder.entries().collect();
+    if entries.is_empty() {
+        empty_dirs.push(path.to_owned());
+    }
+    if sort {
+        entries.sort_by(|a, b| a.name().cmp(b.name()));
+    }
+    for entry in entries {
         match entry {
-            DirEntry::File(mut file) => {
-                file.read_to_end(&mut buf).unwrap();
-                let mut out_file = out_archive.create_file(path.join(file.name())).unwrap();
-                out_file.copy_file_times(&file);
-                out_file.write_all(&buf).unwrap();
-                buf.clear();
-            }
+            DirEntry::File(file) => plan.push((path.join(file.name()), file)),
             DirEntry::Directory(dir) => {
                 let path = path.join(dir.name());
-                repack_files(out_archive, dir, &path);
+                plan_repack(dir, &path, sort, plan, empty_dirs);
             }
         }
     }
 }
 
+/// Moves the files listed in `order` to the front of the plan in the listed order, keeping the
+/// order of all other files. Lines of `order` are archive paths, empty lines and lines starting
+/// with `#` are ignored.
+///
+/// As the builder appends file data in plan order, this places the data of the listed files
+/// contiguously at the start of the archive. The order of the entries in the file table is left
+/// to the builder.
+fn prioritize_plan(plan: &mut [(PathBuf, File<'_>)], order: &str) {
+    let mut priorities = std::collections::HashMap::new();
+    let paths = order
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| pk2::normalize_path(line).ok());
+    for path in paths {
+        let priority = priorities.len();
+        priorities.entry(path.to_ascii_lowercase()).or_insert(priority);
+    }
+    plan.sort_by_key(|(path, _)| {
+        pk2::normalize_path(&path.to_string_lossy())
+            .ok()
+            .and_then(|path| priorities.get(&path.to_ascii_lowercase()).copied())
+            .unwrap_or(usize::MAX)
+    });
+}
+
+/// Copies the planned files into the builder.
+///
+/// The files are read through the handles collected while planning rather than being looked up by
+/// path again, which would resolve entries whose names only differ in case to the same file.
+fn repack_files(builder: &mut Pk2Builder, plan: &[(PathBuf, File<'_>)]) {
+    for &(ref path, file) in plan {
+        builder
+            .add_file(path, file)
+            .and_then(|_| builder.copy_file_times(path, &file))
+            .unwrap_or_else(|e| panic!("failed to write file {:?}: {}", path, e));
+    }
+}
+
 fn pack_app() -> App<'static, 'static> {
     SubCommand::with_name("pack")
         .version(crate_version!())
@@ -191,6 +412,7 @@ fn pack_app() -> App<'static, 'static> {
                 .help("Sets the directory to pack"),
         )
         .arg(key_arg().help("Sets the blowfish key for the resulting archive"))
+        .arg(key_file_arg())
         .arg(
             Arg::with_name("archive")
                 .short("a")
@@ -198,10 +420,18 @@ fn pack_app() -> App<'static, 'static> {
                 .takes_value(true)
                 .help("Sets the output path to pack into"),
         )
+        .arg(Arg::with_name("no-sort-entries").long("no-sort-entries").help(
+            "If passed, keeps the entry order of the input instead of sorting the entries of \
+                 each directory for reproducible output",
+        ))
+        .arg(Arg::with_name("from-manifest").long("from-manifest").takes_value(true).help(
+            "Packs the files listed in a manifest written by `extract --manifest` in \
+                     order, applying their file times",
+        ))
 }
 
 fn pack(matches: &ArgMatches<'static>) {
-    let key = matches.value_of("key").unwrap().as_bytes();
+    let key = &read_key(matches)[..];
     let input_path = matches.value_of_os("directory").map(Path::new).unwrap();
     let out_archive_path = matches
         .value_of_os("archive")
@@ -210,30 +440,445 @@ fn pack(matches: &ArgMatches<'static>) {
     if !input_path.is_dir() {
         return;
     }
-    let mut out_archive = pk2::Pk2::create_new(&out_archive_path, key)
+    let mut plan = Vec::new();
+    let manifest = matches.value_of_os("from-manifest").map(Path::new).map(|manifest_path| {
+        let file = std::fs::File::open(manifest_path)
+            .unwrap_or_else(|_| panic!("failed to open manifest at {:?}", manifest_path));
+        let manifest: Vec<ManifestEntry> = serde_json::from_reader(std::io::BufReader::new(file))
+            .unwrap_or_else(|e| panic!("failed to parse manifest {:?}: {}", manifest_path, e));
+        manifest
+    });
+    // a manifest reproduces the layout of the archive it was extracted from
+    let sort = manifest.is_none() && !matches.is_present("no-sort-entries");
+    match &manifest {
+        Some(manifest) => plan_pack_from_manifest(input_path, manifest, &mut plan),
+        None => plan_pack(input_path, input_path, sort, &mut plan),
+    }
+    if matches.is_present("dry-run") {
+        println!("Would pack {:?} into {:?}:", input_path, out_archive_path);
+        for (_, archive_path, size) in &plan {
+            println!("create {} ({} bytes)", archive_path.display(), size);
+        }
+        return;
+    }
+    let mut builder = Pk2::builder(&out_archive_path, key)
         .unwrap_or_else(|_| panic!("failed to create archive at {:?}", out_archive_path));
     println!("Packing {:?} into {:?}.", input_path, out_archive_path);
-    pack_files(&mut out_archive, input_path, input_path);
+    builder.set_sort_entries(sort);
+    pack_files(&mut builder, &plan, manifest.as_deref());
+    builder.finish().unwrap_or_else(|e| panic!("failed to write the file table: {}", e));
 }
 
-fn pack_files(out_archive: &mut Pk2, dir_path: &Path, base: &Path) {
-    use std::io::{Read, Write};
-    let mut buf = Vec::new();
-    for entry in std::fs::read_dir(dir_path).unwrap() {
-        let entry = entry.unwrap();
+/// Collects the host path, archive path and size of every file listed in the manifest, keeping
+/// the order of the manifest.
+fn plan_pack_from_manifest(
+    base: &Path,
+    manifest: &[ManifestEntry],
+    plan: &mut Vec<(PathBuf, PathBuf, u64)>,
+) {
+    for entry in manifest {
+        let path = base.join(&entry.file);
+        let size = std::fs::metadata(&path)
+            .unwrap_or_else(|e| panic!("failed to read file {:?}: {}", path, e))
+            .len();
+        plan.push((path, entry.path.clone(), size));
+    }
+}
+
+/// Collects the host path, archive path and size of every file that is going to be packed. If
+/// `sort` is set, the entries of each directory are visited in name order, as the order of
+/// `read_dir` depends on the filesystem.
+fn plan_pack(dir_path: &Path, base: &Path, sort: bool, plan: &mut Vec<(PathBuf, PathBuf, u64)>) {
+    let mut entries: Vec<_> =
+        std::fs::read_dir(dir_path).unwrap().collect::<Result<_, _>>().unwrap();
+    if sort {
+        entries.sort_by_key(|entry| entry.file_name());
+    }
+    for entry in entries {
         let ty = entry.file_type().unwrap();
         let path = entry.path();
         if ty.is_dir() {
-            pack_files(out_archive, &path, base);
+            plan_pack(&path, base, sort, plan);
         } else if ty.is_file() {
-            let mut file = std::fs::File::open(&path).unwrap();
-            file.read_to_end(&mut buf).unwrap();
-            out_archive
-                .create_file(Path::new("/").join(path.strip_prefix(base).unwrap()))
-                .unwrap()
-                .write_all(&buf)
+            let archive_path = Path::new("/").join(path.strip_prefix(base).unwrap());
+            let size = entry.metadata().unwrap().len();
+            plan.push((path, archive_path, size));
+        }
+    }
+}
+
+/// Packs the planned files, applying the file times of the manifest entry at the same index if
+/// one is given.
+fn pack_files(
+    builder: &mut Pk2Builder,
+    plan: &[(PathBuf, PathBuf, u64)],
+    manifest: Option<&[ManifestEntry]>,
+) {
+    let now = std::time::SystemTime::now();
+    for (idx, (path, archive_path, _)) in plan.iter().enumerate() {
+        let file = std::fs::File::open(path).unwrap();
+        builder
+            .add_file(archive_path, file)
+            .unwrap_or_else(|e| panic!("failed to write file {:?}: {}", archive_path, e));
+        if let Some(entry) = manifest.and_then(|manifest| manifest.get(idx)) {
+            let time = |time: Option<u64>| time.map_or(now, from_unix_nanos);
+            builder
+                .set_file_times(
+                    archive_path,
+                    time(entry.access_time),
+                    time(entry.create_time),
+                    time(entry.modify_time),
+                )
                 .unwrap();
-            buf.clear();
+        }
+    }
+}
+
+fn patch_app() -> App<'static, 'static> {
+    SubCommand::with_name("patch")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about(crate_description!())
+        .arg(
+            Arg::with_name("archive")
+                .short("a")
+                .long("archive")
+                .required(true)
+                .takes_value(true)
+                .help("Sets the archive to patch"),
+        )
+        .arg(key_arg().help("Sets the blowfish key for the archive to patch"))
+        .arg(key_file_arg())
+        .arg(
+            Arg::with_name("merge-archive")
+                .short("m")
+                .long("merge-archive")
+                .required(true)
+                .takes_value(true)
+                .help("Sets the archive whose contents are merged into the patched archive"),
+        )
+        .arg(
+            Arg::with_name("merge-key")
+                .long("merge-key")
+                .takes_value(true)
+                .help("Sets the blowfish key for the merged archive, defaults to --key"),
+        )
+        .arg(
+            Arg::with_name("out")
+                .short("o")
+                .long("out")
+                .takes_value(true)
+                .help("If passed, writes the patched archive to this path instead of in place"),
+        )
+        .arg(
+            Arg::with_name("on-conflict")
+                .long("on-conflict")
+                .takes_value(true)
+                .possible_values(&["skip", "overwrite"])
+                .default_value("skip")
+                .help("Sets how files that exist in both archives are handled"),
+        )
+}
+
+fn patch(matches: &ArgMatches<'static>) {
+    let key = &read_key(matches)[..];
+    let merge_key = matches.value_of("merge-key").map(str::as_bytes).unwrap_or(key);
+    let archive_path = matches.value_of_os("archive").map(Path::new).unwrap();
+    let merge_path = matches.value_of_os("merge-archive").map(Path::new).unwrap();
+    let overwrite = matches.value_of("on-conflict") == Some("overwrite");
+    let merge_archive = Pk2::open(merge_path, merge_key)
+        .unwrap_or_else(|_| panic!("failed to open archive at {:?}", merge_path));
+    let dry_run = matches.is_present("dry-run");
+    let out_path = match matches.value_of_os("out").map(Path::new) {
+        Some(out_path) if !dry_run => {
+            std::fs::copy(archive_path, out_path)
+                .unwrap_or_else(|_| panic!("failed to create archive at {:?}", out_path));
+            out_path
+        }
+        _ => archive_path,
+    };
+    let mut archive = Pk2::open(out_path, key)
+        .unwrap_or_else(|_| panic!("failed to open archive at {:?}", out_path));
+    let plan = merge_archive
+        .files()
+        .map(|(path, file)| (archive.open_file(&path).is_ok(), path, file.size()))
+        .filter(|&(exists, _, _)| overwrite || !exists)
+        .collect::<Vec<_>>();
+    if dry_run {
+        println!("Would merge {:?} into {:?}:", merge_path, out_path);
+        for (exists, path, size) in &plan {
+            let action = if *exists { "overwrite" } else { "create" };
+            println!("{} {} ({} bytes)", action, path.display(), size);
+        }
+        return;
+    }
+    println!("Merging {:?} into {:?}.", merge_path, out_path);
+    for (exists, path, _) in plan {
+        match exists {
+            true => replace_file(&mut archive, &merge_archive, &path),
+            false => archive.copy_from(&merge_archive, &path, &path),
+        }
+        .unwrap_or_else(|e| panic!("failed to write file {:?}: {}", path, e));
+    }
+}
+
+/// Replaces the file at `path` with the one at the same path in `src`.
+///
+/// The file is copied to a temporary file first and only swapped in once that succeeded, so the
+/// original file stays intact if copying fails.
+fn replace_file<B, B2>(archive: &mut Pk2<B>, src: &Pk2<B2>, path: &Path) -> std::io::Result<()>
+where
+    B: pk2::Backing + pk2::BackingMut,
+    B2: pk2::Backing,
+{
+    let tmp = (0..)
+        .map(|idx| PathBuf::from(format!("/.pk2_mate-patch-{}", idx)))
+        .find(|tmp| archive.open_file(tmp).is_err() && archive.open_directory(tmp).is_err())
+        .unwrap();
+    let res = archive.copy_from(src, path, &tmp).and_then(|()| archive.swap_files(path, &tmp));
+    // deleting the temporary file frees the data that is no longer used
+    let _ = archive.delete_file(&tmp);
+    res?;
+    let file = src.open_file(path)?;
+    match (file.access_time(), file.create_time(), file.modify_time()) {
+        (Some(access), Some(create), Some(modify)) => {
+            archive.set_file_times(path, access, create, modify)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn lint_app() -> App<'static, 'static> {
+    SubCommand::with_name("lint")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about(crate_description!())
+        .arg(
+            Arg::with_name("archive")
+                .short("a")
+                .long("archive")
+                .required(true)
+                .takes_value(true)
+                .help("Sets the archive to check"),
+        )
+        .arg(key_arg().help("Sets the blowfish key"))
+        .arg(key_file_arg())
+        .arg(
+            Arg::with_name("allow")
+                .long("allow")
+                .takes_value(true)
+                .multiple(true)
+                .possible_values(LINT_WARNINGS)
+                .help("Disables the given warning checks"),
+        )
+        .arg(
+            Arg::with_name("deny-warnings")
+                .long("deny-warnings")
+                .help("If passed, exits with an error if any warnings are reported"),
+        )
+}
+
+/// The checks that only produce warnings and can be disabled.
+const LINT_WARNINGS: &[&str] = &["orphaned-data", "empty-directories", "invalid-timestamps"];
+
+/// Checks the archive for problems, exiting with an error code if any errors are found.
+///
+/// Duplicate entries, files with partially overlapping data and files whose data can't be read
+/// are errors, files sharing the exact same data region are not reported as some archives do this
+/// on purpose.
+fn lint(matches: &ArgMatches<'static>) {
+    let key = &read_key(matches)[..];
+    let archive_path = matches.value_of_os("archive").map(Path::new).unwrap();
+    let archive = Pk2::open(archive_path, key)
+        .unwrap_or_else(|_| panic!("failed to open archive at {:?}", archive_path));
+    let allowed: Vec<_> = matches.values_of("allow").map(Iterator::collect).unwrap_or_default();
+    let (mut errors, mut warnings) = (0, 0);
+    let mut error = |check: &str, message: String| {
+        println!("error[{}]: {}", check, message);
+        errors += 1;
+    };
+
+    for (dir, names) in archive.find_duplicate_entries() {
+        error("duplicate-entries", format!("{}: {}", dir, names.join(", ")));
+    }
+    let mut files = archive.iter_files_with_offset();
+    files.retain(|&(_, _, size)| size > 0);
+    files.sort_by_key(|&(_, offset, size)| (offset, size));
+    let mut furthest: Option<&(PathBuf, pk2::StreamOffset, u32)> = None;
+    for file @ (path, offset, size) in &files {
+        if let Some((prev_path, prev_offset, prev_size)) = furthest {
+            let prev_end = prev_offset.0 + *prev_size as u64;
+            let shared = prev_offset == offset && prev_size == size;
+            if offset.0 < prev_end && !shared {
+                error(
+                    "overlapping-data",
+                    format!(
+                        "{} ({:#x}..{:#x}) overlaps {} ({:#x}..{:#x})",
+                        path.display(),
+                        offset,
+                        offset.0 + *size as u64,
+                        prev_path.display(),
+                        prev_offset,
+                        prev_end
+                    ),
+                );
+            }
+        }
+        if furthest.is_none_or(|(_, o, s)| o.0 + (*s as u64) < offset.0 + *size as u64) {
+            furthest = Some(file);
+        }
+    }
+
+    for (path, e) in archive.verify_all_files() {
+        error("unreadable-data", format!("{}: {}", path.display(), e));
+    }
+
+    let mut warning = |check: &str, message: String| {
+        if !allowed.contains(&check) {
+            println!("warning[{}]: {}", check, message);
+            warnings += 1;
+        }
+    };
+    for (offset, len) in archive.unused_regions().expect("failed to read archive length") {
+        let end = offset.0 + len;
+        warning("orphaned-data", format!("{:#x}..{:#x} ({} bytes)", offset, end, len));
+    }
+    lint_dir(archive.open_root_dir(), "/".as_ref(), &mut warning);
+
+    println!("{} errors, {} warnings", errors, warnings);
+    if errors > 0 || (warnings > 0 && matches.is_present("deny-warnings")) {
+        std::process::exit(1);
+    }
+}
+
+fn lint_dir(folder: Directory<'_>, path: &Path, warning: &mut impl FnMut(&str, String)) {
+    if folder.entries().next().is_none() {
+        warning("empty-directories", path.display().to_string());
+    }
+    for entry in folder.entries() {
+        let (path, times) = match entry {
+            DirEntry::File(file) => {
+                (path.join(file.name()), [file.modify_time(), file.create_time()])
+            }
+            DirEntry::Directory(dir) => {
+                let path = path.join(dir.name());
+                lint_dir(dir, &path, warning);
+                (path, [dir.modify_time(), dir.create_time()])
+            }
+        };
+        if times.iter().any(Option::is_none) {
+            warning("invalid-timestamps", path.display().to_string());
+        }
+    }
+}
+
+fn find_app() -> App<'static, 'static> {
+    SubCommand::with_name("find")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about(crate_description!())
+        .arg(
+            Arg::with_name("archive")
+                .short("a")
+                .long("archive")
+                .required(true)
+                .takes_value(true)
+                .help("Sets the archive to search"),
+        )
+        .arg(key_arg().help("Sets the blowfish key"))
+        .arg(key_file_arg())
+        .arg(
+            Arg::with_name("name")
+                .long("name")
+                .takes_value(true)
+                .help("Only matches entries whose name matches the glob, supporting `*` and `?`"),
+        )
+        .arg(
+            Arg::with_name("larger-than")
+                .long("larger-than")
+                .takes_value(true)
+                .validator(|it| it.parse::<u32>().map(drop).map_err(|e| e.to_string()))
+                .help("Only matches files larger than the given number of bytes"),
+        )
+        .arg(
+            Arg::with_name("smaller-than")
+                .long("smaller-than")
+                .takes_value(true)
+                .validator(|it| it.parse::<u32>().map(drop).map_err(|e| e.to_string()))
+                .help("Only matches files smaller than the given number of bytes"),
+        )
+        .arg(
+            Arg::with_name("newer-than")
+                .long("newer-than")
+                .takes_value(true)
+                .validator(|it| parse_date(&it).map(drop))
+                .help("Only matches entries modified after the given date, e.g. `2020-01-31` or `2020-01-31 12:00:00`"),
+        )
+        .arg(
+            Arg::with_name("type")
+                .long("type")
+                .takes_value(true)
+                .possible_values(&["f", "d"])
+                .help("Only matches files (f) or directories (d)"),
+        )
+}
+
+fn parse_date(date: &str) -> Result<std::time::SystemTime, String> {
+    humantime::parse_rfc3339_weak(date)
+        .or_else(|_| humantime::parse_rfc3339_weak(&format!("{} 00:00:00", date)))
+        .map_err(|e| e.to_string())
+}
+
+/// The predicates of the find subcommand, all of which have to match.
+struct FindFilter {
+    name: Option<String>,
+    larger_than: Option<u32>,
+    smaller_than: Option<u32>,
+    newer_than: Option<std::time::SystemTime>,
+    kind: Option<char>,
+}
+
+impl FindFilter {
+    fn matches(&self, entry: &DirEntry) -> bool {
+        let (name, size, modify_time, kind) = match entry {
+            DirEntry::File(file) => (file.name(), Some(file.size()), file.modify_time(), 'f'),
+            DirEntry::Directory(dir) => (dir.name(), None, dir.modify_time(), 'd'),
+        };
+        self.name.as_ref().is_none_or(|glob| pk2::glob_match(glob, name))
+            && self.larger_than.is_none_or(|min| size.is_some_and(|size| size > min))
+            && self.smaller_than.is_none_or(|max| size.is_some_and(|size| size < max))
+            && self.newer_than.is_none_or(|date| modify_time.is_some_and(|time| time > date))
+            && self.kind.is_none_or(|it| it == kind)
+    }
+}
+
+fn find(matches: &ArgMatches<'static>) {
+    let key = &read_key(matches)[..];
+    let archive_path = matches.value_of_os("archive").map(Path::new).unwrap();
+    let archive = Pk2::open(archive_path, key)
+        .unwrap_or_else(|_| panic!("failed to open archive at {:?}", archive_path));
+    let filter = FindFilter {
+        name: matches.value_of("name").map(str::to_owned),
+        larger_than: matches.value_of("larger-than").map(|it| it.parse().unwrap()),
+        smaller_than: matches.value_of("smaller-than").map(|it| it.parse().unwrap()),
+        newer_than: matches.value_of("newer-than").map(|it| parse_date(it).unwrap()),
+        kind: matches.value_of("type").and_then(|it| it.chars().next()),
+    };
+    find_in(archive.open_root_dir(), "/".as_ref(), &filter);
+}
+
+fn find_in(folder: Directory, path: &Path, filter: &FindFilter) {
+    for entry in folder.entries() {
+        let name = match entry {
+            DirEntry::File(file) => file.name(),
+            DirEntry::Directory(dir) => dir.name(),
+        };
+        let entry_path = path.join(name);
+        if filter.matches(&entry) {
+            println!("{}", entry_path.display());
+        }
+        if let DirEntry::Directory(dir) = entry {
+            find_in(dir, &entry_path, filter);
         }
     }
 }
@@ -252,20 +897,79 @@ fn list_app() -> App<'static, 'static> {
                 .help("Sets the archive to open"),
         )
         .arg(key_arg().help("Sets the blowfish key"))
+        .arg(key_file_arg())
         .arg(Arg::with_name("time").short("t").long("time").help("If passed, shows file times"))
+        .arg(
+            Arg::with_name("show-backlinks").long("show-backlinks").alias("follow-backlinks").help(
+                "If passed, shows the `.` and `..` entries and the chain offsets they point to",
+            ),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["text", "json"])
+                .default_value("text")
+                .help("Sets the output format, json prints an array of all entries"),
+        )
 }
 
 fn list(matches: &ArgMatches<'static>) {
-    let key = matches.value_of("key").unwrap().as_bytes();
+    let key = &read_key(matches)[..];
     let archive_path = matches.value_of_os("archive").map(PathBuf::from).unwrap();
     let archive = pk2::Pk2::open(&archive_path, key)
         .unwrap_or_else(|_| panic!("failed to open archive at {:?}", archive_path));
     let folder = archive.open_directory("/").unwrap();
-    list_files(folder, "/".as_ref(), 1);
+    if matches.value_of("format") == Some("json") {
+        let mut entries = Vec::new();
+        collect_list_entries(folder, "/".as_ref(), &mut entries);
+        println!("{}", serde_json::to_string_pretty(&entries).unwrap());
+    } else {
+        list_files(folder, "/".as_ref(), 1, matches.is_present("show-backlinks"));
+    }
+}
+
+#[derive(Serialize)]
+struct ListEntry {
+    path: PathBuf,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    /// The size of the file in bytes, `None` for directories.
+    size: Option<u32>,
+    /// The modification time in nanoseconds since the unix epoch.
+    modify_time: Option<u64>,
+}
+
+fn collect_list_entries(folder: Directory, path: &Path, out: &mut Vec<ListEntry>) {
+    for entry in folder.entries() {
+        match entry {
+            DirEntry::File(file) => out.push(ListEntry {
+                path: path.join(file.name()),
+                kind: "file",
+                size: Some(file.size()),
+                modify_time: unix_nanos(file.modify_time()),
+            }),
+            DirEntry::Directory(dir) => {
+                let path = path.join(dir.name());
+                out.push(ListEntry {
+                    path: path.clone(),
+                    kind: "directory",
+                    size: None,
+                    modify_time: unix_nanos(dir.modify_time()),
+                });
+                collect_list_entries(dir, &path, out);
+            }
+        }
+    }
 }
 
-fn list_files(folder: Directory, path: &Path, ident_level: usize) {
+fn list_files(folder: Directory, path: &Path, ident_level: usize, show_backlinks: bool) {
     println!("{}", path.display());
+    if show_backlinks {
+        for (name, chain) in folder.backlinks() {
+            println!("{}{} -> {:#x}", " ".repeat(ident_level), name, chain);
+        }
+    }
     for entry in folder.entries() {
         match entry {
             DirEntry::File(file) => {
@@ -274,8 +978,108 @@ fn list_files(folder: Directory, path: &Path, ident_level: usize) {
             DirEntry::Directory(dir) => {
                 let dir_name = dir.name();
                 let path = path.join(dir_name);
-                list_files(dir, &path, path.as_os_str().len());
+                list_files(dir, &path, path.as_os_str().len(), show_backlinks);
             }
         }
     }
 }
+
+fn info_app() -> App<'static, 'static> {
+    SubCommand::with_name("info")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about(crate_description!())
+        .arg(
+            Arg::with_name("archive")
+                .short("a")
+                .long("archive")
+                .required(true)
+                .takes_value(true)
+                .help("Sets the archive to inspect"),
+        )
+        .arg(key_arg().help("Sets the blowfish key"))
+        .arg(key_file_arg())
+}
+
+/// Prints summary information about the archive, including a hex dump of the reserved header
+/// bytes some archives store a build stamp in.
+fn info(matches: &ArgMatches<'static>) {
+    let key = &read_key(matches)[..];
+    let archive_path = matches.value_of_os("archive").map(Path::new).unwrap();
+    let archive = Pk2::open(archive_path, key)
+        .unwrap_or_else(|_| panic!("failed to open archive at {:?}", archive_path));
+    println!("files: {}", archive.files().count());
+    let occupancy = archive.block_occupancy().map(|(_, occupied)| occupied).collect::<Vec<_>>();
+    println!("blocks: {}", occupancy.len());
+    println!("empty blocks: {}", occupancy.iter().filter(|&&occupied| occupied == 0).count());
+    println!("occupied entries: {}", occupancy.iter().sum::<usize>());
+    println!("reserved header bytes:");
+    for (i, line) in archive.header_reserved().chunks(16).enumerate() {
+        let hex = line.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ");
+        let ascii = line
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect::<String>();
+        println!("  {:04x}: {:<47}  {}", i * 16, hex, ascii);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use pk2::unsync::Pk2;
+
+    #[test]
+    fn patch_replaces_existing_files() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/a.txt").unwrap().write_all(b"old").unwrap();
+        let mut src = Pk2::create_new_in_memory("").unwrap();
+        src.create_file("/a.txt").unwrap().write_all(b"new contents").unwrap();
+        super::replace_file(&mut archive, &src, "/a.txt".as_ref()).unwrap();
+        assert_eq!(archive.read("/a.txt").unwrap(), b"new contents");
+        assert_eq!(archive.open_root_dir().entries().count(), 1);
+
+        assert!(super::replace_file(&mut archive, &src, "/b.txt".as_ref()).is_err());
+        assert_eq!(archive.read("/a.txt").unwrap(), b"new contents");
+        assert_eq!(archive.open_root_dir().entries().count(), 1);
+    }
+
+    #[test]
+    fn repack_order_file_with_sorted_entries() {
+        let in_path = std::env::temp_dir().join("pk2_mate_repack_order_in.pk2");
+        let out_path = std::env::temp_dir().join("pk2_mate_repack_order_out.pk2");
+        let _ = std::fs::remove_file(&in_path);
+        let _ = std::fs::remove_file(&out_path);
+        let mut builder = Pk2::builder(&in_path, "").unwrap();
+        for path in ["/b/y", "/a", "/b/x", "/c"] {
+            builder.add_file(path, path.as_bytes()).unwrap();
+        }
+        let in_archive = builder.finish().unwrap();
+
+        let mut plan = Vec::new();
+        let root = in_archive.open_directory("/").unwrap();
+        super::plan_repack(root, "/".as_ref(), true, &mut plan, &mut Vec::new());
+        super::prioritize_plan(&mut plan, "/c\n/b/y\n");
+        let mut builder = Pk2::builder(&out_path, "").unwrap();
+        builder.set_sort_entries(true);
+        super::repack_files(&mut builder, &plan);
+        let archive = builder.finish().unwrap();
+
+        let names = |path| {
+            let dir = archive.open_directory(path).unwrap();
+            dir.entries().map(|entry| entry.name().to_owned()).collect::<Vec<_>>()
+        };
+        assert_eq!(names("/"), ["b", "a", "c"]);
+        assert_eq!(names("/b"), ["x", "y"]);
+        let mut files = archive.iter_files_with_offset();
+        files.sort_by_key(|&(_, offset, _)| offset);
+        let data_order = files.into_iter().map(|(path, ..)| path).collect::<Vec<_>>();
+        assert_eq!(data_order, ["/c", "/b/y", "/a", "/b/x"].map(PathBuf::from));
+        drop((in_archive, archive));
+        let _ = std::fs::remove_file(&in_path);
+        let _ = std::fs::remove_file(&out_path);
+    }
+}
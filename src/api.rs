@@ -1,30 +1,279 @@
+pub mod builder;
+pub mod extract;
 pub mod fs;
-use self::fs::{Directory, File, FileMut};
+#[cfg(feature = "tar")]
+mod tar;
+use self::fs::{DirEntry, DirEntryInfo, Directory, DirectoryMut, File, FileMut, Files};
 
+use std::collections::{HashMap, HashSet};
 use std::marker::PhantomData;
-use std::path::{Component, Path};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
 use std::{fs as stdfs, io};
 
 use crate::blowfish::Blowfish;
 use crate::constants::{
-    PK2_CHECKSUM, PK2_CURRENT_DIR_IDENT, PK2_PARENT_DIR_IDENT, PK2_ROOT_BLOCK,
-    PK2_ROOT_BLOCK_VIRTUAL,
+    PK2_CURRENT_DIR_IDENT, PK2_FILE_BLOCK_SIZE, PK2_FILE_ENTRY_SIZE, PK2_HEADER_SIZE,
+    PK2_MAX_PATH_LEN, PK2_PARENT_DIR_IDENT, PK2_ROOT_BLOCK, PK2_ROOT_BLOCK_VIRTUAL,
 };
 use crate::data::block_chain::{PackBlock, PackBlockChain};
 use crate::data::block_manager::BlockManager;
-use crate::data::entry::PackEntry;
+use crate::data::entry::{check_name_len, DirectoryOrFile, PackEntry};
+use crate::data::free_list::FreeList;
 use crate::data::header::PackHeader;
-use crate::data::{ChainIndex, StreamOffset};
-use crate::error::{ChainLookupError, ChainLookupResult, OpenError, OpenResult};
-use crate::io::RawIo;
-use crate::{Lock, LockChoice, ReadOnly};
+use crate::data::{
+    BlockOffset, CaseSensitivity, ChainIndex, NameCodec, NameEncoding, NameTransform, StreamOffset,
+};
+use crate::error::{
+    ChainLookupError, ChainLookupResult, OpenError, OpenResult, ParseWarning, PathLookupError,
+};
+use crate::filetime::FILETIME;
+use crate::io::{AsBytes, Backing, BackingMut, BlockCache, CacheStats};
+use crate::{Lock, LockChoice, Offset, ReadOnly};
+
+/// Settings controlling how an archive is opened, see [`Pk2::open_with`] and
+/// [`Pk2::open_in_with`].
+///
+/// The settings can be combined freely, the specialized `open*` constructors are shorthands for a
+/// single one of them.
+///
+/// ```rust,no_run
+/// # let archive_path = "";
+/// # let key = b"";
+/// use pk2::unsync::Pk2;
+/// use pk2::OpenOptions;
+///
+/// let mut warnings = Vec::new();
+/// let options = OpenOptions::new().read_ahead(1 << 20).warnings(&mut warnings);
+/// let archive = Pk2::open_with(archive_path, key, options).unwrap();
+/// ```
+pub struct OpenOptions<'a> {
+    /// An already derived cipher to use instead of deriving one from the key.
+    cipher: Option<&'a Blowfish>,
+    /// Tolerate blocks that are cut off by the end of the stream.
+    lenient: bool,
+    /// Keep the bytes of entries that are not interpreted to write them back unchanged.
+    preserve_raw: bool,
+    /// Whether [`Pk2::open_with`] restores the file table from the archive's index sidecar.
+    index_sidecar: bool,
+    /// An index cache [`Pk2::open_with`] restores the file table from instead of the sidecar.
+    index_cache: Option<&'a [u8]>,
+    /// Called with the total number of blocks read so far while parsing the file table, parsing
+    /// is aborted if it returns an error.
+    progress: Option<&'a mut dyn FnMut(usize) -> io::Result<()>>,
+    /// A previously parsed file table whose allocations are reused.
+    reuse: Option<BlockManager>,
+    /// The encoding entry names are stored in.
+    name_encoding: NameEncoding,
+    /// The scrambling applied to the raw name fields of entries, if any.
+    name_transform: Option<NameTransform>,
+    /// Collects recoverable anomalies found while parsing the file table.
+    warnings: Option<&'a mut Vec<ParseWarning>>,
+    /// The number of bytes to read at once whenever a block outside of the previously read ones
+    /// is needed, instead of only reading ahead for sequential blocks.
+    read_ahead: Option<usize>,
+}
+
+impl Default for OpenOptions<'_> {
+    fn default() -> Self {
+        OpenOptions {
+            cipher: None,
+            lenient: false,
+            preserve_raw: false,
+            index_sidecar: true,
+            index_cache: None,
+            progress: None,
+            reuse: None,
+            name_encoding: NameEncoding::default(),
+            name_transform: None,
+            warnings: None,
+            read_ahead: None,
+        }
+    }
+}
+
+impl<'a> OpenOptions<'a> {
+    /// Creates the options [`Pk2::open`] and [`Pk2::open_in`] use.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses an already derived cipher instead of deriving one from the key, see
+    /// [`Pk2::open_with_cipher`].
+    pub fn cipher(mut self, cipher: &'a Blowfish) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    /// Sets whether a file table that is cut off by the end of the stream is tolerated, see
+    /// [`Pk2::open_in_lenient`].
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Sets whether the bytes of entries that are not interpreted, like their padding or the
+    /// bytes after the nul terminator of their name, are written back unchanged when the entry
+    /// is modified, disabled by default.
+    ///
+    /// Without this, these bytes are zeroed whenever an entry is written. Enable it for tools
+    /// that have to leave everything they don't change byte for byte intact, for example because
+    /// the archive is hashed. The name field is written anew once an entry is renamed.
+    pub fn preserve_raw_entries(mut self, preserve: bool) -> Self {
+        self.preserve_raw = preserve;
+        self
+    }
+
+    /// Sets whether [`Pk2::open_with`] restores the file table from the index sidecar written by
+    /// [`Pk2::write_index_sidecar`], enabled by default.
+    ///
+    /// The sidecar is never used when parsing leniently or collecting warnings, as both need the
+    /// file table to be parsed.
+    pub fn index_sidecar(mut self, index_sidecar: bool) -> Self {
+        self.index_sidecar = index_sidecar;
+        self
+    }
+
+    /// Makes [`Pk2::open_with`] restore the file table from `cache` instead of the index sidecar,
+    /// see [`Pk2::open_with_index_cache`].
+    ///
+    /// The cache has to be created by an archive opened with the same name options.
+    pub fn index_cache(mut self, cache: &'a [u8]) -> Self {
+        self.index_cache = Some(cache);
+        self
+    }
+
+    /// Calls `progress` with the total number of blocks read so far while parsing the file
+    /// table, see [`Pk2::open_with_progress`]. Opening is aborted with the error it returns, if
+    /// any.
+    pub fn progress(mut self, progress: &'a mut dyn FnMut(usize) -> io::Result<()>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Decodes entry names with `encoding` instead of the default encoding of this build, see
+    /// [`Pk2::open_in_with_name_encoding`].
+    pub fn name_encoding(mut self, encoding: NameEncoding) -> Self {
+        self.name_encoding = encoding;
+        self
+    }
+
+    /// Descrambles entry names with `transform`.
+    ///
+    /// The name fields are descrambled after decrypting the file table and before decoding the
+    /// names, entries written to the archive afterwards have their names scrambled the same way.
+    pub fn name_transform(mut self, transform: NameTransform) -> Self {
+        self.name_transform = Some(transform);
+        self
+    }
+
+    /// Reuses the memory of the file table of `previous` for the file table of the opened
+    /// archive.
+    ///
+    /// This avoids growing the file table from scratch when opening lots of similarly sized
+    /// archives one after another.
+    pub fn reuse<B, L: LockChoice>(mut self, previous: Pk2<B, L>) -> Self {
+        self.reuse = Some(previous.block_manager);
+        self
+    }
+
+    /// Collects the recoverable anomalies found while parsing the file table in `warnings`.
+    ///
+    /// These are tolerated when opening the archive without collecting them as well, this only
+    /// makes them visible, for example to report what is odd about an archive that otherwise
+    /// opens fine.
+    pub fn warnings(mut self, warnings: &'a mut Vec<ParseWarning>) -> Self {
+        self.warnings = Some(warnings);
+        self
+    }
+
+    /// Reads the file table in chunks of `window` bytes instead of block by block.
+    ///
+    /// Whenever a block is needed that lies outside of the last chunk read, the next `window`
+    /// bytes starting at that block are read and following blocks are parsed from memory as long
+    /// as they fall into it. This reduces the number of reads for archives whose blocks are mostly
+    /// stored back to back, like freshly packed ones, at the cost of reading over file data in
+    /// between. Without this option blocks are already read ahead while they are read
+    /// sequentially, so a fixed window only pays off if individual reads are expensive, like on
+    /// network file systems. Windows bigger than 64 MiB are capped.
+    pub fn read_ahead(mut self, window: usize) -> Self {
+        self.read_ahead = Some(window);
+        self
+    }
+}
+
+/// Identifies index caches created by [`Pk2::index_cache`].
+const INDEX_CACHE_MAGIC: &[u8; 8] = b"PK2INDEX";
+
+/// Identifies the state of an archive file by its size and modification time, used to invalidate
+/// index caches.
+fn index_cache_stamp(file: &stdfs::File) -> io::Result<[u8; 20]> {
+    let metadata = file.metadata()?;
+    let modified =
+        metadata.modified()?.duration_since(std::time::UNIX_EPOCH).map_err(io::Error::other)?;
+    let mut stamp = [0; 20];
+    stamp[..8].copy_from_slice(&metadata.len().to_le_bytes());
+    stamp[8..16].copy_from_slice(&modified.as_secs().to_le_bytes());
+    stamp[16..].copy_from_slice(&modified.subsec_nanos().to_le_bytes());
+    Ok(stamp)
+}
+
+/// Returns the file table of `cache` if it was created by [`Pk2::index_cache`] for the current
+/// state of `file`.
+fn strip_index_cache_stamp<'c>(
+    cache: &'c [u8],
+    file: &stdfs::File,
+) -> io::Result<Option<&'c [u8]>> {
+    let stamp = index_cache_stamp(file)?;
+    Ok(cache.strip_prefix(&INDEX_CACHE_MAGIC[..]).and_then(|cache| cache.strip_prefix(&stamp[..])))
+}
+
+/// Returns the path of the index sidecar of the archive at `path`, which is the archive's path
+/// with `.idx` appended.
+fn index_sidecar_path(path: &Path) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".idx");
+    PathBuf::from(sidecar)
+}
+
+/// What [`Pk2::walk_mut`] should do with a visited entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WalkAction {
+    /// Leave the entry as is.
+    Keep,
+    /// Delete the entry.
+    Delete,
+    /// Rename the entry to the given name, keeping it in the same directory.
+    Rename(String),
+}
 
 /// A Pk2 archive.
 pub struct Pk2<Buffer, L: LockChoice> {
     stream: <L as LockChoice>::Lock<Buffer>,
     blowfish: Option<Box<Blowfish>>,
+    header: PackHeader,
     block_manager: BlockManager,
-    유령: PhantomData<Buffer>,
+    free_list: FreeList,
+    reuse_free_space: bool,
+    read_only: bool,
+    panic_on_drop_flush_error: bool,
+    // the buffer is only ever accessed through the lock, so it must not affect auto traits
+    유령: PhantomData<fn() -> Buffer>,
+}
+
+impl<B: io::Read, L: LockChoice> Pk2<B, L> {
+    /// Checks whether the key opens the archive read from `reader`, without parsing its file
+    /// table.
+    ///
+    /// Only the header at the start of the reader is read, which makes this cheap enough to
+    /// probe lots of archives for the key they are encrypted with. Unencrypted archives are
+    /// opened by any key. For encrypted archives an empty key results in
+    /// [`OpenError::MissingKey`] and keys of invalid length in [`OpenError::InvalidKey`], archives
+    /// with an invalid header in [`OpenError::CorruptedFile`] or [`OpenError::UnsupportedVersion`].
+    pub fn verify_key<K: AsRef<[u8]>>(reader: B, key: K) -> OpenResult<bool> {
+        PackHeader::read_and_verify(reader, key.as_ref())
+    }
 }
 
 impl<L: LockChoice> Pk2<stdfs::File, L> {
@@ -38,13 +287,200 @@ impl<L: LockChoice> Pk2<stdfs::File, L> {
         Self::_create_impl(file, key)
     }
 
+    /// Creates a new [`File`](stdfs::File) based archive at the given path containing the given
+    /// directories.
+    ///
+    /// If any of the directories can't be created, the archive file is removed again and the error
+    /// is returned.
+    pub fn create_new_with_dirs<P: AsRef<Path>, K: AsRef<[u8]>, D: AsRef<Path>>(
+        path: P,
+        key: K,
+        dirs: &[D],
+    ) -> OpenResult<Self> {
+        let path = path.as_ref();
+        let mut archive = Self::create_new(path, key)?;
+        match dirs.iter().try_for_each(|dir| archive.create_dir_all(dir)) {
+            Ok(()) => Ok(archive),
+            Err(e) => {
+                drop(archive);
+                let _ = stdfs::remove_file(path);
+                Err(e.into())
+            }
+        }
+    }
+
     /// Opens an archive at the given path.
     ///
     /// Note this eagerly parses the whole archive's file table into memory incurring a lot of read
     /// operations on the file making this operation potentially slow.
+    ///
+    /// If an index sidecar written by [`Pk2::write_index_sidecar`] exists next to the archive, the
+    /// file table is restored from it instead. A sidecar that is stale because the archive changed
+    /// since it was written is ignored and rewritten, failing to rewrite it is not an error.
     pub fn open<P: AsRef<Path>, K: AsRef<[u8]>>(path: P, key: K) -> OpenResult<Self> {
+        Self::open_with(path, key, OpenOptions::new())
+    }
+
+    /// Opens an archive at the given path with the given options.
+    ///
+    /// Unless disabled with [`OpenOptions::index_sidecar`], the index sidecar is used like in
+    /// [`Pk2::open`].
+    pub fn open_with<P: AsRef<Path>, K: AsRef<[u8]>>(
+        path: P,
+        key: K,
+        options: OpenOptions<'_>,
+    ) -> OpenResult<Self> {
+        let path = path.as_ref();
         let file = stdfs::OpenOptions::new().write(true).read(true).open(path)?;
-        Self::_open_in_impl(file, key)
+        let needs_parse = options.lenient || options.warnings.is_some();
+        if let Some(cache) = options.index_cache.filter(|_| !needs_parse) {
+            let index_cache = strip_index_cache_stamp(cache, &file).ok().flatten();
+            return Self::_open_in_impl_with(file, key.as_ref(), options, index_cache);
+        }
+        if !options.index_sidecar || needs_parse {
+            return Self::_open_in_impl_with(file, key.as_ref(), options, None);
+        }
+        // the sidecar is only a cache, so any problem with it falls back to parsing the archive
+        let Ok(sidecar) = stdfs::read(index_sidecar_path(path)) else {
+            return Self::_open_in_impl_with(file, key.as_ref(), options, None);
+        };
+        let Ok(index_cache) = strip_index_cache_stamp(&sidecar, &file) else {
+            return Self::_open_in_impl_with(file, key.as_ref(), options, None);
+        };
+        let archive = Self::_open_in_impl_with(file, key.as_ref(), options, index_cache)?;
+        if index_cache.is_none() {
+            let _ = archive.write_index_sidecar(path);
+        }
+        Ok(archive)
+    }
+
+    /// Opens an archive at the given path using an already derived cipher.
+    ///
+    /// This skips the key derivation which is useful when opening lots of archives sharing the
+    /// same key.
+    pub fn open_with_cipher<P: AsRef<Path>>(path: P, cipher: &Blowfish) -> OpenResult<Self> {
+        Self::open_with(path, "", OpenOptions::new().cipher(cipher))
+    }
+
+    /// Opens an archive at the given path, tolerating a file table that is cut off by the end of
+    /// the file.
+    ///
+    /// See [`Pk2::open_in_lenient`].
+    pub fn open_lenient<P: AsRef<Path>, K: AsRef<[u8]>>(path: P, key: K) -> OpenResult<Self> {
+        Self::open_with(path, key, OpenOptions::new().lenient(true))
+    }
+
+    /// Opens an archive at the given path, decoding entry names with `encoding` instead of the
+    /// default encoding of this build.
+    ///
+    /// See [`Pk2::open_in_with_name_encoding`].
+    pub fn open_with_name_encoding<P: AsRef<Path>, K: AsRef<[u8]>>(
+        path: P,
+        key: K,
+        encoding: NameEncoding,
+    ) -> OpenResult<Self> {
+        Self::open_with(path, key, OpenOptions::new().name_encoding(encoding))
+    }
+
+    /// Opens an archive at the given path, calling `progress` with the total number of blocks read
+    /// so far while parsing its file table.
+    ///
+    /// Parsing the file table of big archives can take a while, this allows interactive tools to
+    /// report that something is happening.
+    pub fn open_with_progress<P: AsRef<Path>, K: AsRef<[u8]>>(
+        path: P,
+        key: K,
+        mut progress: impl FnMut(usize),
+    ) -> OpenResult<Self> {
+        let mut progress = |blocks| {
+            progress(blocks);
+            Ok(())
+        };
+        Self::open_with(path, key, OpenOptions::new().progress(&mut progress))
+    }
+
+    /// Opens an archive at the given path, checking `cancel` after each parsed block and
+    /// failing with an [`Interrupted`](io::ErrorKind::Interrupted) error once it is set.
+    ///
+    /// This allows bounding the time spent opening huge or malicious archives from another thread
+    /// without having to kill the opening thread.
+    pub fn open_cancellable<P: AsRef<Path>, K: AsRef<[u8]>>(
+        path: P,
+        key: K,
+        cancel: &AtomicBool,
+    ) -> OpenResult<Self> {
+        let mut progress = |_| match cancel.load(Ordering::Relaxed) {
+            true => Err(io::Error::new(io::ErrorKind::Interrupted, "opening was cancelled")),
+            false => Ok(()),
+        };
+        Self::open_with(path, key, OpenOptions::new().progress(&mut progress))
+    }
+
+    /// Opens an archive at the given path, restoring its file table from a cache previously
+    /// created by [`Pk2::index_cache`] instead of reading and decrypting it.
+    ///
+    /// The cache is ignored if the file's size or modification time changed since it was
+    /// created or if it is invalid, in which case the file table is parsed as usual. Archives
+    /// with non-default name options are opened with [`OpenOptions::index_cache`] instead.
+    pub fn open_with_index_cache<P: AsRef<Path>, K: AsRef<[u8]>>(
+        path: P,
+        key: K,
+        cache: &[u8],
+    ) -> OpenResult<Self> {
+        Self::open_with(path, key, OpenOptions::new().index_cache(cache))
+    }
+
+    /// Serializes the parsed file table of this archive for use with
+    /// [`Pk2::open_with_index_cache`].
+    ///
+    /// The cache is tied to the current size and modification time of the file, so it should be
+    /// created after all modifications to the archive have been made.
+    pub fn index_cache(&self) -> io::Result<Vec<u8>> {
+        let stamp = self.stream.with_lock(|file| index_cache_stamp(file))?;
+        let mut cache = Vec::new();
+        cache.extend_from_slice(INDEX_CACHE_MAGIC);
+        cache.extend_from_slice(&stamp);
+        self.block_manager.to_cache_bytes(&mut cache);
+        Ok(cache)
+    }
+
+    /// Writes the [`Pk2::index_cache`] of this archive to the index sidecar of the archive at
+    /// `path`, which [`Pk2::open`] picks up to skip parsing the file table.
+    ///
+    /// `path` is the path of the archive itself, the sidecar is placed next to it with `.idx`
+    /// appended to the file name.
+    pub fn write_index_sidecar<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        stdfs::write(index_sidecar_path(path.as_ref()), self.index_cache()?)
+    }
+
+    /// Like [`Pk2::shrink_file`], but if the file's data is the last region of the archive, the
+    /// archive file is truncated to the new end of the data instead of leaving the cut off bytes
+    /// behind.
+    pub fn shrink_file_and_truncate<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        new_size: u32,
+    ) -> io::Result<()> {
+        let (new_end, cut_len) = self.shrink_entry(path.as_ref(), new_size)?;
+        let truncated = self.stream.with_lock(|file| {
+            let is_last = file.metadata()?.len() == new_end.0 + cut_len as u64;
+            if is_last {
+                file.set_len(new_end.0)?;
+            }
+            io::Result::Ok(is_last)
+        })?;
+        if !truncated {
+            self.free_list.free(new_end, cut_len);
+        }
+        Ok(())
+    }
+
+    /// Truncates the archive file to an empty archive encrypted with the same key.
+    ///
+    /// This is faster than deleting every entry when rebuilding an archive in place, while
+    /// keeping the file handle open.
+    pub fn truncate_archive(&mut self) -> io::Result<()> {
+        self.truncate_with(|file| file.set_len(0))
     }
 }
 
@@ -70,17 +506,70 @@ impl<L: LockChoice> Pk2<ReadOnly<stdfs::File>, L> {
     }
 }
 
+impl<L: LockChoice> Pk2<BlockCache<stdfs::File>, L> {
+    /// Opens an archive at the given path, keeping up to `cache_size` recently read blocks of the
+    /// file in memory.
+    ///
+    /// This speeds up workloads that repeatedly read the same files. [`Pk2::cache_stats`] reports
+    /// how effective the cache is for tuning its size.
+    pub fn open_cached<P: AsRef<Path>, K: AsRef<[u8]>>(
+        path: P,
+        key: K,
+        cache_size: usize,
+    ) -> OpenResult<Self> {
+        let file = stdfs::OpenOptions::new().write(true).read(true).open(path)?;
+        Self::_open_in_impl(BlockCache::new(file, cache_size), key)
+    }
+}
+
+impl<B, L: LockChoice> Pk2<BlockCache<B>, L> {
+    /// Returns the hit and miss counts of the block cache.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.stream.with_lock(|stream| stream.stats())
+    }
+}
+
+impl<B: io::Read + io::Seek, L: LockChoice> Pk2<Offset<B>, L> {
+    /// Opens an archive that is embedded in `stream`, starting at `base_offset`.
+    ///
+    /// All offsets of the archive are relative to `base_offset`, data appended to the archive is
+    /// appended to the end of `stream`.
+    pub fn open_at_offset<K: AsRef<[u8]>>(stream: B, key: K, base_offset: u64) -> OpenResult<Self> {
+        Self::open_in(Offset::new(stream, base_offset)?, key)
+    }
+}
+
 impl<L: LockChoice> Pk2<io::Cursor<Vec<u8>>, L> {
     /// Creates a new archive in memory.
     pub fn create_new_in_memory<K: AsRef<[u8]>>(
         key: K,
     ) -> Result<Self, crate::blowfish::InvalidKey> {
-        Self::_create_impl(io::Cursor::new(Vec::with_capacity(4096)), key).map_err(|e| {
+        Self::create_new_in_memory_with_capacity(key, 4096)
+    }
+
+    /// Creates a new archive in memory with its backing buffer pre-allocated to hold at least
+    /// `capacity` bytes.
+    pub fn create_new_in_memory_with_capacity<K: AsRef<[u8]>>(
+        key: K,
+        capacity: usize,
+    ) -> Result<Self, crate::blowfish::InvalidKey> {
+        Self::_create_impl(io::Cursor::new(Vec::with_capacity(capacity)), key).map_err(|e| {
             debug_assert!(matches!(&e, OpenError::InvalidKey));
             // the only error that can actually occur here is an InvalidKey error
             crate::blowfish::InvalidKey
         })
     }
+
+    /// Clears the archive to an empty archive encrypted with the same key.
+    ///
+    /// This is faster than deleting every entry when rebuilding an archive in place.
+    pub fn truncate_archive(&mut self) -> io::Result<()> {
+        self.truncate_with(|cursor| {
+            cursor.get_mut().clear();
+            cursor.set_position(0);
+            Ok(())
+        })
+    }
 }
 
 impl<L: LockChoice> From<Pk2<io::Cursor<Vec<u8>>, L>> for Vec<u8> {
@@ -91,36 +580,126 @@ impl<L: LockChoice> From<Pk2<io::Cursor<Vec<u8>>, L>> for Vec<u8> {
 
 impl<B, L> Pk2<B, L>
 where
-    B: io::Read + io::Seek,
+    B: Backing,
     L: LockChoice,
 {
     /// Opens an archive from the given stream.
     ///
     /// Note this eagerly parses the whole archive's file table into memory incurring a lot of read
     /// operations on the stream.
-    pub fn open_in<K: AsRef<[u8]>>(mut stream: B, key: K) -> OpenResult<Self> {
-        stream.seek(io::SeekFrom::Start(0))?;
+    ///
+    /// As `&std::fs::File` implements [`Read`](io::Read) and [`Seek`](io::Seek) as well, an
+    /// already opened file handle can be shared with the archive by passing a reference to it,
+    /// wrapping it in [`ReadOnly`] to get a read-only archive.
+    ///
+    /// ```rust,no_run
+    /// # let archive_path = "";
+    /// # let key = b"";
+    /// use pk2::unsync::readonly::Pk2;
+    /// let file = std::fs::File::open(archive_path).unwrap();
+    /// let archive = Pk2::<&std::fs::File>::open_in(pk2::ReadOnly(&file), key).unwrap();
+    /// ```
+    pub fn open_in<K: AsRef<[u8]>>(stream: B, key: K) -> OpenResult<Self> {
         Self::_open_in_impl(stream, key)
     }
 
-    fn _open_in_impl<K: AsRef<[u8]>>(mut stream: B, key: K) -> OpenResult<Self> {
-        let header = PackHeader::from_reader(&mut stream)?;
+    /// Opens an archive from the given stream with the given options.
+    ///
+    /// See [`Pk2::open_in`].
+    pub fn open_in_with<K: AsRef<[u8]>>(
+        stream: B,
+        key: K,
+        options: OpenOptions<'_>,
+    ) -> OpenResult<Self> {
+        Self::_open_in_impl_with(stream, key.as_ref(), options, None)
+    }
+
+    /// Opens an archive from the given stream using an already derived cipher.
+    ///
+    /// See [`Pk2::open_in`] and [`Pk2::open_with_cipher`].
+    pub fn open_in_with_cipher(stream: B, cipher: &Blowfish) -> OpenResult<Self> {
+        Self::open_in_with(stream, "", OpenOptions::new().cipher(cipher))
+    }
+
+    /// Opens an archive from the given stream, tolerating a file table that is cut off by the end
+    /// of the stream.
+    ///
+    /// Blocks that are only partially contained in the stream have their incomplete entries
+    /// replaced by empty ones, so that the entries that were read completely stay accessible. The
+    /// offsets of such blocks are reported by [`Pk2::truncated_blocks`]. Well-formed archives
+    /// are parsed exactly like with [`Pk2::open_in`].
+    pub fn open_in_lenient<K: AsRef<[u8]>>(stream: B, key: K) -> OpenResult<Self> {
+        Self::open_in_with(stream, key, OpenOptions::new().lenient(true))
+    }
+
+    /// Opens an archive from the given stream, decoding entry names with `encoding` instead of
+    /// the default encoding of this build.
+    ///
+    /// This allows opening archives with UTF-8 names in builds with the `euc-kr` feature enabled.
+    /// Entries created in the archive afterwards have their names encoded the same way.
+    pub fn open_in_with_name_encoding<K: AsRef<[u8]>>(
+        stream: B,
+        key: K,
+        encoding: NameEncoding,
+    ) -> OpenResult<Self> {
+        Self::open_in_with(stream, key, OpenOptions::new().name_encoding(encoding))
+    }
+
+    fn _open_in_impl<K: AsRef<[u8]>>(stream: B, key: K) -> OpenResult<Self> {
+        Self::_open_in_impl_with(stream, key.as_ref(), OpenOptions::new(), None)
+    }
+
+    /// Opens the archive in `stream`, restoring the file table from `index_cache` if given and
+    /// valid.
+    fn _open_in_impl_with(
+        mut stream: B,
+        key: &[u8],
+        options: OpenOptions<'_>,
+        index_cache: Option<&[u8]>,
+    ) -> OpenResult<Self> {
+        let header = crate::io::read_header(&mut stream)?;
         header.validate_sig()?;
         let blowfish = if header.encrypted {
-            let bf = Blowfish::new(key.as_ref())?;
-            let mut checksum = *PK2_CHECKSUM;
-            bf.encrypt(&mut checksum);
-            header.verify(checksum)?;
+            let bf = match options.cipher {
+                Some(cipher) => cipher.clone(),
+                None => Blowfish::for_encrypted_archive(key)?,
+            };
+            header.verify_cipher(&bf)?;
             Some(Box::new(bf))
         } else {
             None
         };
-        let block_manager = BlockManager::new(blowfish.as_deref(), &mut stream)?;
+        let codec =
+            NameCodec { encoding: options.name_encoding, transform: options.name_transform };
+        let cached = index_cache.and_then(|cache| {
+            let stream_len = stream.storage_size().ok();
+            BlockManager::from_cache_bytes(cache, codec.clone(), options.preserve_raw, stream_len)
+                .ok()
+        });
+        let block_manager = match cached {
+            Some(block_manager) => block_manager,
+            None => BlockManager::new_with(
+                blowfish.as_deref(),
+                &mut stream,
+                codec,
+                options.preserve_raw,
+                options.lenient,
+                options.progress.unwrap_or(&mut |_| Ok(())),
+                options.reuse,
+                options.warnings,
+                options.read_ahead,
+            )?,
+        };
 
         Ok(Pk2 {
             stream: <L as LockChoice>::Lock::new(stream),
             blowfish,
+            header,
             block_manager,
+            free_list: FreeList::default(),
+            reuse_free_space: false,
+            read_only: false,
+            panic_on_drop_flush_error: false,
             유령: PhantomData,
         })
     }
@@ -128,11 +707,10 @@ where
 
 impl<B, L> Pk2<B, L>
 where
-    B: io::Read + io::Write + io::Seek,
+    B: Backing + BackingMut,
     L: LockChoice,
 {
-    pub fn create_new_in<K: AsRef<[u8]>>(mut stream: B, key: K) -> OpenResult<Self> {
-        stream.seek(io::SeekFrom::Start(0))?;
+    pub fn create_new_in<K: AsRef<[u8]>>(stream: B, key: K) -> OpenResult<Self> {
         Self::_create_impl(stream, key)
     }
 
@@ -144,13 +722,70 @@ where
             (PackHeader::new_encrypted(&bf), Some(Box::new(bf)))
         };
 
-        header.to_writer(&mut stream)?;
+        Self::write_empty(&mut stream, blowfish.as_deref(), &header, &NameCodec::default())?;
+        let block_manager = BlockManager::new(blowfish.as_deref(), &mut stream)?;
+        Ok(Pk2 {
+            stream: L::new_locked(stream),
+            blowfish,
+            header,
+            block_manager,
+            free_list: FreeList::default(),
+            reuse_free_space: false,
+            read_only: false,
+            panic_on_drop_flush_error: false,
+            유령: PhantomData,
+        })
+    }
+
+    /// Writes the header and an empty root block.
+    fn write_empty<S: BackingMut + ?Sized>(
+        stream: &mut S,
+        blowfish: Option<&Blowfish>,
+        header: &PackHeader,
+        codec: &NameCodec,
+    ) -> io::Result<()> {
+        crate::io::write_header(&mut *stream, header)?;
         let mut block = PackBlock::default();
         block[0] = PackEntry::new_directory(PK2_CURRENT_DIR_IDENT, PK2_ROOT_BLOCK, None);
-        crate::io::write_block(blowfish.as_deref(), &mut stream, PK2_ROOT_BLOCK.into(), &block)?;
+        crate::io::write_block(blowfish, stream, PK2_ROOT_BLOCK.into(), &block, codec)
+    }
 
-        let block_manager = BlockManager::new(blowfish.as_deref(), &mut stream)?;
-        Ok(Pk2 { stream: L::new_locked(stream), blowfish, block_manager, 유령: PhantomData })
+    /// Resets the archive to an empty one with the same key after `truncate` has emptied the
+    /// stream.
+    ///
+    /// The in-memory state is reset as soon as `truncate` succeeded, so it never describes content
+    /// that is already gone, even if writing the empty file table fails afterwards.
+    fn truncate_with(&mut self, truncate: impl FnOnce(&mut B) -> io::Result<()>) -> io::Result<()> {
+        self.check_writable()?;
+        let blowfish = self.blowfish.as_deref();
+        let mut header = blowfish.map_or_else(PackHeader::default, PackHeader::new_encrypted);
+        header.reserved = self.header.reserved;
+        let codec = self.block_manager.name_codec().clone();
+        // build the new state from an in-memory image first, that way nothing can fail anymore
+        // after the stream has been truncated except for writing the image back
+        let mut image = io::Cursor::new(Vec::new());
+        Self::write_empty(&mut image, blowfish, &header, &codec)?;
+        let mut block_manager = BlockManager::new_with(
+            blowfish,
+            &mut image,
+            codec,
+            false,
+            false,
+            &mut |_| Ok(()),
+            None,
+            None,
+            None,
+        )
+        .map_err(|e| match e {
+            OpenError::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        })?;
+        self.stream.with_lock(truncate)?;
+        block_manager.set_path_cache(self.block_manager.has_path_cache());
+        self.block_manager = block_manager;
+        self.header = header;
+        self.free_list = FreeList::default();
+        self.stream.with_lock(|stream| stream.write_all_at(0, image.get_ref()))
     }
 }
 
@@ -176,47 +811,230 @@ impl<L: LockChoice, B> Pk2<B, L> {
         path: P,
     ) -> ChainLookupResult<(ChainIndex, usize, &PackEntry)> {
         self.block_manager
-            .resolve_path_to_entry_and_parent(PK2_ROOT_BLOCK, check_root(path.as_ref())?)
+            .resolve_path_to_entry_and_parent(PK2_ROOT_BLOCK, &check_root(path.as_ref())?)
+    }
+
+    /// Annotates a lookup error for the given path with the component the lookup failed at.
+    fn path_lookup_error(&self, path: &Path, error: ChainLookupError) -> PathLookupError {
+        let path = check_root(path);
+        let component = path
+            .as_deref()
+            .ok()
+            .and_then(|path| self.block_manager.failing_component(PK2_ROOT_BLOCK, path));
+        PathLookupError::new(error, component)
+    }
+
+    fn check_writable(&self) -> ChainLookupResult<()> {
+        match self.read_only {
+            true => Err(ChainLookupError::ReadOnly),
+            false => Ok(()),
+        }
     }
 
     fn is_file(entry: &PackEntry) -> ChainLookupResult<()> {
-        match entry.is_file() {
-            true => Ok(()),
-            false => Err(ChainLookupError::ExpectedFile),
+        match entry.as_non_empty() {
+            Some(entry) if entry.is_file() => Ok(()),
+            Some(_) => Err(ChainLookupError::IsADirectory),
+            None => Err(ChainLookupError::NotFound),
         }
     }
 
     fn is_dir(entry: &PackEntry) -> ChainLookupResult<()> {
-        match entry.is_directory() {
-            true => Ok(()),
-            false => Err(ChainLookupError::ExpectedDirectory),
+        match entry.as_non_empty() {
+            Some(entry) if entry.is_directory() => Ok(()),
+            Some(_) => Err(ChainLookupError::IsAFile),
+            None => Err(ChainLookupError::NotFound),
+        }
+    }
+
+    /// Returns the directory whose children are stored in the given chain, finding its entry
+    /// through the `..` link of the chain.
+    fn directory_of_chain(&self, chain: ChainIndex) -> ChainLookupResult<Directory<'_, B, L>> {
+        if chain == PK2_ROOT_BLOCK {
+            return Ok(self.open_root_dir());
+        }
+        let case = self.block_manager.case_sensitivity();
+        let parent = self
+            .get_chain(chain)
+            .ok_or(ChainLookupError::InvalidChainIndex)?
+            .find_block_chain_index_of(PK2_PARENT_DIR_IDENT, case)?;
+        let entry_idx = self
+            .get_chain(parent)
+            .ok_or(ChainLookupError::InvalidChainIndex)?
+            .entries()
+            .position(|entry| {
+                entry.as_non_empty().is_some_and(|it| {
+                    it.is_normal_link() && it.directory_children_position() == Some(chain)
+                })
+            })
+            .ok_or(ChainLookupError::NotFound)?;
+        Ok(Directory::new(self, parent, entry_idx))
+    }
+}
+
+/// Returns the canonical form of an archive path.
+///
+/// Both `/` and `\` are accepted as separators. The result is absolute, separated by `/` only and
+/// has redundant separators and `.` components removed and `..` components resolved. Relative
+/// paths are taken to be relative to the root directory. Fails with
+/// [`ChainLookupError::InvalidPath`] if a `..` component leads out of the root directory.
+///
+/// All methods taking archive paths resolve them this way.
+///
+/// ```
+/// assert_eq!(pk2::normalize_path("\\data//textures/./../sound.dat").unwrap(), "/data/sound.dat");
+/// assert!(pk2::normalize_path("/../data").is_err());
+/// ```
+pub fn normalize_path(path: &str) -> ChainLookupResult<String> {
+    match normalize(Path::new(path))? {
+        NormalizedPath { parents: 0, path, .. } => Ok(format!("/{}", path)),
+        _ => Err(ChainLookupError::InvalidPath),
+    }
+}
+
+/// An archive path with its `.` and `..` components resolved, see [`normalize`].
+struct NormalizedPath {
+    /// Whether the path starts at the root directory.
+    absolute: bool,
+    /// The number of `..` components leading out of the directory a relative path starts at.
+    parents: usize,
+    /// The remaining components separated by `/`, empty if the path refers to the directory it
+    /// starts at.
+    path: String,
+}
+
+impl NormalizedPath {
+    /// Returns the path relative to the directory it starts at, including the leading `..`
+    /// components.
+    fn relative(&self) -> PathBuf {
+        let parents = std::iter::repeat_n(PK2_PARENT_DIR_IDENT, self.parents);
+        parents.chain((!self.path.is_empty()).then_some(&*self.path)).collect()
+    }
+}
+
+/// Lexically normalizes an archive path.
+///
+/// Both `/` and `\` are accepted as separators, empty and `.` components are removed and `..`
+/// components are resolved. Fails with [`ChainLookupError::InvalidPath`] if the path is not
+/// valid UTF-8 or if an absolute path leads out of the root directory.
+fn normalize(path: &Path) -> ChainLookupResult<NormalizedPath> {
+    let path = path.to_str().ok_or(ChainLookupError::InvalidPath)?;
+    let absolute = path.starts_with(['/', '\\']);
+    let mut parents = 0;
+    let mut components = Vec::new();
+    for component in path.split(['/', '\\']) {
+        match component {
+            "" | "." => (),
+            ".." if components.pop().is_some() => (),
+            ".." if absolute => return Err(ChainLookupError::InvalidPath),
+            ".." => parents += 1,
+            name => components.push(name),
         }
     }
+    Ok(NormalizedPath { absolute, parents, path: components.join("/") })
+}
+
+/// Resolves `path` lexically, returning the chain it starts at, which is the root directory for
+/// absolute paths and `chain` otherwise, and the path relative to it.
+fn normalize_at(chain: ChainIndex, path: &Path) -> ChainLookupResult<(ChainIndex, PathBuf)> {
+    let path = normalize(path)?;
+    let chain = if path.absolute { PK2_ROOT_BLOCK } else { chain };
+    Ok((chain, path.relative()))
 }
 
 impl<B, L: LockChoice> Pk2<B, L> {
-    pub fn open_file<P: AsRef<Path>>(&self, path: P) -> ChainLookupResult<File<B, L>> {
+    /// The size of the archive header at the start of the stream in bytes.
+    pub const HEADER_SIZE: u64 = PK2_HEADER_SIZE as u64;
+    /// The size of a block of the file table in bytes.
+    pub const BLOCK_SIZE: u64 = PK2_FILE_BLOCK_SIZE as u64;
+
+    /// Returns how entry names are compared when resolving paths.
+    pub fn case_sensitivity(&self) -> CaseSensitivity {
+        self.block_manager.case_sensitivity()
+    }
+
+    /// Returns the encoding entry names are stored in.
+    pub fn name_encoding(&self) -> NameEncoding {
+        self.block_manager.name_encoding()
+    }
+
+    /// Sets how entry names are compared when resolving paths.
+    ///
+    /// Defaults to [`CaseSensitivity::Insensitive`] which matches the game's behavior. Resolving
+    /// case-sensitively allows detecting entries that only differ in their case, as the game would
+    /// only ever see the first of them.
+    pub fn set_case_sensitivity(&mut self, case: CaseSensitivity) {
+        self.block_manager.set_case_sensitivity(case);
+    }
+
+    /// Sets whether the chains of directories resolved from the root are cached by their path.
+    ///
+    /// This speeds up repeatedly resolving paths inside of the same directories, at the cost of
+    /// memory for the cached paths. The cache is cleared whenever the file table is modified.
+    /// Disabled by default.
+    pub fn set_path_cache(&mut self, enabled: bool) {
+        self.block_manager.set_path_cache(enabled);
+    }
+
+    /// Returns the reserved bytes at the end of the archive header.
+    ///
+    /// The format itself leaves these unused, but some archives store a build stamp in them that
+    /// allows correlating the archive with a game version.
+    pub fn header_reserved(&self) -> &[u8] {
+        self.header.reserved()
+    }
+
+    /// Returns whether the archive has been marked as read-only via [`Pk2::set_read_only`].
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Marks the archive as read-only, making all mutating operations fail with
+    /// [`ChainLookupError::ReadOnly`] even if the backing buffer is writable.
+    ///
+    /// This is the runtime counterpart to opening the archive with a [`ReadOnly`] buffer.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    pub fn open_file<P: AsRef<Path>>(&self, path: P) -> ChainLookupResult<File<'_, B, L>> {
         let (chain, entry_idx, entry) = self.root_resolve_path_to_entry_and_parent(path)?;
         Self::is_file(entry)?;
         Ok(File::new(self, chain, entry_idx))
     }
 
-    pub fn open_directory<P: AsRef<Path>>(&self, path: P) -> ChainLookupResult<Directory<B, L>> {
-        let path = check_root(path.as_ref())?;
-        let (chain, entry_idx) =
-            match self.block_manager.resolve_path_to_entry_and_parent(PK2_ROOT_BLOCK, path) {
-                Ok((chain, entry_idx, entry)) => {
-                    Self::is_dir(entry)?;
-                    (chain, entry_idx)
-                }
-                // path was just root
-                Err(ChainLookupError::InvalidPath) => (PK2_ROOT_BLOCK_VIRTUAL, 0),
-                Err(e) => return Err(e),
-            };
-        Ok(Directory::new(self, chain, entry_idx))
+    /// Opens the directory at the absolute `path`.
+    ///
+    /// Paths consisting only of the root and `.` components like `/`, `//` or `/.` open the root
+    /// directory, paths ending in `.` or `..` open the directory they refer to.
+    pub fn open_directory<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> ChainLookupResult<Directory<'_, B, L>> {
+        let path = path.as_ref();
+        check_root(path)?;
+        self.open_root_dir().open_directory(path)
+    }
+
+    /// Opens the directory at `path` for creating and modifying the files inside of it.
+    pub fn open_directory_mut<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> ChainLookupResult<DirectoryMut<'_, B, L>> {
+        let (chain, entry_index) = self.open_directory(path)?.location();
+        Ok(DirectoryMut::new(self, chain, entry_index))
+    }
+
+    /// Returns a snapshot of the entries of the directory at `path`, excluding `.` and `..`.
+    ///
+    /// Unlike [`Directory::entries`] the returned entries do not borrow the archive.
+    pub fn read_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<Vec<DirEntryInfo>> {
+        let path = path.as_ref();
+        let dir = self.open_directory(path).map_err(|e| self.path_lookup_error(path, e))?;
+        Ok(dir.entries().map(DirEntryInfo::from).collect())
     }
 
-    pub fn open_root_dir(&self) -> Directory<B, L> {
+    pub fn open_root_dir(&self) -> Directory<'_, B, L> {
         Directory::new(self, PK2_ROOT_BLOCK_VIRTUAL, 0)
     }
 
@@ -228,30 +1046,280 @@ impl<B, L: LockChoice> Pk2<B, L> {
         base: impl AsRef<Path>,
         cb: impl FnMut(&Path, File<B, L>) -> io::Result<()>,
     ) -> io::Result<()> {
-        self.open_directory(base)?.for_each_file(cb)
+        let base = base.as_ref();
+        self.open_directory(base).map_err(|e| self.path_lookup_error(base, e))?.for_each_file(cb)
+    }
+
+    /// Returns an iterator over every file in the archive together with its absolute path.
+    pub fn files(&self) -> Files<'_, B, L> {
+        Files::new(self, PathBuf::from("/"), PK2_ROOT_BLOCK)
+    }
+
+    /// Returns the decrypted bytes the entry at `path` is stored as.
+    ///
+    /// The layout of the bytes is:
+    ///
+    /// | bytes      | field                                                   |
+    /// |------------|---------------------------------------------------------|
+    /// | `0`        | type, `0` empty, `1` directory, `2` file                |
+    /// | `1..82`    | nul terminated name                                     |
+    /// | `82..106`  | access, creation and modification time as `FILETIME`s   |
+    /// | `106..114` | position of the directory's block chain or file's data  |
+    /// | `114..118` | file size                                               |
+    /// | `118..126` | next block offset, only set on the last entry of a block |
+    /// | `126..128` | padding                                                 |
+    ///
+    /// All numbers are little endian.
+    pub fn raw_entry<P: AsRef<Path>>(&self, path: P) -> io::Result<[u8; PK2_FILE_ENTRY_SIZE]> {
+        let path = path.as_ref();
+        let (_, _, entry) = self
+            .root_resolve_path_to_entry_and_parent(path)
+            .map_err(|e| self.path_lookup_error(path, e))?;
+        Ok(entry.to_raw(self.block_manager.name_codec()))
+    }
+
+    /// Returns the absolute path, data offset and size of every file in the archive.
+    ///
+    /// Sorting these by offset allows finding reclaimable gaps or overlapping file data.
+    pub fn iter_files_with_offset(&self) -> Vec<(PathBuf, StreamOffset, u32)> {
+        let mut files = Vec::new();
+        // the callback never fails, so neither does the traversal
+        let _ = self.open_root_dir().for_each_file(|path, file| {
+            files.push((Path::new("/").join(path), file.pos_data(), file.size()));
+            Ok(())
+        });
+        files
+    }
+
+    /// Returns the offsets of all blocks making up the file table of the archive, in no particular
+    /// order.
+    ///
+    /// Each block occupies [`Pk2::BLOCK_SIZE`] bytes.
+    pub fn block_offsets(&self) -> impl Iterator<Item = BlockOffset> + '_ {
+        self.block_manager.block_offsets()
+    }
+
+    /// Returns the offsets of all blocks making up the file table of the archive together with
+    /// the number of non-empty entries in them, in no particular order.
+    ///
+    /// Blocks without any entries are left behind by deleting lots of entries and could be dropped
+    /// when compacting the file table.
+    pub fn block_occupancy(&self) -> impl Iterator<Item = (BlockOffset, usize)> + '_ {
+        self.block_manager.block_occupancy()
+    }
+
+    /// Returns the offsets of the blocks that were cut off by the end of the stream when the
+    /// archive was opened leniently.
+    pub fn truncated_blocks(&self) -> impl Iterator<Item = BlockOffset> + '_ {
+        self.block_manager.truncated_blocks().iter().copied()
+    }
+
+    /// Finds entries whose names collide under the case-insensitive comparison the game uses.
+    ///
+    /// Returns the path of every directory containing such entries together with the colliding
+    /// names. As lookups only ever find the first matching entry, all but the first entry of each
+    /// group are unreachable by the game.
+    pub fn find_duplicate_entries(&self) -> Vec<(String, Vec<String>)> {
+        let mut duplicates = Vec::new();
+        let mut visited = HashSet::new();
+        let mut stack = vec![(String::from("/"), PK2_ROOT_BLOCK)];
+        while let Some((path, chain)) = stack.pop() {
+            if !visited.insert(chain) {
+                continue;
+            }
+            let Some(chain) = self.get_chain(chain) else { continue };
+            let mut groups = HashMap::new();
+            let mut names = Vec::<Vec<&str>>::new();
+            for entry in chain.entries().filter_map(PackEntry::as_non_empty) {
+                if !entry.is_normal_link() {
+                    continue;
+                }
+                let group = *groups.entry(entry.name().to_ascii_lowercase()).or_insert_with(|| {
+                    names.push(Vec::new());
+                    names.len() - 1
+                });
+                names[group].push(entry.name());
+                if let Some(children) = entry.directory_children_position() {
+                    let child_path = match path.as_str() {
+                        "/" => format!("/{}", entry.name()),
+                        _ => format!("{}/{}", path, entry.name()),
+                    };
+                    stack.push((child_path, children));
+                }
+            }
+            let colliding: Vec<_> = names
+                .into_iter()
+                .filter(|names| names.len() > 1)
+                .flatten()
+                .map(String::from)
+                .collect();
+            if !colliding.is_empty() {
+                duplicates.push((path, colliding));
+            }
+        }
+        duplicates
     }
 }
 
 impl<B, L> Pk2<B, L>
 where
-    B: io::Read + io::Seek,
+    B: Backing,
     L: LockChoice,
 {
     pub fn read<P: AsRef<Path>>(&self, path: P) -> io::Result<Vec<u8>> {
-        let mut file = self.open_file(path)?;
-        let mut buf = Vec::with_capacity(file.size() as usize);
-        std::io::Read::read_to_end(&mut file, &mut buf)?;
+        let mut buf = Vec::new();
+        self.read_into(path, &mut buf)?;
         Ok(buf)
     }
+
+    /// Reads the contents of the file at `path` into `buf`, replacing its previous contents, and
+    /// returns the number of bytes read.
+    ///
+    /// Reusing the same buffer across calls avoids allocating for every file read.
+    pub fn read_into<P: AsRef<Path>>(&self, path: P, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let path = path.as_ref();
+        let mut file = self.open_file(path).map_err(|e| self.path_lookup_error(path, e))?;
+        buf.clear();
+        buf.reserve(file.size() as usize);
+        std::io::Read::read_to_end(&mut file, buf)
+    }
+
+    /// Reads the data of every file in the archive, returning the path of every file that could
+    /// not be read completely together with the error.
+    ///
+    /// This exercises the data of the files rather than the structure of the file table, catching
+    /// data positions and sizes pointing past the end of the stream. The data is streamed, so
+    /// memory use stays bounded regardless of the file sizes.
+    pub fn verify_all_files(&self) -> Vec<(PathBuf, io::Error)> {
+        self.files()
+            .filter_map(|(path, mut file)| {
+                let size = file.size() as u64;
+                let res =
+                    io::copy(&mut file, &mut io::sink()).and_then(|read| match read == size {
+                        true => Ok(()),
+                        false => Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            format!("only {read} of {size} bytes could be read"),
+                        )),
+                    });
+                res.err().map(|e| (path, e))
+            })
+            .collect()
+    }
+
+    /// Reads the whole backing stream of the archive into memory.
+    ///
+    /// This allocates a buffer as big as the archive, which for the game's archives can be
+    /// multiple gigabytes. For in-memory archives, converting the archive into a `Vec<u8>` avoids
+    /// the copy.
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        self.stream.with_lock(|stream| {
+            let len = usize::try_from(stream.storage_size()?).map_err(io::Error::other)?;
+            let mut buf = vec![0; len];
+            stream.read_exact_at(0, &mut buf)?;
+            Ok(buf)
+        })
+    }
+
+    /// Returns the regions of the stream that are neither part of the header, the file table nor
+    /// the data of any file, including the slack at the end of the stream.
+    ///
+    /// The regions are sorted by their offset and returned together with their length. These are
+    /// left behind by deleted, moved or shrunk files and can be reclaimed by compacting the
+    /// archive.
+    pub fn unused_regions(&self) -> io::Result<Vec<(StreamOffset, u64)>> {
+        let len = self.stream.with_lock(|stream| stream.storage_size())?;
+        let mut regions = self
+            .iter_files_with_offset()
+            .into_iter()
+            .map(|(_, StreamOffset(offset), size)| (offset, size as u64))
+            .chain(self.block_offsets().map(|BlockOffset(offset)| (offset, Self::BLOCK_SIZE)))
+            .chain([(0, Self::HEADER_SIZE), (len, 0)])
+            .collect::<Vec<_>>();
+        regions.sort_unstable();
+        let mut unused = Vec::new();
+        let mut covered = 0;
+        for (offset, size) in regions {
+            if offset > covered {
+                unused.push((StreamOffset(covered), offset - covered));
+            }
+            covered = covered.max(offset + size);
+        }
+        Ok(unused)
+    }
 }
 
+// Only implemented for the built-in locks, as those never move or replace the storage.
+macro_rules! impl_file_slice {
+    ($($lock:ty),*) => {$(
+        impl<B: AsBytes> Pk2<B, $lock> {
+            /// Returns the data of the file at `path` as a slice of the in-memory storage of the
+            /// archive, without copying it.
+            ///
+            /// Returns `None` if there is no file at `path` or its data lies outside of the
+            /// storage.
+            pub fn file_slice<P: AsRef<Path>>(&self, path: P) -> Option<&[u8]> {
+                let file = self.open_file(path).ok()?;
+                let start = usize::try_from(file.pos_data().0).ok()?;
+                let range = start..start.checked_add(file.size() as usize)?;
+                let (ptr, len) = self.stream.with_lock(|stream| {
+                    stream.as_bytes().get(range).map(|data| (data.as_ptr(), data.len()))
+                })?;
+                // SAFETY: The storage is only written to through `&mut self`, the lock never
+                // moves or replaces it and `AsBytes` guarantees that reading from it leaves its
+                // bytes in place. So the bytes stay valid and unchanged while `self` is borrowed.
+                Some(unsafe { std::slice::from_raw_parts(ptr, len) })
+            }
+        }
+    )*};
+}
+
+impl_file_slice!(crate::sync::Lock, crate::unsync::Lock);
+
 impl<B, L> Pk2<B, L>
 where
-    B: io::Read + io::Write + io::Seek,
+    B: Backing + BackingMut,
     L: LockChoice,
 {
-    pub fn open_file_mut<P: AsRef<Path>>(&mut self, path: P) -> ChainLookupResult<FileMut<B, L>> {
-        let (chain, entry_idx, entry) = self.root_resolve_path_to_entry_and_parent(path)?;
+    /// Sets whether file data should be written into space freed by deleted or relocated files
+    /// instead of always being appended to the end of the archive.
+    ///
+    /// Only space freed while the archive is open is tracked. This is disabled by default as some
+    /// archives contain multiple entries sharing the same data, which would get overwritten.
+    pub fn set_reuse_free_space(&mut self, reuse: bool) {
+        self.reuse_free_space = reuse;
+    }
+
+    /// Sets whether dropping a [`FileMut`] panics if writing its pending changes fails.
+    ///
+    /// By default such errors are silently discarded, use [`FileMut::flush_drop`] to handle them
+    /// instead.
+    pub fn set_panic_on_drop_flush_error(&mut self, panic: bool) {
+        self.panic_on_drop_flush_error = panic;
+    }
+
+    pub fn open_file_mut<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> ChainLookupResult<FileMut<'_, B, L>> {
+        let path = check_root(path.as_ref())?;
+        self.open_file_mut_in(PK2_ROOT_BLOCK, &path)
+    }
+
+    /// Opens the file at `path` for writing, relative to the directory whose entries are stored in
+    /// `chain`, or relative to the root directory if `path` is absolute.
+    fn open_file_mut_in(
+        &mut self,
+        chain: ChainIndex,
+        path: &Path,
+    ) -> ChainLookupResult<FileMut<'_, B, L>> {
+        self.check_writable()?;
+        let (chain, path) = normalize_at(chain, path)?;
+        if path.as_os_str().is_empty() {
+            return Err(ChainLookupError::IsADirectory);
+        }
+        let (chain, entry_idx, entry) =
+            self.block_manager.resolve_path_to_entry_and_parent(chain, &path)?;
         Self::is_file(entry)?;
         Ok(FileMut::new(self, chain, entry_idx))
     }
@@ -259,25 +1327,542 @@ where
     /// Currently only replaces the entry with an empty one making the data
     /// inaccessible by normal means
     pub fn delete_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.check_writable()?;
+        let path = path.as_ref();
         let (chain_index, entry_idx, entry) = self
+            .root_resolve_path_to_entry_and_parent(path)
+            .map_err(|e| self.path_lookup_error(path, e))?;
+        Self::is_file(entry).map_err(|e| self.path_lookup_error(path, e))?;
+        self.delete_entry(chain_index, entry_idx)
+    }
+
+    /// Replaces the entry with an empty one, freeing the data of files.
+    fn delete_entry(&mut self, chain: ChainIndex, entry_idx: usize) -> io::Result<()> {
+        if let Some(DirectoryOrFile::File { pos_data, size }) =
+            self.get_entry_mut(chain, entry_idx).unwrap().clear().entry.map(|it| it.kind)
+        {
+            self.free_list.free(pos_data, size);
+        }
+
+        self.write_chain_entry(chain, entry_idx)
+    }
+
+    /// Moves the entries of the directory at `path` to the front of its blocks, collapsing the
+    /// empty slots left behind by deleted entries.
+    ///
+    /// The order of the entries, including the leading `.` and `..` entries, is kept. Only the
+    /// blocks of the directory are rewritten, file data is left untouched.
+    pub fn compact_dir<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.check_writable()?;
+        let path = path.as_ref();
+        let chain_index = self
             .block_manager
-            .resolve_path_to_entry_and_parent_mut(PK2_ROOT_BLOCK, check_root(path.as_ref())?)?;
-        Self::is_file(entry)?;
-        entry.clear();
+            .resolve_path_to_block_chain_index_at(PK2_ROOT_BLOCK, &check_root(path)?)
+            .map_err(|e| self.path_lookup_error(path, e))?;
+        let codec = self.block_manager.name_codec().clone();
+        let chain = self.block_manager.get_mut(chain_index).unwrap();
+        let changed = chain.compact();
+        self.stream.with_lock(|stream| {
+            changed.into_iter().try_for_each(|block| {
+                let (offset, block) = &chain.blocks()[block];
+                crate::io::write_block(self.blowfish.as_deref(), stream, *offset, block, &codec)
+            })
+        })
+    }
+
+    /// Sets the access, create and modify time of the file at `path`.
+    ///
+    /// Only the file's entry is written back, its data is left untouched which makes this a lot
+    /// cheaper than setting the times through a [`FileMut`].
+    pub fn set_file_times<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        access: SystemTime,
+        create: SystemTime,
+        modify: SystemTime,
+    ) -> io::Result<()> {
+        self.check_writable()?;
+        let path = path.as_ref();
+        let (chain, entry_idx, entry) = self
+            .root_resolve_path_to_entry_and_parent(path)
+            .map_err(|e| self.path_lookup_error(path, e))?;
+        Self::is_file(entry).map_err(|e| self.path_lookup_error(path, e))?;
+        let entry =
+            self.get_entry_mut(chain, entry_idx).and_then(PackEntry::as_non_empty_mut).unwrap();
+        entry.access_time = access.into();
+        entry.create_time = create.into();
+        entry.modify_time = modify.into();
+        self.write_chain_entry(chain, entry_idx)
+    }
+
+    /// Replaces the file entry at `path` with the given raw entry bytes, see [`Pk2::raw_entry`]
+    /// for their layout.
+    ///
+    /// This allows surgical edits like fixing a corrupt data offset without going through the
+    /// typed API. The replacement has to be a file entry as well, and the next block offset stays
+    /// the one of the entry's slot. Its name has to follow the rules of [`Pk2::rename`], and its
+    /// data has to lie within the archive unless the file is empty, otherwise this fails with
+    /// [`io::ErrorKind::InvalidInput`].
+    pub fn set_raw_entry<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        raw: [u8; PK2_FILE_ENTRY_SIZE],
+    ) -> io::Result<()> {
+        self.check_writable()?;
+        let path = path.as_ref();
+        let (chain, entry_idx, entry) = self
+            .root_resolve_path_to_entry_and_parent(path)
+            .map_err(|e| self.path_lookup_error(path, e))?;
+        Self::is_file(entry).map_err(|e| self.path_lookup_error(path, e))?;
+        let mut new_entry = PackEntry::from_raw(&raw, self.block_manager.name_codec())?;
+        if !new_entry.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "raw entry has to be a file entry",
+            ));
+        }
+        let new_file = new_entry.as_non_empty().unwrap();
+        self.check_entry_name(chain, entry_idx, new_file.name()).map_err(|e| match e.kind() {
+            io::ErrorKind::AlreadyExists => {
+                io::Error::new(io::ErrorKind::InvalidInput, "raw entry name is already in use")
+            }
+            _ => io::Error::new(io::ErrorKind::InvalidInput, e),
+        })?;
+        if let DirectoryOrFile::File { pos_data: StreamOffset(pos), size } = new_file.kind {
+            let storage_size = self.stream.with_lock(|stream| stream.storage_size())?;
+            let in_range = pos >= PK2_HEADER_SIZE as u64
+                && pos.checked_add(size as u64).is_some_and(|end| end <= storage_size);
+            if size > 0 && !in_range {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "raw entry data lies outside of the archive",
+                ));
+            }
+        }
+        let entry = self.get_entry_mut(chain, entry_idx).unwrap();
+        entry.take_contents_from(&mut new_entry);
+        self.write_chain_entry(chain, entry_idx)
+    }
+
+    /// Exchanges the data of the files at `a` and `b`.
+    ///
+    /// Only the data position and size of the two entries are swapped and written back, the data
+    /// itself stays where it is, which makes this cheap regardless of the file sizes. Names and
+    /// file times stay with their entries. If writing either entry fails, both entries are restored
+    /// to their previous contents.
+    pub fn swap_files<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, a: P, b: Q) -> io::Result<()> {
+        self.check_writable()?;
+        let mut locations = [(PK2_ROOT_BLOCK, 0); 2];
+        let mut kinds = Vec::with_capacity(2);
+        for (location, path) in locations.iter_mut().zip([a.as_ref(), b.as_ref()]) {
+            let (chain, entry_idx, entry) = self
+                .root_resolve_path_to_entry_and_parent(path)
+                .map_err(|e| self.path_lookup_error(path, e))?;
+            Self::is_file(entry).map_err(|e| self.path_lookup_error(path, e))?;
+            *location = (chain, entry_idx);
+            kinds.push(entry.as_non_empty().unwrap().kind.clone());
+        }
+        let set_kinds = |this: &mut Self, kinds: Vec<DirectoryOrFile>| {
+            for (&(chain, entry_idx), kind) in locations.iter().zip(kinds) {
+                this.get_entry_mut(chain, entry_idx)
+                    .and_then(PackEntry::as_non_empty_mut)
+                    .unwrap()
+                    .kind = kind;
+            }
+            locations
+                .iter()
+                .try_for_each(|&(chain, entry_idx)| this.write_chain_entry(chain, entry_idx))
+        };
+        let swapped = kinds.iter().rev().cloned().collect();
+        set_kinds(self, swapped).inspect_err(|_| {
+            // undo the swap, including the entry that might have been written already
+            _ = set_kinds(self, kinds);
+        })
+    }
+
+    /// Shrinks the file at `path` to `new_size` bytes, cutting off the rest of its data.
+    ///
+    /// `new_size` may not be bigger than the current size of the file. The cut off bytes remain in
+    /// the archive, see [`Pk2::shrink_file_and_truncate`] to remove them for file backed archives.
+    pub fn shrink_file<P: AsRef<Path>>(&mut self, path: P, new_size: u32) -> io::Result<()> {
+        let (new_end, cut_len) = self.shrink_entry(path.as_ref(), new_size)?;
+        self.free_list.free(new_end, cut_len);
+        Ok(())
+    }
+
+    /// Sets the size of the file entry at `path` to `new_size`, returning the new end of its data
+    /// and the number of bytes that were cut off.
+    fn shrink_entry(&mut self, path: &Path, new_size: u32) -> io::Result<(StreamOffset, u32)> {
+        self.check_writable()?;
+        let (chain, entry_idx, entry) = self
+            .root_resolve_path_to_entry_and_parent(path)
+            .map_err(|e| self.path_lookup_error(path, e))?;
+        Self::is_file(entry).map_err(|e| self.path_lookup_error(path, e))?;
+        let Some(DirectoryOrFile::File { pos_data, size }) =
+            self.get_entry_mut(chain, entry_idx).unwrap().as_non_empty_mut().map(|it| &mut it.kind)
+        else {
+            unreachable!()
+        };
+        if new_size > *size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "new size is bigger than the current file size",
+            ));
+        }
+        let cut_len = *size - new_size;
+        *size = new_size;
+        let new_end = *pos_data + StreamOffset(new_size as u64);
+        self.write_chain_entry(chain, entry_idx)?;
+        Ok((new_end, cut_len))
+    }
+
+    /// Moves the data of files overlapping the data of other files to the end of the archive,
+    /// returning the number of files that were relocated.
+    ///
+    /// Of each group of overlapping files the one starting first is left in place, the others get
+    /// a copy of the bytes they currently read appended and their entry pointed at it. Entries
+    /// sharing the exact same data region are left alone, as some archives deduplicate identical
+    /// files that way. Data that can't be read because it lies past the end of the archive is not
+    /// recovered, such files are shortened to the part that could be read.
+    pub fn repair_overlaps(&mut self) -> io::Result<usize> {
+        use std::io::Read;
+
+        self.check_writable()?;
+        let mut files = Vec::new();
+        // the callback never fails, so neither does the traversal
+        let _ = self.open_root_dir().for_each_file(|_, file| {
+            if file.size() > 0 {
+                files.push((file.pos_data(), file.size(), file.location()));
+            }
+            Ok(())
+        });
+        // of files starting at the same offset, keep the biggest one in place
+        files.sort_unstable_by_key(|&(offset, size, _)| (offset, std::cmp::Reverse(size)));
+
+        let mut overlapping = Vec::new();
+        let mut kept = (StreamOffset(0), 0);
+        let mut end = 0;
+        for (offset, size, location) in files {
+            if offset.0 >= end {
+                kept = (offset, size);
+                end = offset.0 + size as u64;
+            } else if (offset, size) != kept {
+                overlapping.push(location);
+            }
+        }
+
+        let mut buf = Vec::new();
+        for &(chain, entry_idx) in &overlapping {
+            buf.clear();
+            File::new(self, chain, entry_idx).read_to_end(&mut buf)?;
+            let pos_data = match buf.is_empty() {
+                true => StreamOffset(0),
+                false => self.stream.with_lock(|stream| crate::io::append_data(stream, &buf))?,
+            };
+            let entry = self.get_entry_mut(chain, entry_idx).and_then(PackEntry::as_non_empty_mut);
+            entry.expect("file entry vanished").kind =
+                DirectoryOrFile::File { pos_data, size: buf.len() as u32 };
+            self.write_chain_entry(chain, entry_idx)?;
+        }
+        Ok(overlapping.len())
+    }
+
+    /// Renames the file or directory at `from` to the name of the last component of `to`.
+    ///
+    /// This is a pure rename, so both paths have to share the same parent directory. Renaming the
+    /// root directory or renaming an entry to the name of another existing entry fails.
+    pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, from: P, to: Q) -> io::Result<()> {
+        self.check_writable()?;
+        let (from, to) = (from.as_ref(), to.as_ref());
+        let (chain, entry_idx, entry) = self
+            .root_resolve_path_to_entry_and_parent(from)
+            .map_err(|e| self.path_lookup_error(from, e))?;
+        if !entry.as_non_empty().is_some_and(|it| it.is_normal_link()) {
+            return Err(ChainLookupError::InvalidPath.into());
+        }
+        let relative_to = &check_root(to)?;
+        let (to_parent, to_name) = self
+            .block_manager
+            .resolve_path_to_parent(PK2_ROOT_BLOCK, relative_to)
+            .map_err(|e| self.path_lookup_error(to, e))?;
+        if to_parent != chain {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "rename target has to be a name in the same directory",
+            ));
+        }
+        self.rename_entry(chain, entry_idx, to_name)
+    }
+
+    /// Checks that `name` is a valid name for the entry at `entry_idx` of `chain`, that is a single
+    /// path component that fits the name field and isn't used by any other entry of the directory.
+    fn check_entry_name(&self, chain: ChainIndex, entry_idx: usize, name: &str) -> io::Result<()> {
+        self.check_name(name)?;
+        let case = self.block_manager.case_sensitivity();
+        let collides = self
+            .get_chain(chain)
+            .unwrap()
+            .entries()
+            .enumerate()
+            .any(|(idx, entry)| idx != entry_idx && entry.name_eq(name, case));
+        match collides {
+            true => Err(io::ErrorKind::AlreadyExists.into()),
+            false => Ok(()),
+        }
+    }
+
+    /// Checks that `name` is a single path component that fits the name field.
+    fn check_name(&self, name: &str) -> io::Result<()> {
+        let mut components = Path::new(name).components();
+        let single_component =
+            matches!((components.next(), components.next()), (Some(Component::Normal(_)), None));
+        if !single_component || name.contains(['/', '\\']) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "entry name has to be a single path component",
+            ));
+        }
+        check_name_len(name, self.block_manager.name_encoding()).map_err(Into::into)
+    }
 
+    /// Writes the entry at `entry_idx` of `chain` back to the archive.
+    fn write_chain_entry(&self, chain: ChainIndex, entry_idx: usize) -> io::Result<()> {
         self.stream.with_lock(|stream| {
             crate::io::write_chain_entry(
                 self.blowfish.as_deref(),
                 stream,
-                self.get_chain(chain_index).unwrap(),
+                self.get_chain(chain).unwrap(),
                 entry_idx,
+                self.block_manager.name_codec(),
             )
-        })?;
+        })
+    }
+
+    /// Renames the entry, making sure the new name is a valid and unique name in its directory.
+    fn rename_entry(
+        &mut self,
+        chain: ChainIndex,
+        entry_idx: usize,
+        to_name: &str,
+    ) -> io::Result<()> {
+        self.check_entry_name(chain, entry_idx, to_name)?;
+        self.set_entry_name(chain, entry_idx, to_name)
+    }
+
+    /// Renames the entry without checking the new name.
+    fn set_entry_name(
+        &mut self,
+        chain: ChainIndex,
+        entry_idx: usize,
+        to_name: &str,
+    ) -> io::Result<()> {
+        self.get_entry_mut(chain, entry_idx)
+            .and_then(PackEntry::as_non_empty_mut)
+            .unwrap()
+            .set_name(to_name);
+        self.write_chain_entry(chain, entry_idx)
+    }
+
+    /// Visits every file and directory in the archive, applying the [`WalkAction`] returned by
+    /// `visitor` for each entry once all entries have been visited.
+    ///
+    /// Deleting a directory unlinks it, making all of its contents unreachable. Renames follow the
+    /// rules of [`Pk2::rename`], but are checked against the names the directory has after all
+    /// actions have been applied, so entries can swap names. All actions are validated before
+    /// any of them is applied, so an invalid action leaves the archive untouched.
+    pub fn walk_mut(
+        &mut self,
+        mut visitor: impl FnMut(&Path, DirEntry<'_, B, L>) -> WalkAction,
+    ) -> io::Result<()> {
+        self.check_writable()?;
+        let mut actions = Vec::new();
+        let mut stack = vec![(PathBuf::from("/"), self.open_root_dir())];
+        while let Some((path, dir)) = stack.pop() {
+            for entry in dir.entries() {
+                let path = path.join(entry.name());
+                let location = entry.location();
+                if let DirEntry::Directory(dir) = entry {
+                    stack.push((path.clone(), dir));
+                }
+                match visitor(&path, entry) {
+                    WalkAction::Keep => (),
+                    action => actions.push((location, action)),
+                }
+            }
+        }
+        self.check_walk_actions(&actions)?;
+        for ((chain, entry_idx), action) in actions {
+            match action {
+                WalkAction::Keep => (),
+                WalkAction::Delete => self.delete_entry(chain, entry_idx)?,
+                WalkAction::Rename(name) => self.set_entry_name(chain, entry_idx, &name)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that applying `actions` gives every renamed entry a valid name that is unique in
+    /// its directory.
+    fn check_walk_actions(&self, actions: &[((ChainIndex, usize), WalkAction)]) -> io::Result<()> {
+        let mut by_chain = HashMap::<_, HashMap<_, _>>::new();
+        for &((chain, entry_idx), ref action) in actions {
+            by_chain.entry(chain).or_default().insert(entry_idx, action);
+        }
+        let case = self.block_manager.case_sensitivity();
+        let key = |name: &str| match case {
+            CaseSensitivity::Insensitive => name.to_ascii_lowercase(),
+            CaseSensitivity::Sensitive => name.to_owned(),
+        };
+        for (chain, actions) in by_chain {
+            let mut names = HashMap::<_, usize>::new();
+            let mut renamed = Vec::new();
+            for (idx, entry) in self.get_chain(chain).unwrap().entries().enumerate() {
+                let Some(entry) = entry.as_non_empty() else { continue };
+                let name = match actions.get(&idx) {
+                    Some(WalkAction::Delete) => continue,
+                    Some(WalkAction::Rename(name)) => {
+                        self.check_name(name)?;
+                        renamed.push(key(name));
+                        name
+                    }
+                    _ => entry.name(),
+                };
+                *names.entry(key(name)).or_default() += 1;
+            }
+            if renamed.iter().any(|name| names[name] > 1) {
+                return Err(io::ErrorKind::AlreadyExists.into());
+            }
+        }
         Ok(())
     }
 
-    pub fn create_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<FileMut<B, L>> {
+    /// Copies the file or directory at `src_path` in `src` to `dst_path` in this archive,
+    /// including all files in its subdirectories.
+    ///
+    /// The file times are preserved. Copying fails if any of the files already exists in this
+    /// archive, files copied up to that point are kept. Empty directories are not copied.
+    pub fn copy_from<B2: Backing, L2: LockChoice>(
+        &mut self,
+        src: &Pk2<B2, L2>,
+        src_path: impl AsRef<Path>,
+        dst_path: impl AsRef<Path>,
+    ) -> io::Result<()> {
+        self.check_writable()?;
+        let (src_path, dst_path) = (src_path.as_ref(), dst_path.as_ref());
+        let mut buf = Vec::new();
+        match src.open_directory(src_path) {
+            Ok(dir) => dir.for_each_file(|path, file| {
+                self.copy_file_from(file, &dst_path.join(path), &mut buf)
+            }),
+            Err(ChainLookupError::IsAFile) => {
+                let file =
+                    src.open_file(src_path).map_err(|e| src.path_lookup_error(src_path, e))?;
+                self.copy_file_from(file, dst_path, &mut buf)
+            }
+            Err(e) => Err(src.path_lookup_error(src_path, e).into()),
+        }
+    }
+
+    fn copy_file_from<B2: Backing, L2: LockChoice>(
+        &mut self,
+        mut file: File<'_, B2, L2>,
+        dst_path: &Path,
+        buf: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        use std::io::{Read, Write};
+
+        buf.clear();
+        file.read_to_end(buf)?;
+        let mut dst = self.create_file(dst_path)?;
+        dst.copy_file_times(&file);
+        dst.write_all(buf)?;
+        dst.flush_drop()
+    }
+
+    pub fn create_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<FileMut<'_, B, L>> {
         let path = check_root(path.as_ref())?;
+        self.create_file_in(PK2_ROOT_BLOCK, &path)
+    }
+
+    /// Creates a file at `path` with the contents read from `src`, returning the number of bytes
+    /// written.
+    ///
+    /// Unlike the [`FileMut`] returned by [`Pk2::create_file`], which keeps the whole file in
+    /// memory until it is flushed, the data is streamed to the end of the archive in fixed-size
+    /// chunks, so memory use stays bounded regardless of the file size. If reading from `src`
+    /// fails or the data exceeds the maximum file size of `u32::MAX` bytes, the file is removed
+    /// again and the data appended so far is freed for reuse.
+    pub fn write_file_from<P: AsRef<Path>, R: io::Read>(
+        &mut self,
+        path: P,
+        mut src: R,
+    ) -> io::Result<u64> {
+        const CHUNK_SIZE: usize = 64 * 1024;
+        let (chain, entry_idx) = self.create_file(path)?.location();
+        let mut buf = vec![0; CHUNK_SIZE];
+        let mut start = None;
+        let mut len = 0;
+        let res = loop {
+            let n = match src.read(&mut buf) {
+                Ok(0) => break Ok(()),
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => break Err(e),
+            };
+            if len + n as u64 > u32::MAX as u64 {
+                break Err(io::Error::new(
+                    io::ErrorKind::FileTooLarge,
+                    "files can not be larger than u32::MAX bytes",
+                ));
+            }
+            let offset =
+                match self.stream.with_lock(|stream| crate::io::append_data(stream, &buf[..n])) {
+                    Ok(offset) => offset,
+                    Err(e) => break Err(e),
+                };
+            let StreamOffset(start) = *start.get_or_insert(offset);
+            if offset.0 != start + len {
+                self.free_list.free(offset, n as u32);
+                break Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "appended data does not directly follow the previous chunk",
+                ));
+            }
+            len += n as u64;
+        };
+        if let Err(e) = res {
+            if let Some(start) = start {
+                self.free_list.free(start, len as u32);
+            }
+            // the original error is more useful than one from removing the file again
+            let _ = self.delete_entry(chain, entry_idx);
+            return Err(e);
+        }
+        let entry = self.get_entry_mut(chain, entry_idx).and_then(PackEntry::as_non_empty_mut);
+        let entry = entry.expect("created file entry vanished");
+        entry.kind =
+            DirectoryOrFile::File { pos_data: start.unwrap_or(StreamOffset(0)), size: len as u32 };
+        entry.modify_time = FILETIME::now();
+        self.write_chain_entry(chain, entry_idx)?;
+        Ok(len)
+    }
+
+    /// Creates a file at `path` relative to the directory whose entries are stored in `chain`, or
+    /// relative to the root directory if `path` is absolute.
+    fn create_file_in(&mut self, chain: ChainIndex, path: &Path) -> io::Result<FileMut<'_, B, L>> {
+        self.check_writable()?;
+        let path = normalize(path)?;
+        let mut chain = if path.absolute { PK2_ROOT_BLOCK } else { chain };
+        for _ in 0..path.parents {
+            let case = self.block_manager.case_sensitivity();
+            chain = self
+                .get_chain(chain)
+                .ok_or(ChainLookupError::InvalidChainIndex)?
+                .find_block_chain_index_of(PK2_PARENT_DIR_IDENT, case)
+                // the root directory has no parent
+                .map_err(|_| ChainLookupError::InvalidPath)?;
+        }
+        let path = Path::new(&path.path);
         let file_name = path
             .file_name()
             .and_then(std::ffi::OsStr::to_str)
@@ -287,27 +1872,77 @@ where
                 &mut self.block_manager,
                 self.blowfish.as_deref(),
                 stream,
-                PK2_ROOT_BLOCK,
+                chain,
                 path,
             )
         })?;
         let entry = self.get_entry_mut(chain, entry_idx).unwrap();
         *entry = PackEntry::new_file(file_name, StreamOffset(0), 0, entry.next_block());
+        // write the entry right away so that files that never get any data written persist
+        self.write_chain_entry(chain, entry_idx)?;
         Ok(FileMut::new(self, chain, entry_idx))
     }
 
+    /// Creates the directory at `path` together with all of its missing parent directories.
+    ///
+    /// Does nothing if the directory already exists.
+    pub fn create_dir_all<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.check_writable()?;
+        let path = path.as_ref();
+        let relative = &check_root(path)?;
+        match self.block_manager.resolve_path_to_block_chain_index_at(PK2_ROOT_BLOCK, relative) {
+            Ok(_) => return Ok(()),
+            Err(ChainLookupError::NotFound) => (),
+            Err(e) => return Err(self.path_lookup_error(path, e).into()),
+        }
+        let dir_name = relative
+            .file_name()
+            .and_then(std::ffi::OsStr::to_str)
+            .ok_or(ChainLookupError::InvalidPath)?;
+        self.stream.with_lock(|stream| {
+            let blowfish = self.blowfish.as_deref();
+            let (chain, entry_idx) = Self::create_entry_at(
+                &mut self.block_manager,
+                blowfish,
+                stream,
+                PK2_ROOT_BLOCK,
+                relative,
+            )?;
+            let codec = self.block_manager.name_codec().clone();
+            let current_chain = self.block_manager.get_mut(chain).unwrap();
+            let new_chain = crate::io::allocate_new_block_chain(
+                blowfish,
+                stream,
+                current_chain,
+                dir_name,
+                &codec,
+                entry_idx,
+            )?;
+            self.block_manager.insert(new_chain.chain_index(), new_chain);
+            Ok(())
+        })
+    }
+
     /// This function traverses the whole path creating anything that does not
-    /// yet exist returning the last created entry. This means using parent and
-    /// current dir parts in a path that in the end directs to an already
-    /// existing path might still create new directories that arent actually being used.
+    /// yet exist returning the last created entry. The path has to be normalized already.
     fn create_entry_at(
         block_manager: &mut BlockManager,
         blowfish: Option<&Blowfish>,
-        mut stream: &mut B,
+        stream: &mut B,
         chain: ChainIndex,
         path: &Path,
     ) -> io::Result<(ChainIndex, usize)> {
         use crate::io::{allocate_empty_block, allocate_new_block_chain, write_chain_entry};
+        if path.as_os_str().len() > PK2_MAX_PATH_LEN {
+            return Err(ChainLookupError::PathTooLong.into());
+        }
+        let codec = block_manager.name_codec().clone();
+        for component in path.components() {
+            if let Component::Normal(name) = component {
+                let name = name.to_str().ok_or(ChainLookupError::InvalidPath)?;
+                check_name_len(name, codec.encoding)?;
+            }
+        }
         let (mut current_chain_index, mut components) = block_manager
             .validate_dir_path_until(chain, path)?
             .ok_or_else(|| io::Error::from(io::ErrorKind::AlreadyExists))?;
@@ -322,15 +1957,11 @@ where
                         idx
                     } else {
                         // current chain is full so create a new block and append it
-                        let (offset, block) = allocate_empty_block(blowfish, &mut stream)?;
+                        let (offset, block) = allocate_empty_block(blowfish, stream)?;
                         let chain_entry_idx = current_chain.num_entries();
                         current_chain.push_and_link(offset, block);
-                        write_chain_entry(
-                            blowfish,
-                            &mut stream,
-                            current_chain,
-                            chain_entry_idx - 1,
-                        )?;
+                        let link_idx = chain_entry_idx - 1;
+                        write_chain_entry(blowfish, stream, current_chain, link_idx, &codec)?;
                         chain_entry_idx
                     };
                     // Are we done after this? if not, create a new blockchain since this is a new
@@ -339,9 +1970,10 @@ where
                         let dir_name = p.to_str().ok_or(ChainLookupError::InvalidPath)?;
                         let block_chain = allocate_new_block_chain(
                             blowfish,
-                            &mut stream,
+                            stream,
                             current_chain,
                             dir_name,
+                            &codec,
                             chain_entry_idx,
                         )?;
                         current_chain_index = block_chain.chain_index();
@@ -350,13 +1982,6 @@ where
                         return Ok((current_chain.chain_index(), chain_entry_idx));
                     }
                 }
-                Component::ParentDir => {
-                    current_chain_index = block_manager
-                        .get_mut(current_chain_index)
-                        .ok_or(ChainLookupError::InvalidChainIndex)
-                        .and_then(|entry| entry.find_block_chain_index_of(PK2_PARENT_DIR_IDENT))?
-                }
-                Component::CurDir => (),
                 _ => unreachable!(),
             }
         }
@@ -364,20 +1989,1694 @@ where
     }
 }
 
-fn check_root(path: &Path) -> ChainLookupResult<&Path> {
-    path.strip_prefix("/").map_err(|_| ChainLookupError::InvalidPath)
+/// Normalizes an absolute archive path, returning it relative to the root directory.
+fn check_root(path: &Path) -> ChainLookupResult<PathBuf> {
+    match normalize(path)? {
+        NormalizedPath { absolute: true, path, .. } => Ok(PathBuf::from(path)),
+        _ => Err(ChainLookupError::InvalidPath),
+    }
 }
 
-// #[cfg(test)]
-// mod test {
-//     use std::io;
-//     #[test]
-//     fn create_already_existing() {
-//         let mut archive = super::Pk2::create_new_in_memory("").unwrap();
-//         archive.create_file("/test/foo.baz").unwrap();
-//         match archive.create_file("/test/foo.baz") {
-//             Err(e) => assert_eq!(e.kind(), io::ErrorKind::AlreadyExists),
-//             Ok(_) => panic!("file was created twice?"),
-//         };
-//     }
-// }
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use crate::unsync::Pk2;
+    use crate::{OpenOptions, ParseWarning};
+
+    /// Opens the archive in `data`, returning the warnings found while parsing its file table.
+    fn open_collecting_warnings(data: &[u8]) -> (Pk2<io::Cursor<&[u8]>>, Vec<ParseWarning>) {
+        let mut warnings = Vec::new();
+        let options = OpenOptions::new().warnings(&mut warnings);
+        let archive = Pk2::open_in_with(io::Cursor::new(data), "", options).unwrap();
+        (archive, warnings)
+    }
+
+    #[test]
+    fn create_already_existing() {
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/test/foo.baz").unwrap();
+        match archive.create_file("/test/foo.baz") {
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::AlreadyExists),
+            Ok(_) => panic!("file was created twice?"),
+        };
+    }
+
+    #[test]
+    fn open_in_borrowed_file() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/test/foo.baz").unwrap().write_all(b"foo").unwrap();
+        let path = std::env::temp_dir().join("pk2_open_in_borrowed_file.pk2");
+        std::fs::write(&path, Vec::from(archive)).unwrap();
+
+        let file = std::fs::File::open(&path).unwrap();
+        let archive = Pk2::<&std::fs::File>::open_in(&file, "").unwrap();
+        assert_eq!(archive.read("/test/foo.baz").unwrap(), b"foo");
+        let archive =
+            crate::unsync::readonly::Pk2::<&std::fs::File>::open_in(crate::ReadOnly(&file), "")
+                .unwrap();
+        assert_eq!(archive.read("/test/foo.baz").unwrap(), b"foo");
+        drop(file);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn case_sensitive_lookup() {
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/Test/Foo.baz").unwrap();
+        assert!(archive.open_file("/test/foo.baz").is_ok());
+
+        archive.set_case_sensitivity(crate::CaseSensitivity::Sensitive);
+        assert!(archive.open_file("/Test/Foo.baz").is_ok());
+        assert_eq!(
+            archive.open_file("/test/Foo.baz").err(),
+            Some(crate::ChainLookupError::NotFound)
+        );
+        assert_eq!(
+            archive.open_file("/Test/foo.baz").err(),
+            Some(crate::ChainLookupError::NotFound)
+        );
+    }
+
+    #[test]
+    fn find_duplicate_entries() {
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/test/foo.baz").unwrap();
+        archive.create_file("/test/bar.baz").unwrap();
+        archive.set_case_sensitivity(crate::CaseSensitivity::Sensitive);
+        archive.create_file("/test/FOO.baz").unwrap();
+        archive.create_file("/Test/foo.baz").unwrap();
+
+        let mut duplicates = archive.find_duplicate_entries();
+        duplicates.sort();
+        assert_eq!(
+            duplicates,
+            [
+                ("/".to_owned(), vec!["test".to_owned(), "Test".to_owned()]),
+                ("/test".to_owned(), vec!["foo.baz".to_owned(), "FOO.baz".to_owned()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn reuse_free_space() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.set_reuse_free_space(true);
+        archive.create_file("/foo").unwrap().write_all(&[1; 10]).unwrap();
+        archive.create_file("/bar").unwrap().write_all(&[2; 10]).unwrap();
+        archive.delete_file("/foo").unwrap();
+        archive.create_file("/baz").unwrap().write_all(&[3; 5]).unwrap();
+        archive.create_file("/qux").unwrap().write_all(&[4; 5]).unwrap();
+
+        assert_eq!(archive.read("/bar").unwrap(), [2; 10]);
+        assert_eq!(archive.read("/baz").unwrap(), [3; 5]);
+        assert_eq!(archive.read("/qux").unwrap(), [4; 5]);
+        let len = Vec::from(archive).len();
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/bar").unwrap().write_all(&[2; 10]).unwrap();
+        archive.create_file("/baz").unwrap().write_all(&[3; 10]).unwrap();
+        assert_eq!(len, Vec::from(archive).len());
+    }
+
+    #[test]
+    fn repair_overlaps() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/a").unwrap().write_all(&[1; 100]).unwrap();
+        archive.create_file("/b").unwrap().write_all(&[2; 10]).unwrap();
+        archive.create_file("/c").unwrap().write_all(&[3; 10]).unwrap();
+        archive.create_file("/shared").unwrap().write_all(&[4; 10]).unwrap();
+        // point b into the middle of a and shared at the exact data of c
+        let a = archive.raw_entry("/a").unwrap();
+        let mut raw = archive.raw_entry("/b").unwrap();
+        let offset = u64::from_le_bytes(a[106..114].try_into().unwrap()) + 50;
+        raw[106..114].copy_from_slice(&offset.to_le_bytes());
+        archive.set_raw_entry("/b", raw).unwrap();
+        let mut raw = archive.raw_entry("/shared").unwrap();
+        raw[106..114].copy_from_slice(&archive.raw_entry("/c").unwrap()[106..114]);
+        archive.set_raw_entry("/shared", raw).unwrap();
+
+        assert_eq!(archive.repair_overlaps().unwrap(), 1);
+        assert_eq!(archive.read("/a").unwrap(), [1; 100]);
+        assert_eq!(archive.read("/b").unwrap(), [1; 10]);
+        assert_eq!(archive.read("/shared").unwrap(), [3; 10]);
+        // writing to b no longer clobbers a
+        archive.open_file_mut("/b").unwrap().write_all(&[5; 10]).unwrap();
+        assert_eq!(archive.read("/a").unwrap(), [1; 100]);
+        assert_eq!(archive.repair_overlaps().unwrap(), 0);
+    }
+
+    #[test]
+    fn block_occupancy() {
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        for i in 0..30 {
+            archive.create_file(format!("/dir/{i}")).unwrap();
+        }
+        let mut occupancy = archive.block_occupancy().map(|(_, n)| n).collect::<Vec<_>>();
+        occupancy.sort_unstable();
+        // the root holds `.` and `dir`, the directory holds `.`, `..` and the 30 files
+        assert_eq!(occupancy, [2, 12, 20]);
+    }
+
+    #[test]
+    fn iter_files_with_offset() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/foo").unwrap().write_all(&[1; 10]).unwrap();
+        archive.create_file("/dir/bar").unwrap().write_all(&[2; 20]).unwrap();
+
+        let mut files = archive.iter_files_with_offset();
+        files.sort_by_key(|&(_, offset, _)| offset);
+        let [(foo, foo_offset, 10), (bar, bar_offset, 20)] = &files[..] else {
+            panic!("unexpected files {:?}", files);
+        };
+        assert_eq!(foo, std::path::Path::new("/foo"));
+        assert_eq!(bar, std::path::Path::new("/dir/bar"));
+        assert!(foo_offset.0 + 10 <= bar_offset.0);
+    }
+
+    #[test]
+    fn open_with_cipher() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("169841").unwrap();
+        archive.create_file("/foo").unwrap().write_all(b"foo").unwrap();
+        let data = Vec::from(archive);
+
+        let cipher = crate::Blowfish::new(b"169841").unwrap();
+        let archive = Pk2::open_in_with_cipher(io::Cursor::new(&data[..]), &cipher).unwrap();
+        assert_eq!(archive.read("/foo").unwrap(), b"foo");
+        let cipher = crate::Blowfish::new(b"wrong key").unwrap();
+        assert!(matches!(
+            Pk2::open_in_with_cipher(io::Cursor::new(&data[..]), &cipher),
+            Err(crate::OpenError::InvalidKey)
+        ));
+    }
+
+    #[test]
+    fn backslash_separators() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("\\data\\a.txt").unwrap().write_all(b"a").unwrap();
+        assert_eq!(archive.read("/data/a.txt").unwrap(), b"a");
+        assert_eq!(archive.read("\\data/a.txt").unwrap(), b"a");
+        let dir = archive.open_directory("\\data\\").unwrap();
+        assert_eq!(dir.name(), "data");
+        assert!(dir.open_file("..\\data\\a.txt").is_ok());
+        assert_eq!(super::normalize_path("data\\.\\a.txt").unwrap(), "/data/a.txt");
+        assert_eq!(super::normalize_path("//").unwrap(), "/");
+    }
+
+    struct FailingRead;
+    impl io::Read for FailingRead {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::Other.into())
+        }
+    }
+
+    #[test]
+    fn write_file_from() {
+        let data = (0..200_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let mut archive = Pk2::create_new_in_memory("169841").unwrap();
+        assert_eq!(archive.write_file_from("/dir/big.bin", &data[..]).unwrap(), 200_000);
+        assert_eq!(archive.write_file_from("/empty.bin", io::empty()).unwrap(), 0);
+        assert!(archive.write_file_from("/dir/big.bin", &data[..]).is_err());
+
+        let failing = io::Read::chain(&data[..100_000], FailingRead);
+        assert!(archive.write_file_from("/dir/failed.bin", failing).is_err());
+        assert!(archive.open_file("/dir/failed.bin").is_err());
+        assert!(archive.free_list.allocate(100_000).is_some());
+
+        let archive = Pk2::open_in(io::Cursor::new(Vec::from(archive)), "169841").unwrap();
+        assert_eq!(archive.read("/dir/big.bin").unwrap(), data);
+        assert_eq!(archive.read("/empty.bin").unwrap(), b"");
+    }
+
+    #[test]
+    fn truncate_archive() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("169841").unwrap();
+        archive.create_file("/dir/a.txt").unwrap().write_all(b"abc").unwrap();
+        archive.truncate_archive().unwrap();
+        assert!(archive.open_file("/dir/a.txt").is_err());
+        assert_eq!(archive.open_root_dir().entries().count(), 0);
+        archive.create_file("/b.txt").unwrap().write_all(b"b").unwrap();
+
+        let empty = Vec::from(Pk2::create_new_in_memory("169841").unwrap());
+        let data = Vec::from(archive);
+        let header = crate::constants::PK2_HEADER_SIZE;
+        assert_eq!(data[..header], empty[..header]);
+        assert_eq!(data.len(), empty.len() + 1);
+        let archive = Pk2::open_in(io::Cursor::new(data), "169841").unwrap();
+        assert_eq!(archive.read("/b.txt").unwrap(), b"b");
+        assert_eq!(archive.open_root_dir().entries().count(), 1);
+    }
+
+    #[test]
+    fn open_with_warnings() {
+        use crate::constants::{PK2_FILE_ENTRY_SIZE, PK2_ROOT_BLOCK};
+        use crate::{BlockOffset, ParseWarning};
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_dir_all("/a").unwrap();
+        archive.create_dir_all("/b").unwrap();
+        archive.create_file("/c").unwrap();
+        let (a, b, c) = (
+            archive.raw_entry("/a").unwrap(),
+            archive.raw_entry("/b").unwrap(),
+            archive.raw_entry("/c").unwrap(),
+        );
+        let mut data = Vec::from(archive);
+        let (_, warnings) = open_collecting_warnings(&data);
+        assert_eq!(warnings, []);
+
+        let find = |data: &[u8], raw: &[u8]| {
+            data.windows(PK2_FILE_ENTRY_SIZE).position(|window| window == raw).unwrap()
+        };
+        // point /b at the children of /a
+        let b_offset = find(&data, &b);
+        data[b_offset + 106..b_offset + 114].copy_from_slice(&a[106..114]);
+        // give /c a name that is invalid EUC-KR or UTF-8
+        let c_offset = find(&data, &c);
+        data[c_offset + 1] = 0xFF;
+        // link the root block to itself
+        let root = PK2_ROOT_BLOCK.0 as usize;
+        let last_entry = root + 19 * PK2_FILE_ENTRY_SIZE;
+        data[last_entry + 118..last_entry + 126].copy_from_slice(&(root as u64).to_le_bytes());
+
+        let (_, warnings) = open_collecting_warnings(&data);
+        let a_children = u64::from_le_bytes(a[106..114].try_into().unwrap());
+        assert!(warnings.contains(&ParseWarning::SharedChain(crate::ChainIndex(a_children))));
+        assert!(warnings.contains(&ParseWarning::BlockCycle(BlockOffset(root as u64))));
+        assert!(warnings.contains(&ParseWarning::InvalidName {
+            block: BlockOffset(root as u64),
+            entry: (c_offset - root) / PK2_FILE_ENTRY_SIZE,
+        }));
+        assert_eq!(warnings.len(), 3);
+    }
+
+    #[test]
+    fn block_cache() {
+        use std::io::Write;
+
+        use crate::BlockCache;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/a.txt").unwrap().write_all(&[1; 3000]).unwrap();
+        let data = Vec::from(archive);
+
+        let mut archive = crate::unsync::Pk2::<BlockCache<io::Cursor<Vec<u8>>>>::open_in(
+            BlockCache::new(io::Cursor::new(data), 2),
+            "",
+        )
+        .unwrap();
+        assert_eq!(archive.read("/a.txt").unwrap(), [1; 3000]);
+        let misses = archive.cache_stats().misses;
+        assert_eq!(archive.read("/a.txt").unwrap(), [1; 3000]);
+        assert_eq!(archive.cache_stats().misses, misses);
+        assert!(archive.cache_stats().hits > 0);
+
+        archive.open_file_mut("/a.txt").unwrap().write_all(&[2; 10]).unwrap();
+        let contents = archive.read("/a.txt").unwrap();
+        assert_eq!(contents.len(), 3000);
+        assert!(contents[..10].iter().all(|&b| b == 2));
+        assert!(contents[10..].iter().all(|&b| b == 1));
+    }
+
+    #[test]
+    fn verify_all_files() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/a.txt").unwrap().write_all(b"abc").unwrap();
+        archive.create_file("/b.txt").unwrap().write_all(&[1; 64]).unwrap();
+        assert!(archive.verify_all_files().is_empty());
+        let (_, offset, _) = archive
+            .iter_files_with_offset()
+            .into_iter()
+            .find(|(path, _, _)| path == std::path::Path::new("/b.txt"))
+            .unwrap();
+        let mut data = Vec::from(archive);
+        data.truncate(offset.0 as usize + 10);
+        let archive = Pk2::open_in(io::Cursor::new(data), "").unwrap();
+        let failures = archive.verify_all_files();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, std::path::Path::new("/b.txt"));
+        assert_eq!(failures[0].1.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn read_dir() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/dir/a.txt").unwrap().write_all(b"abc").unwrap();
+        archive.create_dir_all("/dir/sub").unwrap();
+        let entries = archive.read_dir("/dir").unwrap();
+        drop(archive);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            (entries[0].name.as_str(), entries[0].is_dir, entries[0].size),
+            ("a.txt", false, Some(3))
+        );
+        assert_eq!(
+            (entries[1].name.as_str(), entries[1].is_dir, entries[1].size),
+            ("sub", true, None)
+        );
+    }
+
+    #[test]
+    fn open_missing_key() {
+        let encrypted = Vec::from(Pk2::create_new_in_memory("169841").unwrap());
+        assert!(matches!(
+            Pk2::open_in(io::Cursor::new(&encrypted[..]), ""),
+            Err(crate::OpenError::MissingKey)
+        ));
+        assert!(matches!(
+            Pk2::open_in(io::Cursor::new(&encrypted[..]), "foobar"),
+            Err(crate::OpenError::InvalidKey)
+        ));
+        let unencrypted = Vec::from(Pk2::create_new_in_memory("").unwrap());
+        assert!(Pk2::open_in(io::Cursor::new(&unencrypted[..]), "foobar").is_ok());
+    }
+
+    #[test]
+    fn open_invalid_entry_offset() {
+        use std::io::Write;
+
+        use crate::constants::{PK2_FILE_ENTRY_SIZE, PK2_ROOT_BLOCK};
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/foo").unwrap().write_all(b"foo").unwrap();
+        archive.create_dir_all("/dir").unwrap();
+        let data = Vec::from(archive);
+        // byte-swaps the position field of the root's entry at `entry`
+        let swap_position = |entry: usize| {
+            let mut data = data.clone();
+            let position = PK2_ROOT_BLOCK.0 as usize + entry * PK2_FILE_ENTRY_SIZE + 106;
+            let value = u64::from_le_bytes(data[position..][..8].try_into().unwrap());
+            data[position..][..8].copy_from_slice(&value.to_be_bytes());
+            data
+        };
+
+        // bad file data only affects the file
+        let data = swap_position(1);
+        let (archive, warnings) = open_collecting_warnings(&data);
+        assert_eq!(
+            warnings,
+            [crate::ParseWarning::InvalidDataOffset { block: crate::BlockOffset(256), entry: 1 }]
+        );
+        assert!(archive.verify_all_files().len() == 1);
+
+        assert!(matches!(
+            Pk2::open_in(io::Cursor::new(swap_position(2)), ""),
+            Err(crate::OpenError::InvalidEntryOffset { block: crate::BlockOffset(256), entry: 2 })
+        ));
+    }
+
+    #[test]
+    fn file_slice() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("169841").unwrap();
+        archive.create_file("/foo.txt").unwrap().write_all(b"foo").unwrap();
+        archive.create_file("/dir/empty").unwrap();
+        assert_eq!(archive.file_slice("/foo.txt"), Some(&b"foo"[..]));
+        assert_eq!(archive.file_slice("/dir/empty"), Some(&[][..]));
+        assert_eq!(archive.file_slice("/dir"), None);
+        assert_eq!(archive.file_slice("/missing"), None);
+
+        let bytes = Vec::from(archive);
+        let archive =
+            crate::sync::Pk2::open_in(crate::ReadOnly(io::Cursor::new(&bytes[..])), "169841")
+                .unwrap();
+        assert_eq!(archive.file_slice("/foo.txt"), Some(&b"foo"[..]));
+    }
+
+    #[test]
+    fn path_cache() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.set_path_cache(true);
+        archive.create_file("/dir/sub/foo").unwrap().write_all(b"foo").unwrap();
+        assert_eq!(archive.read("/dir/sub/foo").unwrap(), b"foo");
+        assert_eq!(archive.read("/DIR/sub/foo").unwrap(), b"foo");
+
+        archive.rename("/dir/sub", "/dir/moved").unwrap();
+        assert!(archive.read("/dir/sub/foo").is_err());
+        archive.create_file("/dir/sub/bar").unwrap().write_all(b"bar").unwrap();
+        assert!(archive.read("/dir/sub/foo").is_err());
+        assert_eq!(archive.read("/dir/sub/bar").unwrap(), b"bar");
+        assert_eq!(archive.read("/dir/moved/foo").unwrap(), b"foo");
+
+        archive.set_case_sensitivity(crate::CaseSensitivity::Sensitive);
+        assert!(archive.read("/DIR/sub/bar").is_err());
+        archive.truncate_archive().unwrap();
+        assert!(archive.read("/dir/sub/bar").is_err());
+    }
+
+    #[test]
+    fn open_padded_block_table() {
+        use std::io::Write;
+
+        for key in ["", "169841"] {
+            let mut archive = Pk2::create_new_in_memory(key).unwrap();
+            archive.create_file("/foo").unwrap().write_all(b"foo").unwrap();
+            let mut data = Vec::from(archive);
+            data.splice(
+                crate::constants::PK2_HEADER_SIZE..crate::constants::PK2_HEADER_SIZE,
+                [0; 256],
+            );
+            assert!(matches!(
+                Pk2::open_in(io::Cursor::new(&data[..]), key),
+                Err(crate::OpenError::InvalidRootBlock)
+            ));
+        }
+    }
+
+    #[test]
+    fn open_lenient_truncated_block() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("169841").unwrap();
+        archive.create_file("/foo").unwrap().write_all(b"foo").unwrap();
+        archive.create_file("/dir/bar").unwrap().write_all(b"bar").unwrap();
+        assert_eq!(archive.truncated_blocks().count(), 0);
+        let mut data = Vec::from(archive);
+
+        // cut off the last block of the directory, leaving its `.` and `..` entries intact
+        let dir_block = data.len() - 3 - crate::constants::PK2_FILE_BLOCK_SIZE;
+        data.truncate(dir_block + 2 * crate::constants::PK2_FILE_ENTRY_SIZE + 10);
+        assert!(Pk2::open_in(io::Cursor::new(&data[..]), "169841").is_err());
+
+        let archive = Pk2::open_in_lenient(io::Cursor::new(&data[..]), "169841").unwrap();
+        assert_eq!(
+            archive.truncated_blocks().collect::<Vec<_>>(),
+            [crate::BlockOffset(dir_block as u64)]
+        );
+        assert_eq!(archive.read("/foo").unwrap(), b"foo");
+        assert!(archive.open_directory("/dir").is_ok());
+        assert!(archive.open_file("/dir/bar").is_err());
+    }
+
+    #[test]
+    fn runtime_read_only() {
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/foo").unwrap();
+        archive.set_read_only(true);
+        assert_eq!(archive.open_file_mut("/foo").err(), Some(crate::ChainLookupError::ReadOnly));
+        assert_eq!(
+            archive.create_file("/bar").err().map(|e| e.kind()),
+            Some(io::ErrorKind::PermissionDenied)
+        );
+        assert_eq!(
+            archive.delete_file("/foo").err().map(|e| e.kind()),
+            Some(io::ErrorKind::PermissionDenied)
+        );
+        assert!(archive.open_file("/foo").is_ok());
+        archive.set_read_only(false);
+        assert!(archive.open_file_mut("/foo").is_ok());
+    }
+
+    #[test]
+    fn rename() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/dir/foo").unwrap().write_all(b"foo").unwrap();
+        archive.create_file("/dir/bar").unwrap();
+        archive.create_file("/other").unwrap();
+
+        archive.rename("/dir/foo", "/dir/baz").unwrap();
+        assert_eq!(archive.read("/dir/baz").unwrap(), b"foo");
+        assert!(archive.open_file("/dir/foo").is_err());
+        archive.rename("/dir", "/renamed").unwrap();
+        assert_eq!(archive.read("/renamed/baz").unwrap(), b"foo");
+        // changing the case of the same entry is fine
+        archive.rename("/renamed/baz", "/renamed/BAZ").unwrap();
+        assert_eq!(archive.open_file("/renamed/baz").unwrap().name(), "BAZ");
+
+        let kind = |res: io::Result<()>| res.unwrap_err().kind();
+        assert_eq!(
+            kind(archive.rename("/renamed/baz", "/renamed/bar")),
+            io::ErrorKind::AlreadyExists
+        );
+        assert_eq!(kind(archive.rename("/renamed/baz", "/baz")), io::ErrorKind::InvalidInput);
+        assert_eq!(kind(archive.rename("/", "/root")), io::ErrorKind::InvalidInput);
+        assert_eq!(kind(archive.rename("/missing", "/found")), io::ErrorKind::NotFound);
+
+        let data = Vec::from(archive);
+        let archive = Pk2::open_in(io::Cursor::new(data), "").unwrap();
+        assert_eq!(archive.read("/renamed/BAZ").unwrap(), b"foo");
+    }
+
+    #[test]
+    fn lookup_error_reports_component() {
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/test/foo.baz").unwrap();
+
+        let err = archive.read("/test/bar/foo.baz").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let err = err.get_ref().unwrap().downcast_ref::<crate::PathLookupError>().unwrap();
+        assert_eq!(err.component(), Some("bar"));
+
+        let err = archive.delete_file("/test/bar.baz").unwrap_err();
+        let err = err.get_ref().unwrap().downcast_ref::<crate::PathLookupError>().unwrap();
+        assert_eq!(err.component(), Some("bar.baz"));
+    }
+
+    #[test]
+    fn precise_lookup_errors() {
+        use crate::ChainLookupError;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/test/foo.baz").unwrap();
+        assert_eq!(archive.open_file("/test").err(), Some(ChainLookupError::IsADirectory));
+        assert_eq!(archive.open_directory("/test/foo.baz").err(), Some(ChainLookupError::IsAFile));
+        assert_eq!(archive.open_file("/test/foo.baz/bar").err(), Some(ChainLookupError::IsAFile));
+        assert_eq!(archive.open_file("/test/bar.baz").err(), Some(ChainLookupError::NotFound));
+
+        let long_name = format!("/test/{}", "a".repeat(82));
+        let err = archive.create_file(long_name).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let long_path = format!("/{}", ["a"; 200].join("/"));
+        let err = archive.create_file(long_path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(archive.open_directory("/a").is_err());
+    }
+
+    #[test]
+    fn walk_mut() {
+        use crate::WalkAction;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/test/foo.bak").unwrap();
+        archive.create_file("/test/foo.baz").unwrap();
+        archive.create_file("/bar.bak").unwrap();
+        archive.create_file("/old/bar.baz").unwrap();
+        archive
+            .walk_mut(|path, _| match path.extension() {
+                Some(ext) if ext == "bak" => WalkAction::Delete,
+                _ if path == std::path::Path::new("/old") => WalkAction::Rename("new".to_owned()),
+                _ => WalkAction::Keep,
+            })
+            .unwrap();
+        assert!(archive.open_file("/test/foo.bak").is_err());
+        assert!(archive.open_file("/bar.bak").is_err());
+        assert!(archive.open_file("/test/foo.baz").is_ok());
+        assert!(archive.open_file("/new/bar.baz").is_ok());
+        assert!(archive.open_directory("/old").is_err());
+
+        archive.create_file("/a.txt").unwrap();
+        archive.create_file("/b.txt").unwrap();
+        let rename = |path: &std::path::Path, to: &str| match path.to_str() {
+            Some("/a.txt") => WalkAction::Rename("b.txt".to_owned()),
+            Some("/b.txt") => WalkAction::Rename(to.to_owned()),
+            Some("/test/foo.baz") => WalkAction::Delete,
+            _ => WalkAction::Keep,
+        };
+        let err = archive.walk_mut(|path, _| rename(path, "B.TXT")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(archive.walk_mut(|path, _| rename(path, "c/d.txt")).is_err());
+        assert!(archive.open_file("/test/foo.baz").is_ok());
+        assert!(archive.open_file("/a.txt").is_ok());
+        archive.walk_mut(|path, _| rename(path, "a.txt")).unwrap();
+        assert!(archive.open_file("/a.txt").is_ok());
+        assert!(archive.open_file("/b.txt").is_ok());
+        assert!(archive.open_file("/test/foo.baz").is_err());
+    }
+
+    #[test]
+    fn verify_key() {
+        let encrypted = Vec::from(Pk2::create_new_in_memory("169841").unwrap());
+        assert!(Pk2::verify_key(&encrypted[..], "169841").unwrap());
+        assert!(!Pk2::verify_key(&encrypted[..], "foobar").unwrap());
+        assert!(Pk2::verify_key(&encrypted[..], "").is_err());
+        let unencrypted = Vec::from(Pk2::create_new_in_memory("").unwrap());
+        assert!(Pk2::verify_key(&unencrypted[..], "foobar").unwrap());
+        assert!(Pk2::verify_key(&unencrypted[..100], "foobar").is_err());
+    }
+
+    #[test]
+    fn copy_from() {
+        use std::io::Write;
+        use std::time::{Duration, SystemTime};
+
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let mut src = Pk2::create_new_in_memory("").unwrap();
+        let mut file = src.create_file("/data/foo.txt").unwrap();
+        file.set_modify_time(time);
+        file.write_all(b"foo").unwrap();
+        drop(file);
+        src.create_file("/data/sub/bar.txt").unwrap().write_all(b"bar").unwrap();
+
+        let mut dst = crate::sync::Pk2::create_new_in_memory("169841").unwrap();
+        dst.copy_from(&src, "/data", "/merged").unwrap();
+        dst.copy_from(&src, "/data/foo.txt", "/single.txt").unwrap();
+        assert_eq!(dst.read("/merged/foo.txt").unwrap(), b"foo");
+        assert_eq!(dst.read("/merged/sub/bar.txt").unwrap(), b"bar");
+        assert_eq!(dst.read("/single.txt").unwrap(), b"foo");
+        assert_eq!(dst.open_file("/merged/foo.txt").unwrap().modify_time(), Some(time));
+
+        let err = dst.copy_from(&src, "/data/foo.txt", "/single.txt").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        let err = dst.copy_from(&src, "/missing", "/other").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn index_cache() {
+        use std::io::Write;
+
+        let path = std::env::temp_dir().join(format!("pk2_index_cache-{}.pk2", std::process::id()));
+        let sidecar = super::index_sidecar_path(&path);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&sidecar);
+        let mut archive = Pk2::create_new(&path, "").unwrap();
+        archive.create_file("/test/foo.baz").unwrap().write_all(b"foo").unwrap();
+        drop(archive);
+
+        let cache = Pk2::open(&path, "").unwrap().index_cache().unwrap();
+        let archive = Pk2::open_with_index_cache(&path, "", &cache).unwrap();
+        assert_eq!(archive.read("/test/foo.baz").unwrap(), b"foo");
+        drop(archive);
+
+        // a cache pointing a directory outside of the archive has to be rejected like the parser
+        // would reject the file table
+        let mut crafted = cache.clone();
+        let name = crafted.windows(5).position(|it| it == b"test\0").unwrap();
+        crafted[name - 1 + 106..][..8].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+        let archive = Pk2::open_with_index_cache(&path, "", &crafted).unwrap();
+        assert_eq!(archive.read("/test/foo.baz").unwrap(), b"foo");
+        drop(archive);
+
+        // an unreadable sidecar is ignored
+        std::fs::create_dir(&sidecar).unwrap();
+        let mut archive = Pk2::open(&path, "").unwrap();
+        std::fs::remove_dir(&sidecar).unwrap();
+        assert_eq!(archive.read("/test/foo.baz").unwrap(), b"foo");
+
+        // the file changed, so the stale cache has to be ignored
+        archive.create_file("/test/bar.baz").unwrap().write_all(b"bar").unwrap();
+        drop(archive);
+        let archive = Pk2::open_with_index_cache(&path, "", &cache).unwrap();
+        assert_eq!(archive.read("/test/bar.baz").unwrap(), b"bar");
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&sidecar);
+    }
+
+    #[test]
+    fn index_cache_name_encoding() {
+        use std::io::Write;
+
+        use crate::NameEncoding;
+
+        let path = std::env::temp_dir()
+            .join(format!("pk2_index_cache_name_encoding-{}.pk2", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let archive = Pk2::create_new(&path, "").unwrap();
+        drop(archive);
+        let options = || OpenOptions::new().name_encoding(NameEncoding::Utf8).index_sidecar(false);
+        let mut archive = Pk2::open_with(&path, "", options()).unwrap();
+        archive.create_file("/dir/한글.txt").unwrap().write_all(b"utf8").unwrap();
+        drop(archive);
+
+        let cache = Pk2::open_with(&path, "", options()).unwrap().index_cache().unwrap();
+        let archive = Pk2::open_with(&path, "", options().index_cache(&cache)).unwrap();
+        assert_eq!(archive.read("/dir/한글.txt").unwrap(), b"utf8");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn index_sidecar() {
+        use std::io::{Read, Seek, SeekFrom, Write};
+
+        let path =
+            std::env::temp_dir().join(format!("pk2_index_sidecar-{}.pk2", std::process::id()));
+        let sidecar = super::index_sidecar_path(&path);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&sidecar);
+        let mut archive = Pk2::create_new(&path, "").unwrap();
+        archive.create_file("/test/foo.baz").unwrap().write_all(b"foo").unwrap();
+        archive.write_index_sidecar(&path).unwrap();
+        drop(archive);
+
+        // wipe the root block, keeping the modification time so the sidecar stays valid
+        let mut file = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        let modified = file.metadata().unwrap().modified().unwrap();
+        let mut root_block = [0; crate::constants::PK2_FILE_BLOCK_SIZE];
+        file.seek(SeekFrom::Start(crate::constants::PK2_ROOT_BLOCK.0)).unwrap();
+        file.read_exact(&mut root_block).unwrap();
+        file.seek(SeekFrom::Start(crate::constants::PK2_ROOT_BLOCK.0)).unwrap();
+        file.write_all(&[0; crate::constants::PK2_FILE_BLOCK_SIZE]).unwrap();
+        file.set_modified(modified).unwrap();
+        let archive = Pk2::open(&path, "").unwrap();
+        assert_eq!(archive.read("/test/foo.baz").unwrap(), b"foo");
+        drop(archive);
+        file.seek(SeekFrom::Start(crate::constants::PK2_ROOT_BLOCK.0)).unwrap();
+        file.write_all(&root_block).unwrap();
+        drop(file);
+
+        // the archive changed, so the stale sidecar has to be ignored and rewritten
+        let mut archive = Pk2::open(&path, "").unwrap();
+        archive.create_file("/test/bar.baz").unwrap().write_all(b"bar").unwrap();
+        drop(archive);
+        let archive = Pk2::open(&path, "").unwrap();
+        assert_eq!(archive.read("/test/bar.baz").unwrap(), b"bar");
+        assert_eq!(std::fs::read(&sidecar).unwrap(), archive.index_cache().unwrap());
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&sidecar);
+    }
+
+    #[test]
+    fn index_sidecar_name_options() {
+        use std::io::Write;
+
+        use crate::{NameEncoding, NameTransform};
+
+        let xor = |field: &mut [u8]| field.iter_mut().for_each(|b| *b ^= 0x5A);
+        let transform = NameTransform::new(xor, xor);
+
+        let path = std::env::temp_dir()
+            .join(format!("pk2_index_sidecar_name_options-{}.pk2", std::process::id()));
+        let sidecar = super::index_sidecar_path(&path);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&sidecar);
+        drop(Pk2::create_new(&path, "").unwrap());
+        let options = || {
+            OpenOptions::new().name_encoding(NameEncoding::Utf8).name_transform(transform.clone())
+        };
+        let mut archive = Pk2::open_with(&path, "", options()).unwrap();
+        archive.create_file("/dir/한글.txt").unwrap().write_all(b"hangul").unwrap();
+        archive.write_index_sidecar(&path).unwrap();
+        drop(archive);
+
+        let archive = Pk2::open_with(&path, "", options()).unwrap();
+        assert_eq!(archive.read("/dir/한글.txt").unwrap(), b"hangul");
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&sidecar);
+    }
+
+    #[test]
+    fn open_with_progress() {
+        let path = std::env::temp_dir().join("pk2_open_with_progress.pk2");
+        let _ = std::fs::remove_file(&path);
+        let mut archive = Pk2::create_new(&path, "").unwrap();
+        for i in 0..30 {
+            archive.create_file(format!("/dir{}/foo{}.baz", i % 3, i)).unwrap();
+        }
+        drop(archive);
+
+        let mut reported = Vec::new();
+        let archive = Pk2::open_with_progress(&path, "", |blocks| reported.push(blocks)).unwrap();
+        assert!(reported.windows(2).all(|it| it[0] < it[1]));
+        assert_eq!(reported.last().copied(), Some(archive.block_offsets().count()));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn sync_archive_is_shared_across_threads() {
+        use std::io::Write;
+        use std::sync::Arc;
+
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<crate::sync::Pk2>();
+        assert_send_sync::<crate::sync::Pk2<io::Cursor<Vec<u8>>>>();
+        assert_send_sync::<crate::sync::File<'static>>();
+        assert_send_sync::<crate::sync::Directory<'static>>();
+
+        let mut archive = crate::sync::Pk2::create_new_in_memory("").unwrap();
+        for i in 0..8 {
+            archive.create_file(format!("/{}.txt", i)).unwrap().write_all(&[i; 1000]).unwrap();
+        }
+        let archive = Arc::new(archive);
+        let threads = (0..8)
+            .map(|i| {
+                let archive = archive.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        assert_eq!(archive.read(format!("/{}.txt", i)).unwrap(), [i; 1000]);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn unused_regions() {
+        use std::io::Write;
+
+        use crate::data::StreamOffset;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        assert_eq!(archive.unused_regions().unwrap(), []);
+        archive.create_file("/foo.txt").unwrap().write_all(b"hello world").unwrap();
+        archive.create_file("/bar.txt").unwrap().write_all(b"bar").unwrap();
+        let foo = archive.open_file("/foo.txt").unwrap().pos_data();
+        archive.shrink_file("/foo.txt", 5).unwrap();
+        archive.delete_file("/bar.txt").unwrap();
+        assert_eq!(archive.unused_regions().unwrap(), [(foo + StreamOffset(5), 9)]);
+    }
+
+    #[test]
+    fn create_dir_all() {
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/file.txt").unwrap();
+        archive.create_dir_all("/data/sub").unwrap();
+        archive.create_dir_all("/data/sub").unwrap();
+        archive.create_dir_all("/music").unwrap();
+        archive.create_dir_all("/").unwrap();
+        assert!(archive.create_dir_all("/file.txt/sub").is_err());
+        assert!(archive.create_dir_all("data").is_err());
+
+        let archive = Pk2::open_in(io::Cursor::new(Vec::from(archive)), "").unwrap();
+        assert_eq!(archive.open_directory("/data/sub").unwrap().entries().count(), 0);
+        assert!(archive.open_directory("/music").is_ok());
+    }
+
+    #[test]
+    fn create_new_with_dirs() {
+        let path = std::env::temp_dir().join("pk2_create_new_with_dirs.pk2");
+        let _ = std::fs::remove_file(&path);
+        let archive = Pk2::create_new_with_dirs(&path, "", &["/data", "/music/ost"]).unwrap();
+        assert!(archive.open_directory("/music/ost").is_ok());
+        drop(archive);
+        assert!(Pk2::open(&path, "").unwrap().open_directory("/data").is_ok());
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(Pk2::create_new_with_dirs(&path, "", &["/data", "invalid"]).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn open_reusing() {
+        use std::io::Write;
+
+        let mut first = Pk2::create_new_in_memory("").unwrap();
+        first.create_file("/first/foo.txt").unwrap().write_all(b"foo").unwrap();
+        let mut second = Pk2::create_new_in_memory("").unwrap();
+        second.create_file("/second/bar.txt").unwrap().write_all(b"bar").unwrap();
+
+        let first = Pk2::open_in(io::Cursor::new(Vec::from(first)), "").unwrap();
+        let options = OpenOptions::new().reuse(first);
+        let second = Pk2::open_in_with(io::Cursor::new(Vec::from(second)), "", options).unwrap();
+        assert_eq!(second.read("/second/bar.txt").unwrap(), b"bar");
+        assert!(second.open_directory("/first").is_err());
+    }
+
+    #[test]
+    fn open_root_directory() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/a/b/foo.txt").unwrap().write_all(b"foo").unwrap();
+        for path in ["/", "/.", "//", "/./", "/a/..", "/a/b/../.."] {
+            let dir = archive.open_directory(path).unwrap();
+            assert_eq!(dir.name(), "/", "{}", path);
+            assert!(dir.open_file("a/b/foo.txt").is_ok(), "{}", path);
+        }
+        for path in ["/a/.", "/a/b/..", "/a/./b/.."] {
+            assert_eq!(archive.open_directory(path).unwrap().name(), "a", "{}", path);
+        }
+        let root = archive.open_root_dir();
+        assert!(
+            matches!(root.open("/."), Ok(crate::DirEntry::Directory(dir)) if dir.name() == "/")
+        );
+        assert!(
+            matches!(root.open("a/b/."), Ok(crate::DirEntry::Directory(dir)) if dir.name() == "b")
+        );
+        assert!(archive.open_directory("").is_err());
+        assert!(archive.open_directory("/..").is_err());
+    }
+
+    #[test]
+    fn compact_dir() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        for i in 0..30 {
+            archive.create_file(format!("/dir/{:02}.txt", i)).unwrap().write_all(b"a").unwrap();
+        }
+        for i in (0..30).filter(|i| i % 3 != 0) {
+            archive.delete_file(format!("/dir/{:02}.txt", i)).unwrap();
+        }
+        archive.compact_dir("/dir").unwrap();
+
+        let archive = Pk2::open_in(io::Cursor::new(Vec::from(archive)), "").unwrap();
+        let chain = archive
+            .block_manager
+            .resolve_path_to_block_chain_index_at(crate::constants::PK2_ROOT_BLOCK, "dir".as_ref())
+            .unwrap();
+        let chain = archive.get_chain(chain).unwrap();
+        let names = chain.entries().map(|it| it.name()).collect::<Vec<_>>();
+        let expected = [".", ".."]
+            .into_iter()
+            .map(str::to_owned)
+            .chain((0..30).step_by(3).map(|i| format!("{:02}.txt", i)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names[..expected.len()].iter().map(|it| it.unwrap()).collect::<Vec<_>>(),
+            expected
+        );
+        assert!(names[expected.len()..].iter().all(Option::is_none));
+        assert_eq!(archive.read("/dir/27.txt").unwrap(), b"a");
+    }
+
+    #[test]
+    fn to_bytes() {
+        use std::io::Write;
+
+        let path = std::env::temp_dir().join("pk2_to_bytes.pk2");
+        let _ = std::fs::remove_file(&path);
+        let mut archive = Pk2::create_new(&path, "").unwrap();
+        archive.create_file("/foo.txt").unwrap().write_all(b"foo").unwrap();
+        assert_eq!(archive.to_bytes().unwrap(), std::fs::read(&path).unwrap());
+        let _ = std::fs::remove_file(&path);
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/foo.txt").unwrap().write_all(b"foo").unwrap();
+        assert_eq!(archive.to_bytes().unwrap(), Vec::from(archive));
+    }
+
+    #[test]
+    fn open_rejects_other_block_layout() {
+        use crate::constants::{PK2_FILE_ENTRY_SIZE, PK2_ROOT_BLOCK};
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        // fill the root's first block so that it links to a second one
+        for i in 0..20 {
+            archive.create_file(format!("/{i}")).unwrap();
+        }
+        let second_block =
+            archive.block_offsets().map(|it| it.0 as usize).find(|&it| it != 256).unwrap();
+        let bytes = Vec::from(archive);
+        // link the 10th entry of a block to another block, as a 10 entry layout would
+        let link = |bytes: &mut Vec<u8>, block: usize| {
+            let next_block = block + 10 * PK2_FILE_ENTRY_SIZE - 10;
+            bytes[next_block..][..8].copy_from_slice(&(PK2_ROOT_BLOCK.0 + 1234).to_le_bytes());
+        };
+
+        // a single stray link is ignored
+        let mut stray = bytes.clone();
+        link(&mut stray, PK2_ROOT_BLOCK.0 as usize);
+        let (archive, warnings) = open_collecting_warnings(&stray);
+        assert_eq!(archive.open_root_dir().entries().count(), 20);
+        assert_eq!(
+            warnings,
+            [crate::ParseWarning::StrayNextBlock { block: crate::BlockOffset(256), entry: 9 }]
+        );
+
+        let mut systematic = bytes;
+        link(&mut systematic, PK2_ROOT_BLOCK.0 as usize);
+        link(&mut systematic, second_block);
+        assert!(matches!(
+            Pk2::open_in(io::Cursor::new(systematic), "").err(),
+            Some(crate::OpenError::UnsupportedBlockLayout)
+        ));
+    }
+
+    #[test]
+    fn set_file_times() {
+        use std::io::Write;
+        use std::time::{Duration, SystemTime};
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/foo.txt").unwrap().write_all(b"foo").unwrap();
+        let time = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        archive.set_file_times("/foo.txt", time(1), time(2), time(3)).unwrap();
+        assert!(archive.set_file_times("/bar.txt", time(1), time(2), time(3)).is_err());
+
+        let archive = Pk2::open_in(io::Cursor::new(Vec::from(archive)), "").unwrap();
+        let file = archive.open_file("/foo.txt").unwrap();
+        assert_eq!(file.access_time(), Some(time(1)));
+        assert_eq!(file.create_time(), Some(time(2)));
+        assert_eq!(file.modify_time(), Some(time(3)));
+        assert_eq!(archive.read("/foo.txt").unwrap(), b"foo");
+    }
+
+    #[test]
+    fn preserve_raw_entries() {
+        use std::io::Write;
+        use std::time::SystemTime;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/foo.txt").unwrap().write_all(b"foo").unwrap();
+        let mut raw = archive.raw_entry("/foo.txt").unwrap();
+        raw[crate::constants::PK2_FILE_ENTRY_SIZE - 2..].copy_from_slice(&[0xAB, 0xCD]);
+        archive.set_raw_entry("/foo.txt", raw).unwrap();
+        let bytes = Vec::from(archive);
+
+        let rewrite = |preserve| {
+            let options = OpenOptions::new().preserve_raw_entries(preserve);
+            let mut archive =
+                Pk2::open_in_with(io::Cursor::new(bytes.clone()), "", options).unwrap();
+            let now = SystemTime::now();
+            archive.set_file_times("/foo.txt", now, now, now).unwrap();
+            let options = OpenOptions::new().preserve_raw_entries(true);
+            let archive = Pk2::open_in_with(io::Cursor::new(Vec::from(archive)), "", options);
+            archive.unwrap().raw_entry("/foo.txt").unwrap()
+        };
+        assert_eq!(rewrite(true)[crate::constants::PK2_FILE_ENTRY_SIZE - 2..], [0xAB, 0xCD]);
+        assert_eq!(rewrite(false)[crate::constants::PK2_FILE_ENTRY_SIZE - 2..], [0, 0]);
+    }
+
+    #[test]
+    fn header_reserved() {
+        let mut bytes = Vec::from(Pk2::create_new_in_memory("169841").unwrap());
+        let archive = Pk2::open_in(io::Cursor::new(&bytes[..]), "169841").unwrap();
+        assert_eq!(archive.header_reserved(), [0; 205]);
+
+        bytes[51..59].copy_from_slice(b"v1.188\0\0");
+        let archive = Pk2::open_in(io::Cursor::new(&bytes[..]), "169841").unwrap();
+        assert_eq!(archive.header_reserved().len(), 205);
+        assert_eq!(&archive.header_reserved()[..6], b"v1.188");
+    }
+
+    #[test]
+    fn swap_files() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/a.txt").unwrap().write_all(b"aaaa").unwrap();
+        archive.create_file("/dir/b.txt").unwrap().write_all(b"bb").unwrap();
+        let len = archive.to_bytes().unwrap().len();
+        archive.swap_files("/a.txt", "/dir/b.txt").unwrap();
+        assert_eq!(archive.read("/a.txt").unwrap(), b"bb");
+        assert_eq!(archive.read("/dir/b.txt").unwrap(), b"aaaa");
+        assert_eq!(archive.to_bytes().unwrap().len(), len);
+        archive.swap_files("/a.txt", "/a.txt").unwrap();
+        assert_eq!(archive.read("/a.txt").unwrap(), b"bb");
+
+        let kind = |res: io::Result<()>| res.unwrap_err().kind();
+        assert_eq!(kind(archive.swap_files("/a.txt", "/dir")), io::ErrorKind::IsADirectory);
+        assert_eq!(kind(archive.swap_files("/missing", "/a.txt")), io::ErrorKind::NotFound);
+
+        let archive = Pk2::open_in(io::Cursor::new(Vec::from(archive)), "").unwrap();
+        assert_eq!(archive.read("/a.txt").unwrap(), b"bb");
+        assert_eq!(archive.read("/dir/b.txt").unwrap(), b"aaaa");
+    }
+
+    #[test]
+    fn shrink_file() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/foo.txt").unwrap().write_all(b"hello world").unwrap();
+        archive.shrink_file("/foo.txt", 5).unwrap();
+        assert_eq!(archive.read("/foo.txt").unwrap(), b"hello");
+        assert!(archive.shrink_file("/foo.txt", 6).is_err());
+        let archive = Pk2::open_in(io::Cursor::new(Vec::from(archive)), "").unwrap();
+        assert_eq!(archive.read("/foo.txt").unwrap(), b"hello");
+
+        let path = std::env::temp_dir().join("pk2_shrink_file.pk2");
+        let _ = std::fs::remove_file(&path);
+        let mut archive = Pk2::create_new(&path, "").unwrap();
+        archive.create_file("/foo.txt").unwrap().write_all(b"hello world").unwrap();
+        let len = std::fs::metadata(&path).unwrap().len();
+        archive.shrink_file_and_truncate("/foo.txt", 5).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len - 6);
+        drop(archive);
+        assert_eq!(
+            crate::unsync::Pk2::open(&path, "").unwrap().read("/foo.txt").unwrap(),
+            b"hello"
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn open_with_read_ahead() {
+        use std::io::Write;
+
+        /// Counts the reads passed through to the buffer.
+        struct Counting(io::Cursor<Vec<u8>>, usize);
+        impl crate::Backing for Counting {
+            fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+                self.1 += 1;
+                self.0.read_at(offset, buf)
+            }
+        }
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        for i in 0..20 {
+            archive.create_file(format!("/dir{i}/file")).unwrap().write_all(&[i; 100]).unwrap();
+        }
+        let data = Vec::from(archive);
+
+        let archive =
+            crate::unsync::Pk2::<Counting>::open_in(Counting(io::Cursor::new(data.clone()), 0), "")
+                .unwrap();
+        let block_reads = archive.stream.into_inner().1;
+        let mut warnings = Vec::new();
+        let archive = crate::unsync::Pk2::<Counting>::open_in_with(
+            Counting(io::Cursor::new(data.clone()), 0),
+            "",
+            OpenOptions::new().read_ahead(1 << 20).warnings(&mut warnings),
+        )
+        .unwrap();
+        // the header, the root block check and a single window covering the whole file table
+        assert_eq!(crate::Lock::with_lock(&archive.stream, |stream| stream.1), 3);
+        assert!(block_reads > archive.block_offsets().count());
+        assert_eq!(archive.read("/dir7/file").unwrap(), [7; 100]);
+        assert_eq!(warnings, []);
+
+        // huge windows are capped instead of failing to allocate
+        let options = OpenOptions::new().read_ahead(usize::MAX);
+        let archive = Pk2::open_in_with(io::Cursor::new(data), "", options).unwrap();
+        assert_eq!(archive.read("/dir7/file").unwrap(), [7; 100]);
+    }
+
+    #[test]
+    fn custom_backing() {
+        use std::io::Write;
+
+        /// Stores everything past `split` in a second buffer.
+        #[derive(Default)]
+        struct Split {
+            head: Vec<u8>,
+            tail: Vec<u8>,
+        }
+        impl Split {
+            const SPLIT: u64 = 4096;
+            fn part(&mut self, offset: u64) -> (&mut Vec<u8>, usize, usize) {
+                match offset.checked_sub(Self::SPLIT) {
+                    Some(offset) => (&mut self.tail, offset as usize, usize::MAX),
+                    None => (&mut self.head, offset as usize, (Self::SPLIT - offset) as usize),
+                }
+            }
+        }
+        impl crate::Backing for Split {
+            fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+                let (part, offset, max) = self.part(offset);
+                let data = part.get(offset..).unwrap_or_default();
+                let n = data.len().min(buf.len()).min(max);
+                buf[..n].copy_from_slice(&data[..n]);
+                Ok(n)
+            }
+        }
+        impl crate::BackingMut for Split {
+            fn write_all_at(&mut self, mut offset: u64, mut data: &[u8]) -> io::Result<()> {
+                while !data.is_empty() {
+                    let (part, start, max) = self.part(offset);
+                    let n = data.len().min(max);
+                    if part.len() < start + n {
+                        part.resize(start + n, 0);
+                    }
+                    part[start..start + n].copy_from_slice(&data[..n]);
+                    data = &data[n..];
+                    offset += n as u64;
+                }
+                Ok(())
+            }
+            fn end(&mut self) -> io::Result<u64> {
+                Ok(match self.tail.len() {
+                    0 => self.head.len() as u64,
+                    len => Self::SPLIT + len as u64,
+                })
+            }
+        }
+
+        let mut archive = crate::unsync::Pk2::<Split>::create_new_in(Split::default(), "").unwrap();
+        archive.create_file("/test/foo.baz").unwrap().write_all(&[1; 3000]).unwrap();
+        archive.create_file("/test/bar.baz").unwrap().write_all(&[2; 3000]).unwrap();
+        let split = archive.stream.into_inner();
+        assert!(!split.tail.is_empty());
+
+        let archive = crate::unsync::Pk2::<Split>::open_in(split, "").unwrap();
+        assert_eq!(archive.read("/test/foo.baz").unwrap(), [1; 3000]);
+        assert_eq!(archive.read("/test/bar.baz").unwrap(), [2; 3000]);
+    }
+
+    #[test]
+    fn write_past_limits() {
+        use std::io::{Seek, SeekFrom, Write};
+
+        /// Claims to end right before the largest representable offset.
+        struct Full(io::Cursor<Vec<u8>>);
+        impl crate::Backing for Full {
+            fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+                self.0.read_at(offset, buf)
+            }
+        }
+        impl crate::BackingMut for Full {
+            fn write_all_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+                self.0.write_all_at(offset, data)
+            }
+            fn end(&mut self) -> io::Result<u64> {
+                Ok(u64::MAX - 10)
+            }
+        }
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        let mut file = archive.create_file("/a.txt").unwrap();
+        file.seek(SeekFrom::Start(u32::MAX as u64)).unwrap();
+        assert_eq!(file.write(b"a").unwrap_err().kind(), io::ErrorKind::FileTooLarge);
+        drop(file);
+
+        let data = Vec::from(archive);
+        let mut archive =
+            crate::unsync::Pk2::<Full>::open_in(Full(io::Cursor::new(data)), "").unwrap();
+        let mut file = archive.create_file("/b.txt").unwrap();
+        file.write_all(&[0; 64]).unwrap();
+        assert_eq!(file.flush_drop().unwrap_err().kind(), io::ErrorKind::StorageFull);
+        let err = archive.create_dir_all("/dir").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+    }
+
+    #[test]
+    fn offsets_above_u32_max() {
+        use std::io::Write;
+
+        /// Places everything appended after `seal` past the 32-bit range without allocating the
+        /// gap in between.
+        #[derive(Default)]
+        struct Sparse {
+            head: Vec<u8>,
+            tail: Vec<u8>,
+            sealed: bool,
+        }
+        impl Sparse {
+            const BASE: u64 = 5 << 30;
+            fn part(&mut self, offset: u64) -> (&mut Vec<u8>, usize) {
+                match offset.checked_sub(Self::BASE) {
+                    Some(offset) => (&mut self.tail, offset as usize),
+                    None => (&mut self.head, offset as usize),
+                }
+            }
+        }
+        impl crate::Backing for Sparse {
+            fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+                let (part, offset) = self.part(offset);
+                let data = part.get(offset..).unwrap_or_default();
+                let n = data.len().min(buf.len());
+                buf[..n].copy_from_slice(&data[..n]);
+                Ok(n)
+            }
+        }
+        impl crate::BackingMut for Sparse {
+            fn write_all_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+                let (part, start) = self.part(offset);
+                if part.len() < start + data.len() {
+                    part.resize(start + data.len(), 0);
+                }
+                part[start..start + data.len()].copy_from_slice(data);
+                Ok(())
+            }
+            fn end(&mut self) -> io::Result<u64> {
+                Ok(match self.sealed {
+                    true => Self::BASE + self.tail.len() as u64,
+                    false => self.head.len() as u64,
+                })
+            }
+        }
+
+        let archive = crate::unsync::Pk2::<Sparse>::create_new_in(Sparse::default(), "").unwrap();
+        let mut sparse = archive.stream.into_inner();
+        sparse.sealed = true;
+
+        let mut archive = crate::unsync::Pk2::<Sparse>::open_in(sparse, "").unwrap();
+        archive.create_file("/high/foo.baz").unwrap().write_all(&[1; 3000]).unwrap();
+        archive.create_file("/high/bar.baz").unwrap().write_all(&[2; 3000]).unwrap();
+        let files = archive.iter_files_with_offset();
+        assert_eq!(files.len(), 2);
+        assert!(files.iter().all(|&(_, offset, _)| offset.0 > u64::from(u32::MAX)));
+        assert!(archive.block_offsets().any(|offset| offset.0 > u64::from(u32::MAX)));
+
+        let archive =
+            crate::unsync::Pk2::<Sparse>::open_in(archive.stream.into_inner(), "").unwrap();
+        assert_eq!(archive.read("/high/foo.baz").unwrap(), [1; 3000]);
+        let mut file = archive.open_file("/high/bar.baz").unwrap();
+        let mut buf = [0; 1000];
+        io::Seek::seek(&mut file, io::SeekFrom::Start(2500)).unwrap();
+        assert_eq!(io::Read::read(&mut file, &mut buf).unwrap(), 500);
+        assert_eq!(buf[..500], [2; 500]);
+    }
+
+    #[test]
+    fn create_file_resolves_dot_components() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/a/../b.txt").unwrap().write_all(b"b").unwrap();
+        assert_eq!(archive.read("/b.txt").unwrap(), b"b");
+        assert!(archive.open_directory("/a").is_err());
+
+        archive.create_file("/c/./d/../e.txt").unwrap().write_all(b"e").unwrap();
+        assert_eq!(archive.read("/c/e.txt").unwrap(), b"e");
+        assert!(archive.open_directory("/c/d").is_err());
+
+        let err = archive.create_file("/c/../b.txt").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(archive.create_file("/../b.txt").is_err());
+
+        archive.create_dir_all("/f/../g").unwrap();
+        assert!(archive.open_directory("/g").is_ok());
+        assert!(archive.open_directory("/f").is_err());
+    }
+
+    #[test]
+    fn paths_resolve_alike() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        let path = "\\a//b\\.\\c\\..\\d.txt";
+        archive.create_file(path).unwrap().write_all(b"d").unwrap();
+        assert_eq!(archive.read(path).unwrap(), b"d");
+        assert_eq!(archive.read("/a/b/d.txt").unwrap(), b"d");
+        assert!(archive.open_directory("/a/b/c").is_err());
+        assert!(archive.read("a/b/d.txt").is_err());
+        assert!(archive.create_file("e.txt").is_err());
+
+        let dir = archive.open_directory("/a//b/").unwrap();
+        assert!(dir.open_file("..\\b/./d.txt").is_ok());
+        assert!(dir.open_file(path).is_ok());
+        assert!(dir.open_directory("../../..").is_err());
+        assert_eq!(dir.open_directory("c/..").unwrap().name(), "b");
+        let mut dir = archive.open_directory_mut("/a").unwrap();
+        dir.create_file("..\\e.txt").unwrap().write_all(b"e").unwrap();
+        assert!(dir.open_file_mut("b\\d.txt").is_ok());
+        assert_eq!(archive.read("/e.txt").unwrap(), b"e");
+    }
+
+    #[test]
+    fn read_into() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/long.txt").unwrap().write_all(b"long contents").unwrap();
+        archive.create_file("/short.txt").unwrap().write_all(b"short").unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(archive.read_into("/long.txt", &mut buf).unwrap(), 13);
+        assert_eq!(buf, b"long contents");
+        assert_eq!(archive.read_into("/short.txt", &mut buf).unwrap(), 5);
+        assert_eq!(buf, b"short");
+        assert!(archive.read_into("/missing.txt", &mut buf).is_err());
+    }
+
+    #[test]
+    fn open_root_without_current_dir_entry() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/a/foo.txt").unwrap().write_all(b"foo").unwrap();
+        archive.create_file("/bar.txt").unwrap().write_all(b"bar").unwrap();
+        let mut bytes = Vec::from(archive);
+        // blank out the `.` entry at the start of the root block
+        let root = crate::constants::PK2_ROOT_BLOCK.0 as usize;
+        bytes[root..root + crate::constants::PK2_FILE_ENTRY_SIZE].fill(0);
+
+        let archive = Pk2::open_in(io::Cursor::new(bytes), "").unwrap();
+        let root = archive.open_directory("/").unwrap();
+        assert_eq!(root.entries().count(), 2);
+        assert_eq!(archive.read("/./bar.txt").unwrap(), b"bar");
+        assert_eq!(archive.read("/a/../a/foo.txt").unwrap(), b"foo");
+        assert_eq!(archive.open_directory("/.").unwrap().name(), "/");
+        assert_eq!(archive.open_directory("/a/..").unwrap().name(), "/");
+        assert_eq!(root.open_directory(".").unwrap().name(), "/");
+        assert!(matches!(root.open("./."), Ok(crate::DirEntry::Directory(_))));
+        assert!(root.open_file("./bar.txt").is_ok());
+        assert!(root.open_directory("a").unwrap().open_file("../bar.txt").is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "euc-kr")]
+    fn open_with_name_encoding() {
+        use std::io::Write;
+
+        use crate::NameEncoding;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/dir/a.txt").unwrap().write_all(b"a").unwrap();
+        archive.create_file("/dir/한.txt").unwrap().write_all(b"euc-kr").unwrap();
+        let bytes = Vec::from(archive);
+        assert!(bytes.windows(2).any(|it| it == b"\xc7\xd1"));
+
+        let mut archive =
+            Pk2::open_in_with_name_encoding(io::Cursor::new(bytes), "", NameEncoding::Utf8)
+                .unwrap();
+        assert_eq!(archive.name_encoding(), NameEncoding::Utf8);
+        archive.create_file("/dir/한국어.txt").unwrap().write_all(b"utf8").unwrap();
+        let bytes = Vec::from(archive);
+        assert!(bytes.windows("한국어".len()).any(|it| it == "한국어".as_bytes()));
+
+        let archive =
+            Pk2::open_in_with_name_encoding(io::Cursor::new(&bytes[..]), "", NameEncoding::Utf8)
+                .unwrap();
+        assert_eq!(archive.read("/dir/한국어.txt").unwrap(), b"utf8");
+        assert_eq!(archive.read("/dir/a.txt").unwrap(), b"a");
+        assert!(archive.read("/dir/한.txt").is_err());
+        let archive = Pk2::open_in(io::Cursor::new(&bytes[..]), "").unwrap();
+        assert_eq!(archive.read("/dir/한.txt").unwrap(), b"euc-kr");
+        assert!(archive.read("/dir/한국어.txt").is_err());
+    }
+
+    #[test]
+    fn open_with_name_transform() {
+        use std::io::Write;
+
+        use crate::NameTransform;
+
+        let key = 0x5A;
+        let xor = move |field: &mut [u8]| field.iter_mut().for_each(|b| *b ^= key);
+        let transform = NameTransform::new(xor, xor);
+
+        let bytes = Vec::from(Pk2::create_new_in_memory("").unwrap());
+        let options = OpenOptions::new().name_transform(transform.clone());
+        let mut archive = Pk2::open_in_with(io::Cursor::new(bytes), "", options).unwrap();
+        archive.create_file("/dir/scrambled.txt").unwrap().write_all(b"b").unwrap();
+        let bytes = Vec::from(archive);
+        assert!(!bytes.windows(13).any(|it| it == b"scrambled.txt"));
+
+        let options = OpenOptions::new().name_transform(transform);
+        let archive = Pk2::open_in_with(io::Cursor::new(&bytes[..]), "", options).unwrap();
+        assert_eq!(archive.read("/dir/scrambled.txt").unwrap(), b"b");
+        let archive = Pk2::open_in(io::Cursor::new(&bytes[..]), "").unwrap();
+        assert!(archive.open_directory("/dir").is_err());
+    }
+
+    #[test]
+    fn index_cache_name_transform() {
+        use std::io::Write;
+
+        use crate::NameTransform;
+
+        let key = 0x5A;
+        let xor = move |field: &mut [u8]| field.iter_mut().for_each(|b| *b ^= key);
+        let transform = NameTransform::new(xor, xor);
+
+        let path = std::env::temp_dir()
+            .join(format!("pk2_index_cache_name_transform-{}.pk2", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        drop(Pk2::create_new(&path, "").unwrap());
+        let options = || OpenOptions::new().name_transform(transform.clone()).index_sidecar(false);
+        let mut archive = Pk2::open_with(&path, "", options()).unwrap();
+        archive.create_file("/dir/scrambled.txt").unwrap().write_all(b"b").unwrap();
+        let cache = archive.index_cache().unwrap();
+        drop(archive);
+
+        let archive = Pk2::open_with(&path, "", options().index_cache(&cache)).unwrap();
+        assert_eq!(archive.read("/dir/scrambled.txt").unwrap(), b"b");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn raw_entry() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/dir/a.txt").unwrap().write_all(b"aaaa").unwrap();
+        archive.create_file("/dir/b.txt").unwrap().write_all(b"bb").unwrap();
+        let a = archive.raw_entry("/dir/a.txt").unwrap();
+        assert_eq!(a[0], 2);
+        assert_eq!(&a[1..6], b"a.txt");
+        assert_eq!(u32::from_le_bytes(a[114..118].try_into().unwrap()), 4);
+
+        // point b.txt at the data of a.txt
+        let mut b = archive.raw_entry("/dir/b.txt").unwrap();
+        b[106..118].copy_from_slice(&a[106..118]);
+        archive.set_raw_entry("/dir/b.txt", b).unwrap();
+        assert_eq!(archive.read("/dir/b.txt").unwrap(), b"aaaa");
+
+        let dir = archive.raw_entry("/dir").unwrap();
+        assert!(archive.set_raw_entry("/dir/b.txt", dir).is_err());
+        assert!(archive.set_raw_entry("/dir", a).is_err());
+
+        let invalid = |patch: &dyn Fn(&mut [u8; crate::constants::PK2_FILE_ENTRY_SIZE])| {
+            let mut raw = b;
+            patch(&mut raw);
+            raw
+        };
+        for raw in [
+            invalid(&|raw| raw[1..7].copy_from_slice(b"a.txt\0")),
+            invalid(&|raw| raw[1..6].copy_from_slice(b"..\\b\0")),
+            invalid(&|raw| raw[1..4].copy_from_slice(b"..\0")),
+            invalid(&|raw| raw[106..114].copy_from_slice(&0u64.to_le_bytes())),
+            invalid(&|raw| raw[114..118].copy_from_slice(&u32::MAX.to_le_bytes())),
+        ] {
+            let err = archive.set_raw_entry("/dir/b.txt", raw).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert_eq!(archive.read("/dir/b.txt").unwrap(), b"aaaa");
+
+        let archive = Pk2::open_in(io::Cursor::new(Vec::from(archive)), "").unwrap();
+        assert_eq!(archive.read("/dir/b.txt").unwrap(), b"aaaa");
+    }
+
+    #[test]
+    fn open_cancellable() {
+        use std::sync::atomic::AtomicBool;
+
+        use crate::OpenError;
+
+        let path = std::env::temp_dir().join("pk2_open_cancellable.pk2");
+        let _ = std::fs::remove_file(&path);
+        drop(Pk2::create_new(&path, "").unwrap());
+
+        let cancel = AtomicBool::new(true);
+        match Pk2::open_cancellable(&path, "", &cancel) {
+            Err(OpenError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::Interrupted),
+            _ => panic!("opening was not cancelled"),
+        }
+        cancel.store(false, std::sync::atomic::Ordering::Relaxed);
+        assert!(Pk2::open_cancellable(&path, "", &cancel).is_ok());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn progress_aborts_within_chain() {
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        for i in 0..100 {
+            archive.create_file(format!("/{}.txt", i)).unwrap();
+        }
+        let bytes = Vec::from(archive);
+
+        // the root chain alone spans several blocks, so aborting has to happen between its blocks
+        let mut reported = Vec::new();
+        let mut progress = |blocks| {
+            reported.push(blocks);
+            match blocks {
+                3 => Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled")),
+                _ => Ok(()),
+            }
+        };
+        let options = OpenOptions::new().progress(&mut progress);
+        match Pk2::open_in_with(io::Cursor::new(&bytes[..]), "", options) {
+            Err(crate::OpenError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::Interrupted),
+            _ => panic!("opening was not aborted"),
+        }
+        assert_eq!(reported, [1, 2, 3]);
+    }
+
+    #[test]
+    fn open_at_offset() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/a.txt").unwrap().write_all(b"a").unwrap();
+        let mut bytes = vec![0xCC; 1000];
+        bytes.extend(Vec::from(archive));
+
+        let mut archive = crate::unsync::Pk2::<crate::Offset<_>>::open_at_offset(
+            io::Cursor::new(bytes),
+            "",
+            1000,
+        )
+        .unwrap();
+        assert_eq!(archive.read("/a.txt").unwrap(), b"a");
+        archive.create_file("/b.txt").unwrap().write_all(b"b").unwrap();
+        let bytes = archive.stream.into_inner().into_inner().into_inner();
+        assert_eq!(bytes[..1000], [0xCC; 1000]);
+
+        let archive = crate::unsync::Pk2::<crate::Offset<_>>::open_at_offset(
+            io::Cursor::new(bytes),
+            "",
+            1000,
+        )
+        .unwrap();
+        assert_eq!(archive.read("/a.txt").unwrap(), b"a");
+        assert_eq!(archive.read("/b.txt").unwrap(), b"b");
+    }
+
+    #[test]
+    fn offset_seek() {
+        use std::io::{Seek, SeekFrom};
+
+        let mut stream = crate::Offset::new(io::Cursor::new(vec![0; 100]), 40).unwrap();
+        assert_eq!(stream.seek(SeekFrom::Start(10)).unwrap(), 10);
+        assert_eq!(stream.seek(SeekFrom::Current(5)).unwrap(), 15);
+        assert_eq!(stream.seek(SeekFrom::End(-10)).unwrap(), 50);
+        assert!(stream.seek(SeekFrom::Current(-51)).is_err());
+        assert!(stream.seek(SeekFrom::End(-61)).is_err());
+        assert!(stream.seek(SeekFrom::Start(u64::MAX)).is_err());
+        assert_eq!(stream.stream_position().unwrap(), 50);
+        assert_eq!(stream.into_inner().position(), 90);
+    }
+
+    #[test]
+    fn empty_file_without_data_position() {
+        use std::io::Write;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/empty.txt").unwrap().write_all(b"x").unwrap();
+        let mut raw = archive.raw_entry("/empty.txt").unwrap();
+        raw[106..118].fill(0);
+        archive.set_raw_entry("/empty.txt", raw).unwrap();
+
+        let archive = Pk2::open_in(io::Cursor::new(Vec::from(archive)), "").unwrap();
+        let file = archive.open_file("/empty.txt").unwrap();
+        assert_eq!(file.size(), 0);
+        assert_eq!(archive.read("/empty.txt").unwrap(), b"");
+    }
+
+    #[test]
+    fn empty_file_persists() {
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        drop(archive.create_file("/dir/empty.txt").unwrap());
+
+        let archive = Pk2::open_in(io::Cursor::new(Vec::from(archive)), "").unwrap();
+        assert_eq!(archive.open_file("/dir/empty.txt").unwrap().size(), 0);
+        assert_eq!(archive.read("/dir/empty.txt").unwrap(), b"");
+    }
+
+    #[test]
+    fn transformed_backing() {
+        use std::io::Write;
+
+        use crate::{Transform, Transformed};
+
+        struct Xor(u8);
+        impl Transform for Xor {
+            fn decode(&self, offset: u64, data: &mut [u8]) {
+                self.encode(offset, data)
+            }
+            fn encode(&self, offset: u64, data: &mut [u8]) {
+                for (i, byte) in data.iter_mut().enumerate() {
+                    *byte ^= self.0.wrapping_add((offset + i as u64) as u8);
+                }
+            }
+        }
+
+        type XorPk2 = crate::unsync::Pk2<Transformed<io::Cursor<Vec<u8>>, Xor>>;
+        let stream = Transformed::new(io::Cursor::new(Vec::new()), Xor(0x5A));
+        let mut archive = XorPk2::create_new_in(stream, "").unwrap();
+        archive.create_file("/dir/a.txt").unwrap().write_all(b"plain text").unwrap();
+        let bytes = archive.stream.into_inner().into_inner().into_inner();
+        assert!(!bytes.windows(10).any(|it| it == b"plain text"));
+        assert!(Pk2::open_in(io::Cursor::new(bytes.clone()), "").is_err());
+
+        let stream = Transformed::new(io::Cursor::new(bytes), Xor(0x5A));
+        let archive = XorPk2::open_in(stream, "").unwrap();
+        assert_eq!(archive.read("/dir/a.txt").unwrap(), b"plain text");
+    }
+}
@@ -0,0 +1,467 @@
+//! Bulk creation of archives.
+use std::collections::HashMap;
+use std::io;
+use std::marker::PhantomData;
+use std::path::{Component, Path};
+use std::time::SystemTime;
+use std::{fs as stdfs, mem};
+
+use crate::api::fs::File;
+use crate::api::{check_root, OpenOptions, Pk2};
+use crate::blowfish::Blowfish;
+use crate::constants::{
+    PK2_CURRENT_DIR_IDENT, PK2_FILE_BLOCK_ENTRY_COUNT, PK2_FILE_BLOCK_SIZE, PK2_MAX_PATH_LEN,
+    PK2_PARENT_DIR_IDENT, PK2_ROOT_BLOCK,
+};
+use crate::data::block_chain::PackBlock;
+use crate::data::entry::{check_name_len, DirectoryOrFile, NonEmptyEntry, PackEntry};
+use crate::data::header::PackHeader;
+use crate::data::{BlockOffset, ChainIndex, NameCodec, StreamOffset};
+use crate::error::{ChainLookupError, ChainLookupResult, OpenResult};
+use crate::io::{Backing, BackingMut};
+use crate::LockChoice;
+
+/// Builds a new archive from scratch, see [`Pk2::builder`].
+///
+/// File data is appended sequentially as files are added, while the file table is kept in memory
+/// and only written out once by [`Pk2Builder::finish`]. This avoids the entry writes and seeks
+/// back and forth the per-file API does for every file, making it the faster choice for packing
+/// lots of files. Directories are laid out case-insensitively, like the game resolves them.
+///
+/// The data of the files is placed in the order they are added, while the order of the entries
+/// in the file table either follows it as well or is sorted, see
+/// [`Pk2Builder::set_sort_entries`].
+pub struct Pk2Builder<Buffer, L: LockChoice> {
+    stream: Buffer,
+    blowfish: Option<Box<Blowfish>>,
+    /// The directories added so far, the root directory comes first.
+    dirs: Vec<BuilderDir>,
+    /// The offset the data of the next file is written at.
+    end: u64,
+    buf: Vec<u8>,
+    sort_entries: bool,
+    유령: PhantomData<fn() -> L>,
+}
+
+#[derive(Default)]
+struct BuilderDir {
+    /// The index of the parent directory in [`Pk2Builder::dirs`].
+    parent: usize,
+    /// The entries of the directory, excluding `.` and `..`.
+    entries: Vec<PackEntry>,
+    /// Maps the lowercased names of the entries to their index in `entries`, and for
+    /// directories the index of the directory in [`Pk2Builder::dirs`].
+    names: HashMap<Box<str>, (usize, Option<usize>)>,
+}
+
+impl<L: LockChoice> Pk2<stdfs::File, L> {
+    /// Starts building a new [`File`](stdfs::File) based archive at the given path, see
+    /// [`Pk2Builder`].
+    pub fn builder<P: AsRef<Path>, K: AsRef<[u8]>>(
+        path: P,
+        key: K,
+    ) -> OpenResult<Pk2Builder<stdfs::File, L>> {
+        let file = stdfs::OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .read(true)
+            .open(path.as_ref())?;
+        Pk2Builder::new(file, key)
+    }
+}
+
+impl<B, L> Pk2<B, L>
+where
+    B: Backing + BackingMut,
+    L: LockChoice,
+{
+    /// Starts building a new archive in the given stream, see [`Pk2Builder`].
+    pub fn builder_in<K: AsRef<[u8]>>(stream: B, key: K) -> OpenResult<Pk2Builder<B, L>> {
+        Pk2Builder::new(stream, key)
+    }
+}
+
+impl<B, L> Pk2Builder<B, L>
+where
+    B: Backing + BackingMut,
+    L: LockChoice,
+{
+    fn new<K: AsRef<[u8]>>(mut stream: B, key: K) -> OpenResult<Self> {
+        let (header, blowfish) = if key.as_ref().is_empty() {
+            (PackHeader::default(), None)
+        } else {
+            let bf = Blowfish::new(key.as_ref())?;
+            (PackHeader::new_encrypted(&bf), Some(Box::new(bf)))
+        };
+        // the stream is a valid empty archive until the file table is written by `finish`
+        Pk2::<B, L>::write_empty(&mut stream, blowfish.as_deref(), &header, &NameCodec::default())?;
+        let end = stream.end()?;
+        Ok(Pk2Builder {
+            stream,
+            blowfish,
+            dirs: vec![BuilderDir::default()],
+            end,
+            buf: Vec::new(),
+            sort_entries: false,
+            유령: PhantomData,
+        })
+    }
+
+    /// Adds a file at `path` with the contents read from `src`, returning the number of bytes
+    /// written.
+    ///
+    /// Missing parent directories are created. The data is streamed to the end of the archive,
+    /// if reading from `src` fails or the data exceeds the maximum file size of `u32::MAX` bytes
+    /// the file is not added.
+    pub fn add_file<P: AsRef<Path>, R: io::Read>(
+        &mut self,
+        path: P,
+        mut src: R,
+    ) -> io::Result<u64> {
+        const CHUNK_SIZE: usize = 64 * 1024;
+        let (dir, name) = self.create_parents(path.as_ref())?;
+        let key = name.to_ascii_lowercase().into_boxed_str();
+        if self.dirs[dir].names.contains_key(&key) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        self.buf.resize(CHUNK_SIZE, 0);
+        let start = self.end;
+        let mut len = 0;
+        loop {
+            let n = match src.read(&mut self.buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if len + n as u64 > u32::MAX as u64 {
+                return Err(io::Error::new(
+                    io::ErrorKind::FileTooLarge,
+                    "files can not be larger than u32::MAX bytes",
+                ));
+            }
+            self.stream.write_all_at(start + len, &self.buf[..n])?;
+            len += n as u64;
+        }
+        self.end += len;
+        let pos_data = if len == 0 { StreamOffset(0) } else { StreamOffset(start) };
+        let entry = PackEntry::new_file(name, pos_data, len as u32, None);
+        let dir = &mut self.dirs[dir];
+        dir.names.insert(key, (dir.entries.len(), None));
+        dir.entries.push(entry);
+        Ok(len)
+    }
+
+    /// Creates the directory at `path` together with all of its missing parent directories.
+    ///
+    /// Does nothing if the directory already exists.
+    pub fn create_dir_all<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        if check_root(path.as_ref())?.as_os_str().is_empty() {
+            return Ok(());
+        }
+        let (dir, name) = self.create_parents(path.as_ref())?;
+        self.create_dir(dir, &name).map(drop).map_err(Into::into)
+    }
+
+    /// Sets the access, create and modify time of the previously added file at `path`.
+    pub fn set_file_times<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        access: SystemTime,
+        create: SystemTime,
+        modify: SystemTime,
+    ) -> io::Result<()> {
+        let entry = self.file_entry_mut(path.as_ref())?;
+        entry.access_time = access.into();
+        entry.create_time = create.into();
+        entry.modify_time = modify.into();
+        Ok(())
+    }
+
+    /// Copies the access, create and modify time of `other` to the previously added file at
+    /// `path`.
+    pub fn copy_file_times<P: AsRef<Path>, B2, L2: LockChoice>(
+        &mut self,
+        path: P,
+        other: &File<'_, B2, L2>,
+    ) -> io::Result<()> {
+        let other = other.entry();
+        let entry = self.file_entry_mut(path.as_ref())?;
+        entry.access_time = other.access_time;
+        entry.create_time = other.create_time;
+        entry.modify_time = other.modify_time;
+        Ok(())
+    }
+
+    /// Sets whether the entries of every directory are sorted like [`Pk2::sort`] does, disabled
+    /// by default.
+    ///
+    /// When enabled, the file table only depends on the paths and times of the added files, and
+    /// not on the order they were added in, which only determines where their data is placed.
+    /// This allows ordering the data for loading performance while keeping the file table
+    /// reproducible.
+    pub fn set_sort_entries(&mut self, sort: bool) {
+        self.sort_entries = sort;
+    }
+
+    /// Writes the file table and opens the finished archive.
+    pub fn finish(mut self) -> OpenResult<Pk2<B, L>> {
+        let codec = &NameCodec::default();
+        if self.sort_entries {
+            self.sort();
+        }
+        // the root's first block was reserved at creation, every other block is appended after
+        // the file data in the order of `layout`
+        let layout = self.layout();
+        let mut next_block = self.end;
+        let mut dir_blocks = vec![Vec::new(); self.dirs.len()];
+        for idx in layout {
+            let dir = &self.dirs[idx];
+            let special_entries = if idx == 0 { 1 } else { 2 };
+            let block_count =
+                (special_entries + dir.entries.len()).div_ceil(PK2_FILE_BLOCK_ENTRY_COUNT);
+            let mut blocks = Vec::with_capacity(block_count);
+            if idx == 0 {
+                blocks.push(BlockOffset::from(PK2_ROOT_BLOCK));
+            }
+            while blocks.len() < block_count {
+                blocks.push(BlockOffset(next_block));
+                next_block += PK2_FILE_BLOCK_SIZE as u64;
+            }
+            dir_blocks[idx] = blocks;
+        }
+        let chain_of = |dir: usize| ChainIndex(dir_blocks[dir][0].0);
+
+        for (idx, dir) in mem::take(&mut self.dirs).into_iter().enumerate() {
+            let BuilderDir { parent, mut entries, names } = dir;
+            for (entry_idx, subdir) in names.into_values() {
+                let Some(subdir) = subdir else { continue };
+                if let Some(DirectoryOrFile::Directory { pos_children }) =
+                    entries[entry_idx].as_non_empty_mut().map(|entry| &mut entry.kind)
+                {
+                    *pos_children = chain_of(subdir);
+                }
+            }
+            let mut special =
+                vec![PackEntry::new_directory(PK2_CURRENT_DIR_IDENT, chain_of(idx), None)];
+            if idx != 0 {
+                special.push(PackEntry::new_directory(
+                    PK2_PARENT_DIR_IDENT,
+                    chain_of(parent),
+                    None,
+                ));
+            }
+            let mut entries = special.into_iter().chain(entries);
+            let blocks = &dir_blocks[idx];
+            for (block_idx, &offset) in blocks.iter().enumerate() {
+                let mut block = PackBlock::default();
+                for slot in block.entries_mut() {
+                    match entries.next() {
+                        Some(entry) => *slot = entry,
+                        None => break,
+                    }
+                }
+                if let Some(&next) = blocks.get(block_idx + 1) {
+                    block[PK2_FILE_BLOCK_ENTRY_COUNT - 1].set_next_block(next);
+                }
+                crate::io::write_block(
+                    self.blowfish.as_deref(),
+                    &mut self.stream,
+                    offset,
+                    &block,
+                    codec,
+                )?;
+            }
+        }
+
+        let options = OpenOptions::new();
+        let options = match self.blowfish.as_deref() {
+            Some(blowfish) => options.cipher(blowfish),
+            None => options,
+        };
+        Pk2::_open_in_impl_with(self.stream, b"", options, None)
+    }
+
+    /// Sorts the entries of every directory like [`Pk2::sort`] does, directories first and by
+    /// name otherwise.
+    fn sort(&mut self) {
+        fn sort_key(entry: &PackEntry) -> Option<(bool, &str)> {
+            entry.as_non_empty().map(|it| (it.is_file(), it.name()))
+        }
+        for dir in &mut self.dirs {
+            dir.entries.sort_by(|a, b| sort_key(a).cmp(&sort_key(b)));
+            for (idx, entry) in dir.entries.iter().enumerate() {
+                let key = entry.as_non_empty().unwrap().name().to_ascii_lowercase();
+                dir.names.get_mut(&*key).unwrap().0 = idx;
+            }
+        }
+    }
+
+    /// Returns the order the blocks of the directories are laid out in.
+    ///
+    /// This is the order the directories were created in, unless entries are sorted. Then the
+    /// directories are laid out in the order of a depth first walk, so that the layout does not
+    /// depend on the order the files were added in either.
+    fn layout(&self) -> Vec<usize> {
+        if !self.sort_entries {
+            return (0..self.dirs.len()).collect();
+        }
+        let mut layout = Vec::with_capacity(self.dirs.len());
+        let mut stack = vec![0];
+        while let Some(idx) = stack.pop() {
+            layout.push(idx);
+            let mut subdirs = self.dirs[idx]
+                .names
+                .values()
+                .filter_map(|&(entry, subdir)| Some((entry, subdir?)))
+                .collect::<Vec<_>>();
+            // pushing the subdirectories in reverse entry order visits them in entry order
+            subdirs.sort_unstable_by(|a, b| b.cmp(a));
+            stack.extend(subdirs.into_iter().map(|(_, subdir)| subdir));
+        }
+        layout
+    }
+
+    /// Looks up the entry of the previously added file at `path`.
+    fn file_entry_mut(&mut self, path: &Path) -> ChainLookupResult<&mut NonEmptyEntry> {
+        let relative = check_root(path)?;
+        let mut dir = 0;
+        let mut components = relative.components().peekable();
+        while let Some(component) = components.next() {
+            let name = component.as_os_str().to_str().ok_or(ChainLookupError::InvalidPath)?;
+            let key = name.to_ascii_lowercase();
+            let names = &self.dirs[dir].names;
+            let (idx, subdir) = *names.get(&*key).ok_or(ChainLookupError::NotFound)?;
+            match (subdir, components.peek()) {
+                (Some(subdir), Some(_)) => dir = subdir,
+                (None, Some(_)) => return Err(ChainLookupError::IsAFile),
+                (Some(_), None) => return Err(ChainLookupError::IsADirectory),
+                (None, None) => return Ok(self.dirs[dir].entries[idx].as_non_empty_mut().unwrap()),
+            }
+        }
+        Err(ChainLookupError::InvalidPath)
+    }
+
+    /// Creates all missing parent directories of `path`, returning the index of the parent
+    /// directory and the file name of `path`.
+    fn create_parents(&mut self, path: &Path) -> io::Result<(usize, String)> {
+        if path.as_os_str().len() > PK2_MAX_PATH_LEN {
+            return Err(ChainLookupError::PathTooLong.into());
+        }
+        let relative = check_root(path)?;
+        let mut names = Vec::new();
+        for component in relative.components() {
+            if let Component::Normal(name) = component {
+                let name = name.to_str().ok_or(ChainLookupError::InvalidPath)?;
+                check_name_len(name, NameCodec::default().encoding)?;
+                names.push(name.to_owned());
+            }
+        }
+        let name = names.pop().ok_or(ChainLookupError::InvalidPath)?;
+        let mut dir = 0;
+        for parent in names {
+            dir = self.create_dir(dir, &parent)?;
+        }
+        Ok((dir, name))
+    }
+
+    /// Returns the directory `name` inside of `dir`, creating it if it doesn't exist.
+    fn create_dir(&mut self, dir: usize, name: &str) -> ChainLookupResult<usize> {
+        let key = name.to_ascii_lowercase().into_boxed_str();
+        if let Some(&(_, subdir)) = self.dirs[dir].names.get(&key) {
+            return subdir.ok_or(ChainLookupError::IsAFile);
+        }
+        let new_dir = self.dirs.len();
+        self.dirs.push(BuilderDir { parent: dir, ..BuilderDir::default() });
+        let dir = &mut self.dirs[dir];
+        let entry_idx = dir.entries.len();
+        // the position of the children is only known once the file table is laid out
+        let entry = PackEntry::new_directory(name, ChainIndex(0), None);
+        dir.entries.push(entry);
+        dir.names.insert(key, (entry_idx, Some(new_dir)));
+        Ok(new_dir)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Cursor};
+    use std::time::{Duration, SystemTime};
+
+    use crate::unsync::Pk2;
+
+    #[test]
+    fn builder() {
+        let mut builder = Pk2::builder_in(Cursor::new(Vec::new()), "169841").unwrap();
+        builder.add_file("/foo", &b"foo"[..]).unwrap();
+        builder.add_file("/dir/bar", &b"bar"[..]).unwrap();
+        // enough files to span multiple blocks
+        for i in 0..50 {
+            builder.add_file(format!("/Dir/sub/{i}"), i.to_string().as_bytes()).unwrap();
+        }
+        builder.add_file("/empty", io::empty()).unwrap();
+        builder.create_dir_all("/dir/nested/deeper").unwrap();
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        builder.set_file_times("/DIR/bar", time, time, time).unwrap();
+
+        let err = builder.add_file("/FOO", &b""[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        let err = builder.add_file("/foo/baz", &b""[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotADirectory);
+
+        let archive = builder.finish().unwrap();
+        assert_eq!(archive.read("/foo").unwrap(), b"foo");
+        assert_eq!(archive.read("/dir/bar").unwrap(), b"bar");
+        assert_eq!(archive.read("/dir/sub/42").unwrap(), b"42");
+        assert_eq!(archive.read("/empty").unwrap(), b"");
+        assert_eq!(archive.open_file("/dir/bar").unwrap().modify_time(), Some(time));
+        assert_eq!(archive.read_dir("/dir/sub").unwrap().len(), 50);
+        archive.open_directory("/dir/nested/deeper").unwrap();
+        assert_eq!(archive.open_directory("/dir/sub/..").unwrap().name(), "dir");
+
+        let bytes = Vec::from(archive);
+        let archive = Pk2::open_in(Cursor::new(bytes), "169841").unwrap();
+        assert_eq!(archive.read("/dir/sub/7").unwrap(), b"7");
+    }
+
+    #[test]
+    fn builder_sort_entries() {
+        let build = |paths: &[&str]| {
+            let mut builder = Pk2::builder_in(Cursor::new(Vec::new()), "").unwrap();
+            builder.set_sort_entries(true);
+            for path in paths {
+                builder.add_file(path, path.as_bytes()).unwrap();
+            }
+            builder.finish().unwrap()
+        };
+        let paths = ["/c/z", "/a", "/b/y", "/b/x", "/d"];
+        let forward = build(&paths);
+        let reversed = build(&paths.iter().rev().copied().collect::<Vec<_>>());
+
+        for archive in [&forward, &reversed] {
+            let names = |path| {
+                let dir = archive.open_directory(path).unwrap();
+                dir.entries().map(|entry| entry.name().to_owned()).collect::<Vec<_>>()
+            };
+            assert_eq!(names("/"), ["b", "c", "a", "d"]);
+            assert_eq!(names("/b"), ["x", "y"]);
+            assert_eq!(archive.read("/b/x").unwrap(), b"/b/x");
+        }
+        // the directories are laid out the same way, no matter which one was created first
+        for dir in ["/b", "/c"] {
+            let children = |archive: &Pk2<_>| archive.raw_entry(dir).unwrap()[106..114].to_vec();
+            assert_eq!(children(&forward), children(&reversed));
+        }
+
+        // the data is still placed in the order the files were added in
+        let data_order = |archive: &Pk2<_>| {
+            let mut files = archive.iter_files_with_offset();
+            files.sort_by_key(|&(_, offset, _)| offset);
+            files.into_iter().map(|(path, ..)| path).collect::<Vec<_>>()
+        };
+        let expected = paths.iter().map(std::path::PathBuf::from).collect::<Vec<_>>();
+        assert_eq!(data_order(&forward), expected);
+        let expected = expected.into_iter().rev().collect::<Vec<_>>();
+        assert_eq!(data_order(&reversed), expected);
+    }
+}
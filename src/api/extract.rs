@@ -0,0 +1,335 @@
+//! Extracting archive contents to the filesystem.
+use std::fs as stdfs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::api::fs::{DirEntry, Directory, File};
+use crate::api::{LockChoice, Pk2};
+use crate::io::Backing;
+
+/// How files are handled whose output path already exists when extracting with
+/// [`ExtractOptions::flatten`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum OnConflict {
+    /// The file is not extracted.
+    #[default]
+    Skip,
+    /// The existing file is overwritten.
+    Overwrite,
+    /// The file is extracted under a name with an increasing counter appended to its stem.
+    Rename,
+}
+
+/// Settings for [`Pk2::extract_to`].
+#[derive(Clone, Debug, Default)]
+pub struct ExtractOptions {
+    /// The number of directory levels below the base that are descended into, `None` for no
+    /// limit. A depth of `0` only extracts the files directly inside of the base.
+    pub depth: Option<usize>,
+    /// Whether the access and modify times of the entries are written to the extracted files.
+    pub write_times: bool,
+    /// Whether all files are extracted directly into the output directory instead of recreating
+    /// the directory structure.
+    pub flatten: bool,
+    /// How files with the same name are handled when flattening.
+    pub on_conflict: OnConflict,
+    /// Only files whose path relative to the base matches one of these globs are extracted, if
+    /// any are given.
+    ///
+    /// Globs are matched ignoring ASCII case against paths separated by `/`, where `*` matches
+    /// any sequence of characters and `?` matches a single character.
+    pub include: Vec<String>,
+    /// Files whose path relative to the base matches one of these globs are not extracted.
+    pub exclude: Vec<String>,
+}
+
+/// The outcome of [`Pk2::extract_to`].
+#[derive(Debug, Default)]
+pub struct ExtractStats {
+    /// The archive path and output path of every extracted file, in extraction order.
+    pub extracted: Vec<(PathBuf, PathBuf)>,
+    /// The total number of bytes extracted.
+    pub bytes: u64,
+    /// The number of files skipped because their output path already existed.
+    pub skipped: usize,
+    /// The number of entries not extracted because their name would place them outside of the
+    /// output directory, like names containing path separators or `..`.
+    pub rejected: usize,
+    /// The output path of every file or directory that could not be written, together with the
+    /// error that occurred. Files inside of a directory that could not be created are not
+    /// attempted.
+    pub failed: Vec<(PathBuf, io::Error)>,
+}
+
+impl<B, L> Pk2<B, L>
+where
+    B: Backing,
+    L: LockChoice,
+{
+    /// Extracts all files inside of the directory at `base`, including the files inside of its
+    /// subdirectories, into the directory `out_dir` which is created if it doesn't exist.
+    ///
+    /// Entry names are checked before they are used as file names, entries whose name would
+    /// resolve outside of the directory they are extracted to are never written. These are
+    /// counted in [`ExtractStats::rejected`] instead. Failing to write a file does not abort the
+    /// extraction, such failures are collected in [`ExtractStats::failed`].
+    pub fn extract_to<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        base: P,
+        out_dir: Q,
+        options: &ExtractOptions,
+    ) -> io::Result<ExtractStats> {
+        let base = base.as_ref();
+        let dir = self.open_directory(base).map_err(|e| self.path_lookup_error(base, e))?;
+        let out_dir = out_dir.as_ref();
+        stdfs::create_dir_all(out_dir)?;
+        let mut extractor = Extractor {
+            options,
+            include: options.include.iter().map(|glob| lowercase_chars(glob)).collect(),
+            exclude: options.exclude.iter().map(|glob| lowercase_chars(glob)).collect(),
+            stats: ExtractStats::default(),
+        };
+        extractor.extract_dir(dir, base, Path::new(""), out_dir, 0);
+        Ok(extractor.stats)
+    }
+}
+
+struct Extractor<'a> {
+    options: &'a ExtractOptions,
+    include: Vec<Vec<char>>,
+    exclude: Vec<Vec<char>>,
+    stats: ExtractStats,
+}
+
+impl Extractor<'_> {
+    /// Extracts the contents of `dir`, whose path is `archive_path` in the archive and
+    /// `relative_path` relative to the base, into `out_dir`.
+    fn extract_dir<B: Backing, L: LockChoice>(
+        &mut self,
+        dir: Directory<'_, B, L>,
+        archive_path: &Path,
+        relative_path: &Path,
+        out_dir: &Path,
+        depth: usize,
+    ) {
+        for entry in dir.entries() {
+            let name = entry.name();
+            if !is_safe_name(name) {
+                self.stats.rejected += 1;
+                continue;
+            }
+            let archive_path = archive_path.join(name);
+            let relative_path = relative_path.join(name);
+            match entry {
+                DirEntry::File(file) => {
+                    if self.is_selected(&relative_path) {
+                        let out_path = out_dir.join(name);
+                        if let Err((out_path, e)) = self.extract_file(file, archive_path, out_path)
+                        {
+                            self.stats.failed.push((out_path, e));
+                        }
+                    }
+                }
+                DirEntry::Directory(dir) => {
+                    if self.options.depth.is_some_and(|max| depth >= max) {
+                        continue;
+                    }
+                    let out_dir = match self.options.flatten {
+                        true => out_dir.to_owned(),
+                        false => out_dir.join(name),
+                    };
+                    match stdfs::create_dir(&out_dir) {
+                        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => {
+                            self.stats.failed.push((out_dir, e));
+                            continue;
+                        }
+                        _ => (),
+                    }
+                    self.extract_dir(dir, &archive_path, &relative_path, &out_dir, depth + 1);
+                }
+            }
+        }
+    }
+
+    /// Extracts `file` to `out_path`, returning the path that could not be written on error.
+    fn extract_file<B: Backing, L: LockChoice>(
+        &mut self,
+        mut file: File<'_, B, L>,
+        archive_path: PathBuf,
+        mut out_path: PathBuf,
+    ) -> Result<(), (PathBuf, io::Error)> {
+        if self.options.flatten && out_path.exists() {
+            match self.options.on_conflict {
+                OnConflict::Skip => {
+                    self.stats.skipped += 1;
+                    return Ok(());
+                }
+                OnConflict::Overwrite => (),
+                OnConflict::Rename => out_path = unique_path(&out_path),
+            }
+        }
+        let mut write = || {
+            let mut out = stdfs::File::create(&out_path)?;
+            let bytes = io::copy(&mut file, &mut out)?;
+            if self.options.write_times {
+                let mut times = stdfs::FileTimes::new();
+                if let Some(time) = file.modify_time() {
+                    times = times.set_modified(time);
+                }
+                if let Some(time) = file.access_time() {
+                    times = times.set_accessed(time);
+                }
+                out.set_times(times)?;
+            }
+            Ok(bytes)
+        };
+        match write() {
+            Ok(bytes) => {
+                self.stats.bytes += bytes;
+                self.stats.extracted.push((archive_path, out_path));
+                Ok(())
+            }
+            Err(e) => Err((out_path, e)),
+        }
+    }
+
+    /// Returns whether the file at `relative_path` passes the include and exclude globs.
+    fn is_selected(&self, relative_path: &Path) -> bool {
+        if self.include.is_empty() && self.exclude.is_empty() {
+            return true;
+        }
+        let path = lowercase_chars(&relative_path.to_string_lossy().replace('\\', "/"));
+        (self.include.is_empty() || self.include.iter().any(|glob| glob_match_chars(glob, &path)))
+            && !self.exclude.iter().any(|glob| glob_match_chars(glob, &path))
+    }
+}
+
+/// Returns whether `name` can be used as a file name without escaping the directory it is
+/// joined onto.
+fn is_safe_name(name: &str) -> bool {
+    !matches!(name, "" | "." | "..")
+        && !name.contains(['/', '\\', ':', '\0'])
+        && Path::new(name).components().count() == 1
+}
+
+fn lowercase_chars(s: &str) -> Vec<char> {
+    s.to_ascii_lowercase().chars().collect()
+}
+
+/// Matches `name` against `glob` ignoring ASCII case, where `*` in the glob matches any sequence
+/// of characters and `?` matches a single character.
+///
+/// This is how [`ExtractOptions::include`] and [`ExtractOptions::exclude`] are matched.
+pub fn glob_match(glob: &str, name: &str) -> bool {
+    glob_match_chars(&lowercase_chars(glob), &lowercase_chars(name))
+}
+
+fn glob_match_chars(glob: &[char], name: &[char]) -> bool {
+    let (mut g, mut n) = (0, 0);
+    // the position after the last `*` in the glob and the name position it was tried at
+    let mut backtrack = None;
+    while n < name.len() {
+        match glob.get(g) {
+            Some('*') => {
+                g += 1;
+                backtrack = Some((g, n));
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                g += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((bg, bn)) => {
+                    g = bg;
+                    n = bn + 1;
+                    backtrack = Some((bg, bn + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+/// Appends an increasing counter to the file stem until the path does not exist yet.
+fn unique_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|ext| format!(".{}", ext.to_string_lossy()));
+    (1..)
+        .map(|n| {
+            path.with_file_name(format!("{} ({}){}", stem, n, extension.as_deref().unwrap_or("")))
+        })
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use super::{glob_match, ExtractOptions, OnConflict};
+    use crate::unsync::Pk2;
+
+    #[test]
+    fn extract_to() {
+        let out = std::env::temp_dir().join(format!("pk2-extract-to-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&out);
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/foo.txt").unwrap().write_all(b"foo").unwrap();
+        archive.create_file("/dir/bar.txt").unwrap().write_all(b"bar").unwrap();
+        archive.create_file("/dir/bar.dat").unwrap().write_all(b"dat").unwrap();
+        archive.create_file("/dir/sub/foo.txt").unwrap().write_all(b"sub").unwrap();
+        archive.create_file("/evil").unwrap();
+        // names with separators can only be produced by editing the archive bytes
+        let raw = archive.raw_entry("/evil").unwrap();
+        let mut data = Vec::from(archive);
+        let pos = data.windows(raw.len()).position(|window| window == raw).unwrap();
+        data[pos + 1..pos + 9].copy_from_slice(b"..\\evil\0");
+        let archive = Pk2::open_in(std::io::Cursor::new(data), "").unwrap();
+
+        let options = ExtractOptions { exclude: vec!["*.DAT".to_owned()], ..Default::default() };
+        let stats = archive.extract_to("/", out.join("tree"), &options).unwrap();
+        assert_eq!(stats.extracted.len(), 3);
+        assert_eq!(stats.bytes, 9);
+        assert_eq!(stats.rejected, 1);
+        assert_eq!(std::fs::read(out.join("tree/dir/sub/foo.txt")).unwrap(), b"sub");
+        assert!(!out.join("tree/dir/bar.dat").exists());
+        assert!(!out.join("evil").exists());
+
+        let options = ExtractOptions { depth: Some(0), ..Default::default() };
+        let stats = archive.extract_to("/dir", out.join("shallow"), &options).unwrap();
+        assert_eq!(stats.extracted.len(), 2);
+        assert!(!out.join("shallow/sub").exists());
+
+        let options = ExtractOptions {
+            flatten: true,
+            on_conflict: OnConflict::Rename,
+            include: vec!["*foo.txt".to_owned()],
+            ..Default::default()
+        };
+        let stats = archive.extract_to("/", out.join("flat"), &options).unwrap();
+        assert_eq!(stats.extracted.len(), 2);
+        assert_eq!(std::fs::read(out.join("flat/foo.txt")).unwrap(), b"foo");
+        assert_eq!(std::fs::read(out.join("flat/foo (1).txt")).unwrap(), b"sub");
+
+        // a file that can't be written doesn't stop the other files from being extracted
+        std::fs::create_dir_all(out.join("failing/dir/bar.txt")).unwrap();
+        let stats = archive.extract_to("/", out.join("failing"), &Default::default()).unwrap();
+        assert_eq!(stats.extracted.len(), 3);
+        assert_eq!(stats.failed.len(), 1);
+        assert_eq!(stats.failed[0].0, out.join("failing/dir/bar.txt"));
+        assert_eq!(std::fs::read(out.join("failing/dir/sub/foo.txt")).unwrap(), b"sub");
+
+        std::fs::remove_dir_all(&out).unwrap();
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("*.TXT", "dir/foo.txt"));
+        assert!(glob_match("dir/?oo.*", "DIR/foo.txt"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("*.txt", "foo.txt.bak"));
+        assert!(!glob_match("?", ""));
+    }
+}
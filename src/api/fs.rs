@@ -1,14 +1,18 @@
 //! File structs representing file entries inside a pk2 archive.
+use std::borrow::Cow;
 use std::hash::Hash;
-use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::io::{self, Cursor, IoSliceMut, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
 use crate::api::{LockChoice, Pk2};
+use crate::constants::PK2_ROOT_BLOCK;
 use crate::data::block_chain::PackBlockChain;
 use crate::data::entry::{DirectoryOrFile, NonEmptyEntry, PackEntry};
-use crate::data::{ChainIndex, StreamOffset};
+use crate::data::{ChainIndex, EntryOffset, StreamOffset};
 use crate::error::{ChainLookupError, ChainLookupResult};
+use crate::filetime::FILETIME;
+use crate::io::{Backing, BackingMut};
 use crate::Lock;
 
 /// A readable file entry in a pk2 archive.
@@ -56,7 +60,12 @@ impl<'pk2, Buffer, L: LockChoice> File<'pk2, Buffer, L> {
         }
     }
 
-    fn pos_data(&self) -> StreamOffset {
+    /// Returns the chain this file's entry resides in and its index in that chain.
+    pub(super) fn location(&self) -> (ChainIndex, usize) {
+        (self.chain, self.entry_index)
+    }
+
+    pub(crate) fn pos_data(&self) -> StreamOffset {
         match self.entry().kind {
             DirectoryOrFile::File { pos_data, .. } => pos_data,
             DirectoryOrFile::Directory { .. } => unreachable!(),
@@ -67,7 +76,17 @@ impl<'pk2, Buffer, L: LockChoice> File<'pk2, Buffer, L> {
         self.entry().name()
     }
 
-    fn entry(&self) -> &'pk2 NonEmptyEntry {
+    /// Returns the name encoded in the name encoding of the archive, before any name transform is
+    /// applied.
+    ///
+    /// Returns `None` if the stored name was not valid in the name encoding, see
+    /// [`ParseWarning::InvalidName`](crate::ParseWarning::InvalidName). This avoids allocating for
+    /// names whose encoded form is identical to their UTF-8 form.
+    pub fn name_bytes(&self) -> Option<Cow<'pk2, [u8]>> {
+        self.entry().name_bytes(self.archive.block_manager.name_encoding())
+    }
+
+    pub(super) fn entry(&self) -> &'pk2 NonEmptyEntry {
         self.archive
             .get_entry(self.chain, self.entry_index)
             .and_then(PackEntry::as_non_empty)
@@ -75,7 +94,8 @@ impl<'pk2, Buffer, L: LockChoice> File<'pk2, Buffer, L> {
     }
 
     fn remaining_len(&self) -> usize {
-        (self.size() as u64 - self.seek_pos) as usize
+        // the position may lie past the end after seeking
+        (self.size() as u64).saturating_sub(self.seek_pos) as usize
     }
 }
 
@@ -90,13 +110,16 @@ impl<Buffer, L: LockChoice> Seek for File<'_, Buffer, L> {
 
 impl<Buffer, L> Read for File<'_, Buffer, L>
 where
-    Buffer: Read + Seek,
+    Buffer: Backing,
     L: LockChoice,
 {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let pos_data = self.pos_data();
         let rem_len = self.remaining_len();
         let len = buf.len().min(rem_len);
+        if len == 0 {
+            return Ok(0);
+        }
         let n = self.archive.stream.with_lock(|stream| {
             crate::io::read_at(stream, pos_data + StreamOffset(self.seek_pos), &mut buf[..len])
         })?;
@@ -104,20 +127,42 @@ where
         Ok(n)
     }
 
+    /// Fills the buffers in order while holding the archive's lock only once.
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        let pos_data = self.pos_data() + StreamOffset(self.seek_pos);
+        let rem_len = self.remaining_len();
+        let n = self.archive.stream.with_lock(|stream| {
+            let mut n = 0;
+            for buf in bufs {
+                let len = buf.len().min(rem_len - n);
+                if len == 0 {
+                    break;
+                }
+                crate::io::read_exact_at(
+                    stream,
+                    pos_data + StreamOffset(n as u64),
+                    &mut buf[..len],
+                )?;
+                n += len;
+            }
+            io::Result::Ok(n)
+        })?;
+        self.seek_pos += n as u64;
+        Ok(n)
+    }
+
     fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
         let pos_data = self.pos_data();
         let rem_len = self.remaining_len();
-        if buf.len() < rem_len {
+        if buf.len() > rem_len {
             Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer"))
+        } else if buf.is_empty() {
+            Ok(())
         } else {
             self.archive.stream.with_lock(|stream| {
-                crate::io::read_at(
-                    stream,
-                    pos_data + StreamOffset(self.seek_pos),
-                    &mut buf[..rem_len],
-                )
+                crate::io::read_exact_at(stream, pos_data + StreamOffset(self.seek_pos), buf)
             })?;
-            self.seek_pos += rem_len as u64;
+            self.seek_pos += buf.len() as u64;
             Ok(())
         }
     }
@@ -131,9 +176,14 @@ where
 }
 
 /// A writable file entry in a pk2 archive.
+///
+/// Changes are buffered in memory and written to the archive when the file is flushed or dropped.
+/// Errors that occur while writing on drop are discarded unless the archive has been configured
+/// to panic on them via [`Pk2::set_panic_on_drop_flush_error`], so callers that need to know
+/// whether the data made it into the archive should use [`FileMut::flush_drop`].
 pub struct FileMut<'pk2, Buffer, L>
 where
-    Buffer: Write + Read + Seek,
+    Buffer: Backing + BackingMut,
     L: LockChoice,
 {
     archive: &'pk2 mut Pk2<Buffer, L>,
@@ -142,11 +192,15 @@ where
     // the index of this file in the chain
     entry_index: usize,
     data: Cursor<Vec<u8>>,
+    // whether data has been written that has not been flushed yet
+    dirty: bool,
+    // whether the modify time has been set explicitly, in which case flushing keeps it
+    modify_time_set: bool,
 }
 
 impl<'pk2, Buffer, L> FileMut<'pk2, Buffer, L>
 where
-    Buffer: Read + Write + Seek,
+    Buffer: Backing + BackingMut,
     L: LockChoice,
 {
     pub(super) fn new(
@@ -154,7 +208,19 @@ where
         chain: ChainIndex,
         entry_index: usize,
     ) -> Self {
-        FileMut { archive, chain, entry_index, data: Cursor::new(Vec::new()) }
+        FileMut {
+            archive,
+            chain,
+            entry_index,
+            data: Cursor::new(Vec::new()),
+            dirty: false,
+            modify_time_set: false,
+        }
+    }
+
+    /// Returns the chain this file's entry resides in and its index in that chain.
+    pub(super) fn location(&self) -> (ChainIndex, usize) {
+        (self.chain, self.entry_index)
     }
 
     pub fn modify_time(&self) -> Option<SystemTime> {
@@ -171,6 +237,7 @@ where
 
     pub fn set_modify_time(&mut self, time: SystemTime) {
         self.entry_mut().modify_time = time.into();
+        self.modify_time_set = true;
     }
 
     pub fn set_access_time(&mut self, time: SystemTime) {
@@ -187,6 +254,7 @@ where
         this.modify_time = other.modify_time;
         this.create_time = other.create_time;
         this.access_time = other.access_time;
+        self.modify_time_set = true;
     }
 
     pub fn size(&self) -> u32 {
@@ -196,9 +264,27 @@ where
         }
     }
 
+    /// Returns whether this file has been written to since it was last flushed.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Flushes the file and drops it, returning any error that occurred while writing.
     pub fn flush_drop(mut self) -> io::Result<()> {
         let res = self.flush();
+        debug_assert!(
+            res.is_err() || !self.dirty,
+            "file {:?} is still dirty after flushing successfully",
+            self.name()
+        );
+        debug_assert!(
+            self.archive.get_entry(self.chain, self.entry_index).is_some_and(PackEntry::is_file),
+            "file entry vanished while the file was open"
+        );
+        // skip the flush on drop, but still free the data buffer which forgetting would leak
+        let data = std::mem::take(self.data.get_mut());
         std::mem::forget(self);
+        drop(data);
         res
     }
 
@@ -206,6 +292,11 @@ where
         self.entry().name()
     }
 
+    /// Returns the stored name bytes, see [`File::name_bytes`].
+    pub fn name_bytes(&self) -> Option<Cow<'_, [u8]>> {
+        self.entry().name_bytes(self.archive.block_manager.name_encoding())
+    }
+
     fn entry(&self) -> &NonEmptyEntry {
         self.archive
             .get_entry(self.chain, self.entry_index)
@@ -222,6 +313,7 @@ where
 
     fn fetch_data(&mut self) -> io::Result<()> {
         let DirectoryOrFile::File { size, pos_data } = self.entry().kind else { unreachable!() };
+        reserve_to(self.data.get_mut(), size as usize)?;
         self.data.get_mut().resize(size as usize, 0);
         self.archive
             .stream
@@ -239,7 +331,7 @@ where
 
 impl<Buffer, L> Seek for FileMut<'_, Buffer, L>
 where
-    Buffer: Read + Write + Seek,
+    Buffer: Backing + BackingMut,
     L: LockChoice,
 {
     fn seek(&mut self, seek: SeekFrom) -> io::Result<u64> {
@@ -252,7 +344,7 @@ where
 
 impl<Buffer, L> Read for FileMut<'_, Buffer, L>
 where
-    Buffer: Read + Write + Seek,
+    Buffer: Backing + BackingMut,
     L: LockChoice,
 {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
@@ -266,36 +358,45 @@ where
     }
 
     fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
-        let len = buf.len();
-        let size = self.data.get_ref().len().max(self.size() as usize);
-        buf.resize(len + size, 0);
-        self.read_exact(&mut buf[len..]).map(|()| size)
+        self.try_fetch_data()?;
+        self.data.read_to_end(buf)
     }
 }
 
 impl<Buffer, L> Write for FileMut<'_, Buffer, L>
 where
-    Buffer: Read + Write + Seek,
+    Buffer: Backing + BackingMut,
     L: LockChoice,
 {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
         self.try_fetch_data()?;
-        let len = self.data.get_ref().len();
-        match len.checked_add(buf.len()).map(|new_len| new_len.checked_sub(u32::MAX as usize)) {
-            // data + buf < u32::MAX
-            Some(None | Some(0)) => self.data.write(buf),
-            // data + buf > u32::MAX, truncate buf
-            Some(Some(slice_overflow)) => self.data.write(&buf[..buf.len() - slice_overflow]),
-            // data + buf overflows usize::MAX
-            None => Ok(0),
+        // The cursor zero-fills any gap between the end of the data and the current position, so
+        // the file may only grow up to u32::MAX counted from the position we are writing at.
+        let remaining = (u32::MAX as u64).saturating_sub(self.data.position());
+        if remaining == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::FileTooLarge,
+                "files can not be larger than u32::MAX bytes",
+            ));
         }
+        let len = buf.len().min(usize::try_from(remaining).unwrap_or(usize::MAX));
+        let end = self.data.position() as usize + len;
+        reserve_to(self.data.get_mut(), end)?;
+        self.dirty = true;
+        self.data.write(&buf[..len])
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        if self.data.get_ref().is_empty() {
+        if !self.dirty || self.data.get_ref().is_empty() {
             return Ok(()); // nothing to write
         }
-        self.set_modify_time(SystemTime::now());
+        if !self.modify_time_set {
+            self.entry_mut().modify_time = FILETIME::now();
+        }
+        let codec = self.archive.block_manager.name_codec().clone();
         let chain = self.archive.block_manager.get_mut(self.chain).expect("invalid chain");
         let entry_offset = chain.stream_offset_for_entry(self.entry_index).expect("invalid entry");
 
@@ -312,30 +413,67 @@ where
             };
             // new unwritten file/more data than what fits, so use a new block
             if data_len > *size {
-                // Append data at the end of the buffer as it no longer fits
-                // This causes fragmentation
-                *pos_data = crate::io::append_data(&mut *stream, data)?;
+                let free_list = &mut self.archive.free_list;
+                free_list.free(*pos_data, *size);
+                let reused = match self.archive.reuse_free_space {
+                    true => free_list.allocate(data_len),
+                    false => None,
+                };
+                match reused {
+                    Some(offset) => {
+                        crate::io::write_data_at(&mut *stream, offset, data, data_len)?;
+                        *pos_data = offset;
+                    }
+                    // Append data at the end of the buffer as it no longer fits
+                    // This causes fragmentation
+                    None => *pos_data = crate::io::append_data(&mut *stream, data)?,
+                }
             } else {
                 // data fits into the previous buffer space
-                crate::io::write_data_at(&mut *stream, *pos_data, data)?;
+                crate::io::write_data_at(&mut *stream, *pos_data, data, *size)?;
+                self.archive
+                    .free_list
+                    .free(*pos_data + StreamOffset(data_len as u64), *size - data_len);
             }
             *size = data_len;
 
-            crate::io::write_entry_at(self.archive.blowfish.as_deref(), stream, entry_offset, entry)
-        })
+            crate::io::write_entry_at(
+                self.archive.blowfish.as_deref(),
+                stream,
+                entry_offset,
+                entry,
+                &codec,
+            )
+        })?;
+        self.dirty = false;
+        Ok(())
     }
 }
 
 impl<Buffer, L> Drop for FileMut<'_, Buffer, L>
 where
-    Buffer: Write + Read + Seek,
+    Buffer: Backing + BackingMut,
     L: LockChoice,
 {
     fn drop(&mut self) {
-        let _ = self.flush();
+        if let Err(e) = self.flush() {
+            if self.archive.panic_on_drop_flush_error && !std::thread::panicking() {
+                panic!("failed to flush file {:?} on drop: {}", self.name(), e);
+            }
+        }
     }
 }
 
+/// Reserves space for `len` bytes in `buf`, returning an error instead of aborting if the memory
+/// can't be allocated.
+fn reserve_to(buf: &mut Vec<u8>, len: usize) -> io::Result<()> {
+    buf.try_reserve(len.saturating_sub(buf.len()))
+        .map_err(|e| io::Error::new(io::ErrorKind::OutOfMemory, e))
+}
+
+type ForEachFileCallback<'a, 'pk2, Buffer, L> =
+    dyn FnMut(&Path, File<'pk2, Buffer, L>) -> io::Result<()> + 'a;
+
 fn seek_impl(seek: SeekFrom, seek_pos: u64, size: u64) -> io::Result<u64> {
     let (base_pos, offset) = match seek {
         SeekFrom::Start(n) => {
@@ -358,6 +496,50 @@ fn seek_impl(seek: SeekFrom, seek_pos: u64, size: u64) -> io::Result<u64> {
     }
 }
 
+/// An iterator over all files of an archive together with their absolute paths, created by
+/// [`Pk2::files`].
+pub struct Files<'pk2, Buffer, L: LockChoice> {
+    archive: &'pk2 Pk2<Buffer, L>,
+    /// Directories that have yet to be visited, together with their paths.
+    worklist: Vec<(PathBuf, ChainIndex)>,
+    /// The directory currently being visited and the index of the next entry to look at.
+    current: Option<(PathBuf, ChainIndex, usize)>,
+}
+
+impl<'pk2, Buffer, L: LockChoice> Files<'pk2, Buffer, L> {
+    pub(super) fn new(archive: &'pk2 Pk2<Buffer, L>, path: PathBuf, chain: ChainIndex) -> Self {
+        Files { archive, worklist: vec![(path, chain)], current: None }
+    }
+}
+
+impl<'pk2, Buffer, L: LockChoice> Iterator for Files<'pk2, Buffer, L> {
+    type Item = (PathBuf, File<'pk2, Buffer, L>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (path, chain, idx) = match &mut self.current {
+                Some(current) => current,
+                None => {
+                    let (path, chain) = self.worklist.pop()?;
+                    self.current.insert((path, chain, 0))
+                }
+            };
+            let Some(entry) = self.archive.get_chain(*chain).and_then(|it| it.get(*idx)) else {
+                self.current = None;
+                continue;
+            };
+            *idx += 1;
+            match DirEntry::from(entry, self.archive, *chain, *idx - 1) {
+                Some(DirEntry::File(file)) => return Some((path.join(file.name()), file)),
+                Some(DirEntry::Directory(dir)) => {
+                    self.worklist.push((path.join(dir.name()), dir.pos_children()))
+                }
+                None => (),
+            }
+        }
+    }
+}
+
 pub enum DirEntry<'pk2, Buffer, L: LockChoice> {
     Directory(Directory<'pk2, Buffer, L>),
     File(File<'pk2, Buffer, L>),
@@ -386,6 +568,82 @@ impl<'pk2, Buffer, L: LockChoice> DirEntry<'pk2, Buffer, L> {
             None
         }
     }
+
+    pub fn name(&self) -> &'pk2 str {
+        match self {
+            DirEntry::Directory(dir) => dir.name(),
+            DirEntry::File(file) => file.name(),
+        }
+    }
+
+    /// Returns the stored name bytes, see [`File::name_bytes`].
+    pub fn name_bytes(&self) -> Option<Cow<'pk2, [u8]>> {
+        match self {
+            DirEntry::Directory(dir) => dir.name_bytes(),
+            DirEntry::File(file) => file.name_bytes(),
+        }
+    }
+
+    /// The chain this entry resides in and its index in that chain.
+    pub(super) fn location(&self) -> (ChainIndex, usize) {
+        match self {
+            DirEntry::Directory(dir) => (dir.chain, dir.entry_index),
+            DirEntry::File(file) => (file.chain, file.entry_index),
+        }
+    }
+}
+
+/// The kind of an entry, as returned by [`Directory::entry_kind`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EntryKind {
+    File,
+    Directory,
+}
+
+/// An owned snapshot of a directory entry as returned by [`Pk2::read_dir`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntryInfo {
+    pub name: String,
+    pub is_dir: bool,
+    /// The size of the file, `None` for directories.
+    pub size: Option<u32>,
+    pub modify_time: Option<SystemTime>,
+}
+
+impl<Buffer, L: LockChoice> From<DirEntry<'_, Buffer, L>> for DirEntryInfo {
+    fn from(entry: DirEntry<'_, Buffer, L>) -> Self {
+        match entry {
+            DirEntry::Directory(dir) => DirEntryInfo {
+                name: dir.name().to_owned(),
+                is_dir: true,
+                size: None,
+                modify_time: dir.modify_time(),
+            },
+            DirEntry::File(file) => DirEntryInfo {
+                name: file.name().to_owned(),
+                is_dir: false,
+                size: Some(file.size()),
+                modify_time: file.modify_time(),
+            },
+        }
+    }
+}
+
+/// A single entry slot of a directory as returned by [`Directory::raw_entries`].
+pub enum EntrySlot<'pk2, Buffer, L: LockChoice> {
+    /// An unused slot that new entries can be placed in.
+    Empty,
+    /// A `.` or `..` entry and the chain it points to.
+    Backlink { name: &'pk2 str, target: ChainIndex },
+    /// A file or directory.
+    Entry(DirEntry<'pk2, Buffer, L>),
+}
+
+impl<Buffer, L: LockChoice> Copy for EntrySlot<'_, Buffer, L> {}
+impl<Buffer, L: LockChoice> Clone for EntrySlot<'_, Buffer, L> {
+    fn clone(&self) -> Self {
+        *self
+    }
 }
 
 /// A directory entry in a pk2 archive.
@@ -411,6 +669,11 @@ impl<'pk2, Buffer, L: LockChoice> Directory<'pk2, Buffer, L> {
         Directory { archive, chain, entry_index }
     }
 
+    /// Returns the chain this directory's entry resides in and its index in that chain.
+    pub(super) fn location(&self) -> (ChainIndex, usize) {
+        (self.chain, self.entry_index)
+    }
+
     fn entry(&self) -> &'pk2 NonEmptyEntry {
         self.archive
             .get_entry(self.chain, self.entry_index)
@@ -429,10 +692,21 @@ impl<'pk2, Buffer, L: LockChoice> Directory<'pk2, Buffer, L> {
         self.archive.get_chain(chain).expect("invalid dir object")
     }
 
+    /// Returns the name of this directory, which is `/` for the root directory.
     pub fn name(&self) -> &'pk2 str {
         self.entry().name()
     }
 
+    /// Returns the stored name bytes, see [`File::name_bytes`].
+    pub fn name_bytes(&self) -> Option<Cow<'pk2, [u8]>> {
+        self.entry().name_bytes(self.archive.block_manager.name_encoding())
+    }
+
+    /// Returns whether this is the root directory of the archive.
+    pub fn is_root(&self) -> bool {
+        self.pos_children() == PK2_ROOT_BLOCK
+    }
+
     pub fn modify_time(&self) -> Option<SystemTime> {
         self.entry().modify_time()
     }
@@ -445,36 +719,84 @@ impl<'pk2, Buffer, L: LockChoice> Directory<'pk2, Buffer, L> {
         self.entry().create_time()
     }
 
+    /// Resolves `path` relative to this directory, or relative to the archive root if it is
+    /// absolute.
+    ///
+    /// Returns the directory the lookup starts at as the error if `path` refers to it itself.
+    fn resolve_path_to_entry_and_parent(
+        &self,
+        path: &Path,
+    ) -> ChainLookupResult<Result<(ChainIndex, usize, &'pk2 PackEntry), Self>> {
+        let (chain, path) = super::normalize_at(self.pos_children(), path)?;
+        if path.as_os_str().is_empty() {
+            return Ok(Err(match chain {
+                PK2_ROOT_BLOCK => self.archive.open_root_dir(),
+                _ => *self,
+            }));
+        }
+        self.archive.block_manager.resolve_path_to_entry_and_parent(chain, &path).map(Ok)
+    }
+
+    /// Opens the file at `path`, which is either relative to this directory or absolute.
     pub fn open_file(&self, path: impl AsRef<Path>) -> ChainLookupResult<File<'pk2, Buffer, L>> {
         let (chain, entry_idx, entry) = self
-            .archive
-            .block_manager
-            .resolve_path_to_entry_and_parent(self.chain, path.as_ref())?;
+            .resolve_path_to_entry_and_parent(path.as_ref())?
+            .map_err(|_| ChainLookupError::IsADirectory)?;
         Pk2::<Buffer, L>::is_file(entry).map(|_| File::new(self.archive, chain, entry_idx))
     }
 
+    /// Opens the directory at `path`, which is either relative to this directory or absolute.
+    ///
+    /// Paths ending in `.` or `..` open the directory they refer to.
     pub fn open_directory(
         &self,
         path: impl AsRef<Path>,
     ) -> ChainLookupResult<Directory<'pk2, Buffer, L>> {
-        let (chain, entry_idx, entry) = self
-            .archive
-            .block_manager
-            .resolve_path_to_entry_and_parent(self.chain, path.as_ref())?;
-
-        if entry.as_non_empty().map_or(false, |it| it.is_directory() && it.is_normal_link()) {
-            Ok(Directory::new(self.archive, chain, entry_idx))
-        } else {
-            Err(ChainLookupError::NotFound)
+        let (chain, entry_idx, entry) =
+            match self.resolve_path_to_entry_and_parent(path.as_ref())? {
+                Ok(resolved) => resolved,
+                Err(dir) => return Ok(dir),
+            };
+        Pk2::<Buffer, L>::is_dir(entry)?;
+        match entry.as_non_empty() {
+            Some(dir) if dir.is_normal_link() => Ok(Directory::new(self.archive, chain, entry_idx)),
+            // `.` and `..` links
+            Some(dir) => {
+                self.archive.directory_of_chain(dir.directory_children_position().unwrap())
+            }
+            None => Err(ChainLookupError::NotFound),
         }
     }
 
+    /// Opens the entry at `path`, which is either relative to this directory or absolute.
     pub fn open(&self, path: impl AsRef<Path>) -> ChainLookupResult<DirEntry<'pk2, Buffer, L>> {
-        let (chain, entry_idx, entry) = self
-            .archive
-            .block_manager
-            .resolve_path_to_entry_and_parent(self.chain, path.as_ref())?;
-        DirEntry::from(entry, self.archive, chain, entry_idx).ok_or(ChainLookupError::NotFound)
+        let path = path.as_ref();
+        let (chain, entry_idx, entry) = match self.resolve_path_to_entry_and_parent(path)? {
+            Ok(resolved) => resolved,
+            Err(dir) => return Ok(DirEntry::Directory(dir)),
+        };
+        match entry.as_non_empty() {
+            Some(dir) if dir.is_directory() && !dir.is_normal_link() => {
+                self.open_directory(path).map(DirEntry::Directory)
+            }
+            _ => DirEntry::from(entry, self.archive, chain, entry_idx)
+                .ok_or(ChainLookupError::NotFound),
+        }
+    }
+
+    /// Returns whether the entry at `path` is a file or a directory, without creating a handle
+    /// for it.
+    ///
+    /// The path is resolved like in [`Directory::open`].
+    pub fn entry_kind(&self, path: impl AsRef<Path>) -> ChainLookupResult<EntryKind> {
+        let Ok((_, _, entry)) = self.resolve_path_to_entry_and_parent(path.as_ref())? else {
+            return Ok(EntryKind::Directory);
+        };
+        match entry.as_non_empty() {
+            Some(entry) if entry.is_file() => Ok(EntryKind::File),
+            Some(_) => Ok(EntryKind::Directory),
+            None => Err(ChainLookupError::NotFound),
+        }
     }
 
     /// Invokes cb on every file in this directory and its children
@@ -489,7 +811,7 @@ impl<'pk2, Buffer, L: LockChoice> Directory<'pk2, Buffer, L> {
         pub fn for_each_file_rec<'pk2, Buffer, L: LockChoice>(
             path: &mut PathBuf,
             dir: &Directory<'pk2, Buffer, L>,
-            cb: &mut dyn FnMut(&Path, File<Buffer, L>) -> io::Result<()>,
+            cb: &mut ForEachFileCallback<'_, 'pk2, Buffer, L>,
         ) -> io::Result<()> {
             for entry in dir.entries() {
                 match entry {
@@ -520,6 +842,16 @@ impl<'pk2, Buffer, L: LockChoice> Directory<'pk2, Buffer, L> {
             .flat_map(move |(idx, entry)| entry.is_file().then(|| File::new(archive, chain, idx)))
     }
 
+    /// Returns an iterator over the `.` and `..` entries of this directory
+    /// together with the chain they point to.
+    pub fn backlinks(&self) -> impl Iterator<Item = (&'pk2 str, ChainIndex)> {
+        self.dir_chain(self.pos_children())
+            .entries()
+            .filter_map(PackEntry::as_non_empty)
+            .filter(|entry| !entry.is_normal_link())
+            .filter_map(|entry| Some((entry.name(), entry.directory_children_position()?)))
+    }
+
     /// Returns an iterator over all items in this directory excluding `.` and
     /// `..`.
     pub fn entries(&self) -> impl Iterator<Item = DirEntry<'pk2, Buffer, L>> {
@@ -530,6 +862,132 @@ impl<'pk2, Buffer, L: LockChoice> Directory<'pk2, Buffer, L> {
             .enumerate()
             .flat_map(move |(idx, entry)| DirEntry::from(entry, archive, chain, idx))
     }
+
+    /// Returns an iterator over every entry slot of this directory together with its index,
+    /// including empty slots and the `.` and `..` entries.
+    ///
+    /// Unlike [`Directory::entries`] this reflects the on-disk layout, where each block holds 20
+    /// slots, so the slot at index `i` lives in block `i / 20` of the directory's chain.
+    pub fn raw_entries(&self) -> impl Iterator<Item = (usize, EntrySlot<'pk2, Buffer, L>)> {
+        let this = *self;
+        self.dir_chain(self.pos_children())
+            .entries()
+            .enumerate()
+            .map(move |(idx, entry)| (idx, this.entry_slot(idx, entry)))
+    }
+
+    /// Returns an iterator over every entry slot of this directory together with the offset it is
+    /// stored at in the archive.
+    ///
+    /// The slots are the same as the ones returned by [`Directory::raw_entries`]. Together with
+    /// [`Pk2::raw_entry`] this allows rewriting specific entries in place.
+    pub fn raw_entries_with_offsets(
+        &self,
+    ) -> impl Iterator<Item = (StreamOffset, EntrySlot<'pk2, Buffer, L>)> {
+        let this = *self;
+        self.dir_chain(self.pos_children()).iter_with_offsets().enumerate().map(
+            move |(idx, (EntryOffset(offset), entry))| {
+                (StreamOffset(offset), this.entry_slot(idx, entry))
+            },
+        )
+    }
+
+    fn entry_slot(self, idx: usize, entry: &'pk2 PackEntry) -> EntrySlot<'pk2, Buffer, L> {
+        let chain = self.pos_children();
+        match entry.as_non_empty() {
+            Some(non_empty) if !non_empty.is_normal_link() => EntrySlot::Backlink {
+                name: non_empty.name(),
+                target: non_empty.directory_children_position().unwrap_or(chain),
+            },
+            _ => DirEntry::from(entry, self.archive, chain, idx)
+                .map_or(EntrySlot::Empty, EntrySlot::Entry),
+        }
+    }
+
+    /// Returns the first entry of this directory matching the predicate, excluding `.` and `..`.
+    ///
+    /// Entries are visited in the order they are stored in, like [`Directory::entries`].
+    pub fn find(
+        &self,
+        mut pred: impl FnMut(&DirEntry<'pk2, Buffer, L>) -> bool,
+    ) -> Option<DirEntry<'pk2, Buffer, L>> {
+        self.entries().find(|entry| pred(entry))
+    }
+
+    /// Returns whether any entry of this directory matches the predicate, excluding `.` and `..`.
+    pub fn any(&self, mut pred: impl FnMut(&DirEntry<'pk2, Buffer, L>) -> bool) -> bool {
+        self.entries().any(|entry| pred(&entry))
+    }
+
+    /// Returns the position of the first entry matching the predicate among the entries returned
+    /// by [`Directory::entries`].
+    pub fn position(
+        &self,
+        mut pred: impl FnMut(&DirEntry<'pk2, Buffer, L>) -> bool,
+    ) -> Option<usize> {
+        self.entries().position(|entry| pred(&entry))
+    }
+
+    /// Returns up to `take` entries of this directory after skipping the first `skip` ones,
+    /// excluding `.` and `..`.
+    ///
+    /// Entries are returned in the order they are stored in, which stays the same as long as the
+    /// directory is not modified, so consecutive windows can be used to page through big
+    /// directories. Archives opened with [`Pk2::open_sorted`] store them sorted by name.
+    pub fn entries_range(&self, skip: usize, take: usize) -> Vec<DirEntry<'pk2, Buffer, L>> {
+        self.entries().skip(skip).take(take).collect()
+    }
+}
+
+/// A directory entry in a pk2 archive that allows creating and modifying the files inside of it.
+pub struct DirectoryMut<'pk2, Buffer, L: LockChoice> {
+    archive: &'pk2 mut Pk2<Buffer, L>,
+    chain: ChainIndex,
+    entry_index: usize,
+}
+
+impl<'pk2, Buffer, L: LockChoice> DirectoryMut<'pk2, Buffer, L> {
+    pub(super) fn new(
+        archive: &'pk2 mut Pk2<Buffer, L>,
+        chain: ChainIndex,
+        entry_index: usize,
+    ) -> Self {
+        DirectoryMut { archive, chain, entry_index }
+    }
+
+    /// Returns a read-only view of this directory.
+    pub fn as_directory(&self) -> Directory<'_, Buffer, L> {
+        Directory::new(self.archive, self.chain, self.entry_index)
+    }
+
+    pub fn name(&self) -> &str {
+        self.as_directory().name()
+    }
+
+    fn pos_children(&self) -> ChainIndex {
+        self.as_directory().pos_children()
+    }
+}
+
+impl<Buffer, L> DirectoryMut<'_, Buffer, L>
+where
+    Buffer: Backing + BackingMut,
+    L: LockChoice,
+{
+    /// Opens the file at `path` for writing, which is either relative to this directory or
+    /// absolute.
+    pub fn open_file_mut(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> ChainLookupResult<FileMut<'_, Buffer, L>> {
+        self.archive.open_file_mut_in(self.pos_children(), path.as_ref())
+    }
+
+    /// Creates a file at `path`, which is either relative to this directory or absolute, creating
+    /// all missing parent directories.
+    pub fn create_file(&mut self, path: impl AsRef<Path>) -> io::Result<FileMut<'_, Buffer, L>> {
+        self.archive.create_file_in(self.pos_children(), path.as_ref())
+    }
 }
 
 impl<Buffer, L: LockChoice> Hash for Directory<'_, Buffer, L> {
@@ -550,7 +1008,7 @@ impl<Buffer, L: LockChoice> Hash for File<'_, Buffer, L> {
 
 impl<Buffer, L> Hash for FileMut<'_, Buffer, L>
 where
-    Buffer: Read + Write + Seek,
+    Buffer: Backing + BackingMut,
     L: LockChoice,
 {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
@@ -559,3 +1017,406 @@ where
         state.write_usize(self.entry_index);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+    use std::io::{Seek, SeekFrom, Write};
+
+    use super::{DirEntry, EntryKind, EntrySlot};
+    use crate::constants::PK2_ROOT_BLOCK;
+    use crate::unsync::Pk2;
+    use crate::FILETIME;
+
+    #[test]
+    fn name_bytes() {
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/dir/foo.txt").unwrap();
+        archive.create_file("/dir/\u{d55c}.txt").unwrap();
+        let dir = archive.open_directory("/dir").unwrap();
+        assert!(matches!(dir.name_bytes(), Some(Cow::Borrowed(b"dir"))));
+        let file = archive.open_file("/dir/foo.txt").unwrap();
+        assert!(matches!(file.name_bytes(), Some(Cow::Borrowed(b"foo.txt"))));
+        let file = archive.open_file("/dir/\u{d55c}.txt").unwrap();
+        #[cfg(feature = "euc-kr")]
+        assert_eq!(file.name_bytes().as_deref(), Some(&b"\xc7\xd1.txt"[..]));
+        #[cfg(not(feature = "euc-kr"))]
+        assert_eq!(file.name_bytes().as_deref(), Some("\u{d55c}.txt".as_bytes()));
+
+        let raw = archive.raw_entry("/dir/foo.txt").unwrap();
+        let mut data = Vec::from(archive);
+        let pos = data.windows(raw.len()).position(|window| window == raw).unwrap();
+        data[pos + 1] = 0xFF;
+        let archive = Pk2::open_in(std::io::Cursor::new(data), "").unwrap();
+        let dir = archive.open_directory("/dir").unwrap();
+        let file = dir.files().find(|file| file.name().starts_with('\u{FFFD}')).unwrap();
+        assert_eq!(file.name_bytes(), None);
+    }
+
+    #[test]
+    fn directory_open_relative_and_absolute() {
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/test/foo.baz").unwrap();
+        archive.create_file("/other/bar.baz").unwrap();
+        let dir = archive.open_directory("/test").unwrap();
+        assert_eq!(dir.open_file("foo.baz").unwrap().name(), "foo.baz");
+        assert_eq!(dir.open_file("/other/bar.baz").unwrap().name(), "bar.baz");
+        assert_eq!(dir.open_directory("/other").unwrap().name(), "other");
+        assert!(dir.open_directory("/").unwrap().open_file("test/foo.baz").is_ok());
+        assert!(dir.open_file("bar.baz").is_err());
+        let root = archive.open_root_dir();
+        assert!(root.open_file("test/foo.baz").is_ok());
+        assert!(root.open("/test").is_ok());
+    }
+
+    #[test]
+    fn files_iterates_whole_archive() {
+        use std::path::PathBuf;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        for path in ["/a.txt", "/dir/b.txt", "/dir/sub/c.bin", "/other/d.txt"] {
+            archive.create_file(path).unwrap();
+        }
+        let mut paths = archive
+            .files()
+            .filter(|(_, file)| file.name().ends_with(".txt"))
+            .map(|(path, _)| path)
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(paths, ["/a.txt", "/dir/b.txt", "/other/d.txt"].map(PathBuf::from),);
+        assert_eq!(archive.files().count(), 4);
+    }
+
+    /// An in-memory buffer whose writes can be made to fail.
+    #[derive(Default)]
+    struct FailingWrites {
+        inner: std::io::Cursor<Vec<u8>>,
+        fail: bool,
+        /// Fails only the write after the given number of successful ones.
+        fail_once_after: Option<usize>,
+    }
+    impl std::io::Read for FailingWrites {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+    impl Seek for FailingWrites {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+    impl Write for FailingWrites {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let fail_once = match self.fail_once_after {
+                Some(0) => self.fail_once_after.take().is_some(),
+                Some(ref mut n) => {
+                    *n -= 1;
+                    false
+                }
+                None => false,
+            };
+            match self.fail || fail_once {
+                true => Err(std::io::ErrorKind::Other.into()),
+                false => self.inner.write(buf),
+            }
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn flush_drop_reports_errors() {
+        use crate::Lock;
+
+        let mut archive =
+            Pk2::<FailingWrites>::create_new_in(FailingWrites::default(), "").unwrap();
+        let mut file = archive.create_file("/foo.baz").unwrap();
+        assert!(!file.is_dirty());
+        file.write_all(b"foo").unwrap();
+        assert!(file.is_dirty());
+        file.flush().unwrap();
+        assert!(!file.is_dirty());
+        file.write_all(b"bar").unwrap();
+        file.archive.stream.with_lock(|stream| stream.fail = true);
+        assert!(file.flush_drop().is_err());
+    }
+
+    #[test]
+    fn truncate_resets_state_on_error() {
+        let mut archive =
+            Pk2::<FailingWrites>::create_new_in(FailingWrites::default(), "").unwrap();
+        archive.create_file("/dir/a.txt").unwrap().write_all(b"a").unwrap();
+        let res = archive.truncate_with(|stream| {
+            stream.inner = Default::default();
+            stream.fail = true;
+            Ok(())
+        });
+        assert!(res.is_err());
+        assert!(archive.open_file("/dir/a.txt").is_err());
+        assert!(archive.open_directory("/dir").is_err());
+        assert_eq!(archive.open_root_dir().entries().count(), 0);
+    }
+
+    #[test]
+    fn swap_files_restores_entries_on_error() {
+        use crate::Lock;
+
+        let mut archive =
+            Pk2::<FailingWrites>::create_new_in(FailingWrites::default(), "").unwrap();
+        archive.create_file("/a.txt").unwrap().write_all(b"a").unwrap();
+        archive.create_file("/b.txt").unwrap().write_all(b"bb").unwrap();
+        archive.stream.with_lock(|stream| stream.fail_once_after = Some(1));
+        assert!(archive.swap_files("/a.txt", "/b.txt").is_err());
+        assert_eq!(archive.read("/a.txt").unwrap(), b"a");
+        assert_eq!(archive.read("/b.txt").unwrap(), b"bb");
+
+        let data = archive.stream.with_lock(|stream| stream.inner.get_ref().clone());
+        let archive = Pk2::open_in(std::io::Cursor::new(data), "").unwrap();
+        assert_eq!(archive.read("/a.txt").unwrap(), b"a");
+        assert_eq!(archive.read("/b.txt").unwrap(), b"bb");
+    }
+
+    #[test]
+    #[should_panic(expected = "failed to flush file")]
+    fn panic_on_drop_flush_error() {
+        use crate::Lock;
+
+        let mut archive =
+            Pk2::<FailingWrites>::create_new_in(FailingWrites::default(), "").unwrap();
+        archive.set_panic_on_drop_flush_error(true);
+        let mut file = archive.create_file("/foo.baz").unwrap();
+        file.archive.stream.with_lock(|stream| stream.fail = true);
+        file.write_all(b"foo").unwrap();
+    }
+
+    #[test]
+    fn new_files_have_times() {
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        let before = FILETIME::now().into_systime().unwrap();
+        let mut file = archive.create_file("/foo.bin").unwrap();
+        assert!(file.create_time().unwrap() >= before);
+        assert!(file.access_time().unwrap() >= before);
+        let modify_time = file.modify_time().unwrap();
+        assert!(modify_time >= before);
+        file.write_all(b"foo").unwrap();
+        file.flush_drop().unwrap();
+        assert!(archive.open_file("/foo.bin").unwrap().modify_time().unwrap() >= modify_time);
+    }
+
+    #[test]
+    fn write_past_end_zero_fills_gap() {
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/foo.bin").unwrap().write_all(b"hello").unwrap();
+
+        let mut file = archive.open_file_mut("/foo.bin").unwrap();
+        assert_eq!(file.seek(SeekFrom::End(4)).unwrap(), 9);
+        file.write_all(b"abc").unwrap();
+        file.flush_drop().unwrap();
+
+        assert_eq!(archive.read("/foo.bin").unwrap(), b"hello\0\0\0\0abc");
+    }
+
+    #[test]
+    fn empty_buffer_reads_and_writes() {
+        use std::io::Read;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        let mut fresh = archive.create_file("/fresh.bin").unwrap();
+        assert_eq!(fresh.write(b"").unwrap(), 0);
+        assert!(!fresh.is_dirty());
+        assert_eq!(fresh.read(&mut []).unwrap(), 0);
+        fresh.read_exact(&mut []).unwrap();
+        fresh.flush_drop().unwrap();
+        let mut file = archive.open_file("/fresh.bin").unwrap();
+        assert_eq!(file.size(), 0);
+        assert_eq!(file.read(&mut [0; 4]).unwrap(), 0);
+        file.read_exact(&mut []).unwrap();
+
+        archive.create_file("/existing.bin").unwrap().write_all(b"hello").unwrap();
+        let mut existing = archive.open_file_mut("/existing.bin").unwrap();
+        assert_eq!(existing.write(b"").unwrap(), 0);
+        assert!(!existing.is_dirty());
+        assert_eq!(existing.read(&mut []).unwrap(), 0);
+        existing.read_exact(&mut []).unwrap();
+        let mut buf = [0; 2];
+        existing.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"he");
+        let mut rest = Vec::new();
+        assert_eq!(existing.read_to_end(&mut rest).unwrap(), 3);
+        assert_eq!(rest, b"llo");
+        drop(existing);
+
+        let mut file = archive.open_file("/existing.bin").unwrap();
+        assert_eq!(file.read(&mut []).unwrap(), 0);
+        file.read_exact(&mut []).unwrap();
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"he");
+        assert_eq!(
+            file.read_exact(&mut [0; 4]).unwrap_err().kind(),
+            std::io::ErrorKind::UnexpectedEof
+        );
+        file.seek(SeekFrom::Start(10)).unwrap();
+        assert_eq!(file.read(&mut buf).unwrap(), 0);
+        file.read_exact(&mut []).unwrap();
+        assert_eq!(archive.read("/existing.bin").unwrap(), b"hello");
+    }
+
+    #[test]
+    fn flush_rejects_region_outside_of_stream() {
+        use crate::data::entry::DirectoryOrFile;
+        use crate::data::StreamOffset;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/foo.bin").unwrap().write_all(b"hello").unwrap();
+        let (chain, idx, _) = archive.root_resolve_path_to_entry_and_parent("/foo.bin").unwrap();
+        let entry = archive.get_entry_mut(chain, idx).unwrap().as_non_empty_mut().unwrap();
+        let DirectoryOrFile::File { pos_data, .. } = &mut entry.kind else { unreachable!() };
+        *pos_data = StreamOffset(1 << 40);
+
+        let mut file = archive.open_file_mut("/foo.bin").unwrap();
+        file.data.get_mut().extend_from_slice(b"hello");
+        file.write_all(b"abc").unwrap();
+        assert_eq!(file.flush_drop().unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn read_vectored() {
+        use std::io::{IoSliceMut, Read};
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/foo.bin").unwrap().write_all(b"hello world").unwrap();
+        let mut file = archive.open_file("/foo.bin").unwrap();
+        file.seek(SeekFrom::Start(1)).unwrap();
+        let (mut a, mut b, mut c) = ([0; 4], [0; 4], [0; 4]);
+        let bufs = &mut [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b), IoSliceMut::new(&mut c)];
+        assert_eq!(file.read_vectored(bufs).unwrap(), 10);
+        assert_eq!((&a, &b, &c[..2]), (b"ello", b" wor", &b"ld"[..]));
+        assert_eq!(file.read_vectored(&mut [IoSliceMut::new(&mut a)]).unwrap(), 0);
+    }
+
+    #[test]
+    fn entries_range() {
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        for i in 0..50 {
+            archive.create_file(format!("/dir/{:02}.txt", i)).unwrap().write_all(b"a").unwrap();
+        }
+        let dir = archive.open_directory("/dir").unwrap();
+        let names = |skip, take| {
+            dir.entries_range(skip, take)
+                .into_iter()
+                .map(|entry| match entry {
+                    crate::DirEntry::File(file) => file.name().to_owned(),
+                    crate::DirEntry::Directory(dir) => dir.name().to_owned(),
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(0, 2), ["00.txt", "01.txt"]);
+        assert_eq!(names(48, 5), ["48.txt", "49.txt"]);
+        assert!(names(60, 5).is_empty());
+        let all = (0..5).flat_map(|page| names(page * 10, 10)).collect::<Vec<_>>();
+        assert_eq!(all.len(), 50);
+    }
+
+    #[test]
+    fn directory_find() {
+        use crate::DirEntry;
+
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        for path in ["/dir/a.txt", "/dir/sub/b.txt", "/dir/c.ifo", "/dir/d.ifo"] {
+            archive.create_file(path).unwrap().write_all(b"a").unwrap();
+        }
+        let dir = archive.open_directory("/dir").unwrap();
+        let is_ifo = |entry: &DirEntry<_, _>| matches!(entry, DirEntry::File(file) if file.name().ends_with(".ifo"));
+        assert!(matches!(dir.find(is_ifo), Some(DirEntry::File(file)) if file.name() == "c.ifo"));
+        assert!(dir
+            .find(|entry| matches!(entry, DirEntry::File(file) if file.name() == "x"))
+            .is_none());
+        assert!(dir.any(|entry| matches!(entry, DirEntry::Directory(_))));
+        assert_eq!(dir.position(is_ifo), Some(2));
+    }
+
+    #[test]
+    fn directory_mut() {
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/dir/sub/a.txt").unwrap().write_all(b"a").unwrap();
+        let mut dir = archive.open_directory_mut("/dir/sub").unwrap();
+        assert_eq!(dir.name(), "sub");
+        dir.create_file("b.txt").unwrap().write_all(b"b").unwrap();
+        dir.create_file("nested/c.txt").unwrap().write_all(b"c").unwrap();
+        dir.create_file("../d.txt").unwrap().write_all(b"d").unwrap();
+        dir.create_file("/e.txt").unwrap().write_all(b"e").unwrap();
+        assert!(dir.create_file("../../../f.txt").is_err());
+        dir.open_file_mut("a.txt").unwrap().write_all(b"A").unwrap();
+        assert!(dir.open_file_mut("nested").is_err());
+        assert_eq!(dir.as_directory().entries().count(), 3);
+
+        assert_eq!(archive.read("/dir/sub/a.txt").unwrap(), b"A");
+        assert_eq!(archive.read("/dir/sub/b.txt").unwrap(), b"b");
+        assert_eq!(archive.read("/dir/sub/nested/c.txt").unwrap(), b"c");
+        assert_eq!(archive.read("/dir/d.txt").unwrap(), b"d");
+        assert_eq!(archive.read("/e.txt").unwrap(), b"e");
+    }
+
+    #[test]
+    fn directory_is_root() {
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/dir/a.txt").unwrap().write_all(b"a").unwrap();
+        let root = archive.open_root_dir();
+        assert!(root.is_root());
+        assert_eq!(root.name(), "/");
+        let dir = archive.open_directory("/dir").unwrap();
+        assert!(!dir.is_root());
+        assert!(dir.open_directory("..").unwrap().is_root());
+        assert!(dir.open_directory("/").unwrap().is_root());
+    }
+
+    #[test]
+    fn directory_raw_entries() {
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/dir/a.txt").unwrap().write_all(b"a").unwrap();
+        archive.create_file("/dir/b.txt").unwrap().write_all(b"b").unwrap();
+        archive.delete_file("/dir/a.txt").unwrap();
+        let dir = archive.open_directory("/dir").unwrap();
+        let slots = dir.raw_entries().collect::<Vec<_>>();
+        assert_eq!(slots.len(), 20);
+        assert!(matches!(slots[0], (0, EntrySlot::Backlink { name: ".", .. })));
+        assert!(
+            matches!(slots[1], (1, EntrySlot::Backlink { name: "..", target }) if target == PK2_ROOT_BLOCK)
+        );
+        assert!(matches!(slots[2], (2, EntrySlot::Empty)));
+        assert!(
+            matches!(slots[3], (3, EntrySlot::Entry(DirEntry::File(file))) if file.name() == "b.txt")
+        );
+        assert!(slots[4..].iter().all(|(_, slot)| matches!(slot, EntrySlot::Empty)));
+    }
+
+    #[test]
+    fn directory_raw_entries_with_offsets() {
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        for idx in 0..25 {
+            archive.create_file(format!("/dir/{idx}.txt")).unwrap().write_all(b"a").unwrap();
+        }
+        let dir = archive.open_directory("/dir").unwrap();
+        let slots = dir.raw_entries_with_offsets().collect::<Vec<_>>();
+        assert_eq!(slots.len(), 40);
+        let (offset, slot) = &slots[26];
+        let EntrySlot::Entry(DirEntry::File(file)) = slot else { panic!("expected a file") };
+        let (offset, path) = (offset.0 as usize, format!("/dir/{}", file.name()));
+        let raw = archive.raw_entry(&path).unwrap();
+        assert_eq!(Vec::from(archive)[offset..][..raw.len()], raw);
+    }
+
+    #[test]
+    fn directory_entry_kind() {
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/dir/a.txt").unwrap().write_all(b"a").unwrap();
+        let dir = archive.open_directory("/dir").unwrap();
+        assert_eq!(dir.entry_kind("a.txt"), Ok(EntryKind::File));
+        assert_eq!(dir.entry_kind("/dir/a.txt"), Ok(EntryKind::File));
+        assert_eq!(dir.entry_kind(".."), Ok(EntryKind::Directory));
+        assert_eq!(dir.entry_kind("."), Ok(EntryKind::Directory));
+        assert_eq!(dir.entry_kind("/"), Ok(EntryKind::Directory));
+        assert_eq!(dir.entry_kind("b.txt"), Err(crate::ChainLookupError::NotFound));
+    }
+}
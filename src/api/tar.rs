@@ -0,0 +1,68 @@
+//! Exporting archive contents as a tar stream.
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::api::{LockChoice, Pk2};
+use crate::io::Backing;
+
+impl<B, L> Pk2<B, L>
+where
+    B: Backing,
+    L: LockChoice,
+{
+    /// Writes all files inside of the directory at `base`, including the files inside of its
+    /// subdirectories, into a tar stream.
+    ///
+    /// The paths inside the tar stream are relative to `base` and the modification times are
+    /// taken from the archive's entries.
+    pub fn export_tar<P: AsRef<Path>, W: io::Write>(&self, base: P, out: W) -> io::Result<W> {
+        let mut builder = tar::Builder::new(out);
+        self.for_each_file(base, |path, mut file| {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_size(file.size() as u64);
+            header.set_mode(0o644);
+            let mtime = file
+                .modify_time()
+                .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map_or(0, |duration| duration.as_secs());
+            header.set_mtime(mtime);
+            builder.append_data(&mut header, path, &mut file)
+        })?;
+        builder.into_inner()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+
+    use crate::unsync::Pk2;
+
+    #[test]
+    fn export_tar() {
+        let mut archive = Pk2::create_new_in_memory("").unwrap();
+        archive.create_file("/foo").unwrap().write_all(b"foo").unwrap();
+        archive.create_file("/dir/bar").unwrap().write_all(b"bar").unwrap();
+        archive.create_file("/dir/sub/baz").unwrap().write_all(b"baz").unwrap();
+
+        let data = archive.export_tar("/dir", Vec::new()).unwrap();
+        let mut tar = tar::Archive::new(&data[..]);
+        let mut files = tar
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let mut content = String::new();
+                entry.read_to_string(&mut content).unwrap();
+                (entry.path().unwrap().display().to_string(), content)
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(
+            files,
+            [("bar".to_owned(), "bar".to_owned()), ("sub/baz".to_owned(), "baz".to_owned())]
+        );
+    }
+}
@@ -4,6 +4,7 @@ use std::fmt;
 use byteorder::{ByteOrder, LE};
 
 use crate::constants::PK2_SALT;
+use crate::error::{OpenError, OpenResult};
 
 /// Error type for invalid blowfish keys.
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
@@ -16,6 +17,11 @@ impl fmt::Display for InvalidKey {
     }
 }
 
+/// The blowfish cipher used for encrypting and decrypting archives.
+///
+/// Deriving the cipher from a key is fairly expensive, so when opening many archives with the same
+/// key it can be created once and passed to [`Pk2::open_with_cipher`](crate::Pk2::open_with_cipher).
+#[derive(Clone)]
 pub struct Blowfish {
     s: [[u32; 256]; 4],
     p: [u32; 18],
@@ -36,27 +42,88 @@ fn next_u32_wrap(buf: &[u8], offset: &mut usize) -> u32 {
 }
 
 impl Blowfish {
+    /// Derives the archive cipher from the given key.
     pub fn new(key: &[u8]) -> Result<Self, InvalidKey> {
+        let key = Self::derive_key(key)?;
+        let mut this = Blowfish { p: P, s: S };
+        this.expand_key(&key);
+        Ok(this)
+    }
+
+    /// Returns the key the cipher is actually keyed with for the given user key, which is the
+    /// user key XORed with the pk2 salt.
+    ///
+    /// This is purely informational, for example to compare the derivation against a known-good
+    /// key when an archive fails to open. The derived key has the same length as the user key,
+    /// which has to be between 4 and 56 bytes long.
+    pub fn derive_key(key: &[u8]) -> Result<Vec<u8>, InvalidKey> {
         if key.len() < 4 || key.len() > 56 {
             return Err(InvalidKey);
         }
         let mut key = key.to_vec();
         gen_final_blowfish_key_inplace(&mut key);
-        let mut this = Blowfish { p: P, s: S };
-        this.expand_key(&key);
-        Ok(this)
+        Ok(key)
+    }
+
+    /// Derives the cipher for opening an encrypted archive, reporting an empty key as
+    /// [`OpenError::MissingKey`] rather than an invalid one.
+    pub(crate) fn for_encrypted_archive(key: &[u8]) -> OpenResult<Self> {
+        if key.is_empty() {
+            return Err(OpenError::MissingKey);
+        }
+        Ok(Self::new(key)?)
     }
 
+    /// Encrypts the data in place, trailing bytes not filling a whole 8 byte block are left as is.
     pub fn encrypt(&self, data: &mut [u8]) {
         data.chunks_exact_mut(8)
             .for_each(|data| self.encrypt_block(data.try_into().unwrap_or_else(|_| unreachable!())))
     }
 
+    /// Decrypts the data in place, trailing bytes not filling a whole 8 byte block are left as is.
     pub fn decrypt(&self, data: &mut [u8]) {
         data.chunks_exact_mut(8)
             .for_each(|data| self.decrypt_block(data.try_into().unwrap_or_else(|_| unreachable!())))
     }
 
+    /// Runs published test vectors through the cipher, returning whether encryption and
+    /// decryption produce the expected results.
+    ///
+    /// The vectors are the ones by Eric Young for the unmodified cipher, so the cipher is keyed
+    /// directly instead of mixing the key with the pk2 salt first. They are also run through
+    /// [`Blowfish::encrypt`] to check that the two halves of each block are read and written as
+    /// little endian `u32`s, as archives require.
+    pub fn self_test() -> bool {
+        // (key, plaintext, ciphertext), with the blocks as big endian numbers
+        const VECTORS: [(u64, u64, u64); 3] = [
+            (0x0000000000000000, 0x0000000000000000, 0x4EF997456198DD78),
+            (0xFFFFFFFFFFFFFFFF, 0xFFFFFFFFFFFFFFFF, 0x51866FD5B85ECB8A),
+            (0x3000000000000000, 0x1000000000000001, 0x7D856F9A613063F2),
+        ];
+        let halves = |block: u64| ((block >> 32) as u32, block as u32);
+        let to_le_bytes = |(l, r): (u32, u32)| {
+            let mut bytes = [0; 8];
+            LE::write_u32(&mut bytes[..4], l);
+            LE::write_u32(&mut bytes[4..], r);
+            bytes
+        };
+        VECTORS.iter().all(|&(key, plaintext, ciphertext)| {
+            let mut cipher = Blowfish { p: P, s: S };
+            cipher.expand_key(&key.to_be_bytes());
+            let (l, r) = halves(plaintext);
+            if cipher.encrypt_p(l, r) != halves(ciphertext) {
+                return false;
+            }
+            let mut data = to_le_bytes(halves(plaintext));
+            cipher.encrypt(&mut data);
+            if data != to_le_bytes(halves(ciphertext)) {
+                return false;
+            }
+            cipher.decrypt(&mut data);
+            data == to_le_bytes(halves(plaintext))
+        })
+    }
+
     #[inline(always)]
     fn encrypt_block(&self, block: &mut [u8; 8]) {
         let l = LE::read_u32(&block[..4]);
@@ -316,3 +383,24 @@ fn roundtrip() {
     bf.decrypt(&mut enc);
     assert_eq!(&enc, data);
 }
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn self_test() {
+        assert!(super::Blowfish::self_test());
+    }
+
+    #[test]
+    fn derive_key() {
+        assert_eq!(
+            super::Blowfish::derive_key(b"169841").unwrap(),
+            [0x32, 0xce, 0xdd, 0x7c, 0xbc, 0xa8]
+        );
+        // the salt only covers the first 10 bytes, the rest of the key is used as is
+        let derived = super::Blowfish::derive_key(b"169841169841").unwrap();
+        assert_eq!(&derived[10..], b"41");
+        assert_eq!(super::Blowfish::derive_key(b"169"), Err(super::InvalidKey));
+        assert_eq!(super::Blowfish::derive_key(&[0; 57]), Err(super::InvalidKey));
+    }
+}
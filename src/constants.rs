@@ -13,21 +13,32 @@ pub const PK2_CHECKSUM_STORED: usize = 3;
 /// The checksum value.
 pub const PK2_CHECKSUM: &[u8; 16] = b"Joymax Pak File\0";
 
+pub const PK2_HEADER_SIZE: usize = mem::size_of::<RawPackHeader>();
 pub const PK2_FILE_ENTRY_SIZE: usize = mem::size_of::<RawPackFileEntry>();
+/// The number of entries per block of the file table.
+///
+/// The whole block layout is derived from this, so supporting a format variant with a different
+/// block size only requires changing it. Archives using another block size fail to open with
+/// [`OpenError::UnsupportedBlockLayout`](crate::OpenError::UnsupportedBlockLayout).
 pub const PK2_FILE_BLOCK_ENTRY_COUNT: usize = 20;
 pub const PK2_FILE_BLOCK_SIZE: usize =
     mem::size_of::<[RawPackFileEntry; PK2_FILE_BLOCK_ENTRY_COUNT]>();
 
-pub const PK2_ROOT_BLOCK: ChainIndex = ChainIndex(mem::size_of::<RawPackHeader>() as u64);
+pub const PK2_ROOT_BLOCK: ChainIndex = ChainIndex(PK2_HEADER_SIZE as u64);
 // Sentinel entry to give the root block a proper path descriptor
 pub const PK2_ROOT_BLOCK_VIRTUAL: ChainIndex = ChainIndex(0);
 
+/// The size of the name field of an entry in bytes.
+pub const PK2_FILE_NAME_LEN: usize = 81;
+/// The maximum length of a path the game client can handle.
+pub const PK2_MAX_PATH_LEN: usize = 260;
+
 pub const PK2_CURRENT_DIR_IDENT: &str = ".";
 pub const PK2_PARENT_DIR_IDENT: &str = "..";
 
 /// The in-file header layout.
 #[allow(dead_code)]
-#[repr(packed)]
+#[repr(C, packed)]
 pub struct RawPackHeader {
     pub signature: [u8; 30],
     pub version: u32,
@@ -38,11 +49,11 @@ pub struct RawPackHeader {
 
 /// The structure of a single entry in a pack file.
 #[allow(dead_code)]
-#[repr(packed)]
+#[repr(C, packed)]
 #[derive(Copy, Clone)]
 pub struct RawPackFileEntry {
     pub ty: u8, //0 = Empty, 1 = Directory, 2  = File
-    pub name: [u8; 81],
+    pub name: [u8; PK2_FILE_NAME_LEN],
     pub access: FILETIME,
     pub create: FILETIME,
     pub modify: FILETIME,
@@ -3,9 +3,127 @@
 pub mod block_chain;
 pub mod block_manager;
 pub mod entry;
+pub mod free_list;
 pub mod header;
 
-use std::ops;
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::{fmt, ops};
+
+/// How entry names are compared when resolving paths.
+///
+/// The game itself resolves paths case-insensitively which is why this is the default.
+#[derive(Copy, Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub enum CaseSensitivity {
+    /// Names are compared ignoring ASCII case.
+    #[default]
+    Insensitive,
+    /// Names have to match exactly.
+    Sensitive,
+}
+
+/// The encoding entry names are stored in.
+///
+/// The original game archives use EUC-KR, which is the default if the `euc-kr` feature is
+/// enabled. Otherwise names are stored as UTF-8.
+#[derive(Copy, Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub enum NameEncoding {
+    /// Names are stored as EUC-KR.
+    #[cfg(feature = "euc-kr")]
+    #[default]
+    EucKr,
+    /// Names are stored as UTF-8, invalid sequences are replaced when decoding.
+    #[cfg_attr(not(feature = "euc-kr"), default)]
+    Utf8,
+}
+
+impl NameEncoding {
+    /// Encodes `name`, borrowing it if its encoded form is identical to its UTF-8 form.
+    pub(crate) fn encode(self, name: &str) -> Cow<'_, [u8]> {
+        match self {
+            #[cfg(feature = "euc-kr")]
+            NameEncoding::EucKr => encoding_rs::EUC_KR.encode(name).0,
+            NameEncoding::Utf8 => Cow::Borrowed(name.as_bytes()),
+        }
+    }
+
+    /// Decodes `bytes`, returning whether invalid sequences had to be replaced.
+    pub(crate) fn decode(self, bytes: &[u8]) -> (String, bool) {
+        match self {
+            #[cfg(feature = "euc-kr")]
+            NameEncoding::EucKr => {
+                let (name, lossy) = encoding_rs::EUC_KR.decode_without_bom_handling(bytes);
+                (name.into_owned(), lossy)
+            }
+            NameEncoding::Utf8 => match String::from_utf8_lossy(bytes) {
+                Cow::Borrowed(name) => (name.to_owned(), false),
+                Cow::Owned(name) => (name, true),
+            },
+        }
+    }
+}
+
+/// Scrambles or unscrambles a name field in place.
+type TransformFn = Arc<dyn Fn(&mut [u8]) + Send + Sync>;
+
+/// A reversible scrambling of the raw name field of entries.
+///
+/// Some modded archives obfuscate entry names, for example by XORing the name bytes, on top of the
+/// block table encryption. The whole name field is passed, including the padding after the nul
+/// terminator.
+#[derive(Clone)]
+pub struct NameTransform {
+    /// Reverts the scrambling of a name field read from the archive in place.
+    pub decode: TransformFn,
+    /// Scrambles a name field about to be written to the archive in place.
+    pub encode: TransformFn,
+}
+
+impl NameTransform {
+    /// Creates a transform from the functions reverting and applying the scrambling, which may
+    /// capture state like a key.
+    pub fn new(
+        decode: impl Fn(&mut [u8]) + Send + Sync + 'static,
+        encode: impl Fn(&mut [u8]) + Send + Sync + 'static,
+    ) -> Self {
+        NameTransform { decode: Arc::new(decode), encode: Arc::new(encode) }
+    }
+}
+
+impl fmt::Debug for NameTransform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NameTransform").finish_non_exhaustive()
+    }
+}
+
+/// How entry names are converted from and to the bytes of their name field.
+#[derive(Clone, Debug, Default)]
+pub struct NameCodec {
+    pub encoding: NameEncoding,
+    pub transform: Option<NameTransform>,
+}
+
+impl NameCodec {
+    /// Reverts the name transform, if any, of a raw name field.
+    pub(crate) fn untransform(&self, field: &mut [u8]) {
+        if let Some(transform) = &self.transform {
+            (transform.decode)(field);
+        }
+    }
+
+    /// Applies the name transform, if any, to a raw name field.
+    pub(crate) fn transform(&self, field: &mut [u8]) {
+        if let Some(transform) = &self.transform {
+            (transform.encode)(field);
+        }
+    }
+}
+
+impl From<NameEncoding> for NameCodec {
+    fn from(encoding: NameEncoding) -> Self {
+        NameCodec { encoding, transform: None }
+    }
+}
 
 /// Offset into the stream for a given chain. This is also used as an index into
 /// the block manager, hence the name.
@@ -37,3 +155,59 @@ impl ops::Add for StreamOffset {
         StreamOffset(self.0 + rhs)
     }
 }
+
+macro_rules! impl_offset_arith {
+    ($($ty:ident),*) => {$(
+        impl $ty {
+            /// Adds `rhs` to the offset, returning `None` on overflow.
+            #[inline]
+            pub fn checked_add(self, rhs: u64) -> Option<Self> {
+                self.0.checked_add(rhs).map($ty)
+            }
+
+            /// Subtracts `rhs` from the offset, returning `None` on underflow.
+            #[inline]
+            pub fn checked_sub(self, rhs: u64) -> Option<Self> {
+                self.0.checked_sub(rhs).map($ty)
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_offset_fmt {
+    ($($ty:ident),*) => {$(
+        impl fmt::Display for $ty {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Display::fmt(&self.0, f)
+            }
+        }
+
+        impl fmt::LowerHex for $ty {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::LowerHex::fmt(&self.0, f)
+            }
+        }
+    )*};
+}
+
+impl_offset_arith!(ChainIndex, BlockOffset, StreamOffset);
+impl_offset_fmt!(ChainIndex, BlockOffset, EntryOffset, StreamOffset);
+
+#[cfg(test)]
+mod test {
+    use super::{ChainIndex, StreamOffset};
+
+    #[test]
+    fn offset_formatting() {
+        assert_eq!(format!("{}", StreamOffset(256)), "256");
+        assert_eq!(format!("{:#x}", ChainIndex(256)), "0x100");
+    }
+
+    #[test]
+    fn offset_checked_arithmetic() {
+        assert_eq!(StreamOffset(10).checked_add(5), Some(StreamOffset(15)));
+        assert_eq!(StreamOffset(u64::MAX).checked_add(1), None);
+        assert_eq!(StreamOffset(10).checked_sub(10), Some(StreamOffset(0)));
+        assert_eq!(StreamOffset(10).checked_sub(11), None);
+    }
+}
@@ -3,7 +3,7 @@ use std::ops;
 
 use crate::constants::{PK2_FILE_BLOCK_ENTRY_COUNT, PK2_FILE_ENTRY_SIZE};
 use crate::data::entry::{NonEmptyEntry, PackEntry};
-use crate::data::{BlockOffset, ChainIndex, EntryOffset};
+use crate::data::{BlockOffset, CaseSensitivity, ChainIndex, EntryOffset, NameCodec};
 use crate::error::{ChainLookupError, ChainLookupResult};
 use crate::io::RawIo;
 
@@ -19,6 +19,10 @@ impl PackBlockChain {
         PackBlockChain { blocks }
     }
 
+    pub fn blocks(&self) -> &[(BlockOffset, PackBlock)] {
+        &self.blocks
+    }
+
     pub fn push_and_link(&mut self, offset: BlockOffset, block: PackBlock) {
         self.last_entry_mut().set_next_block(offset);
         self.blocks.push((offset, block));
@@ -54,6 +58,16 @@ impl PackBlockChain {
         self.blocks.iter().flat_map(|block| &block.1.entries)
     }
 
+    /// An iterator over the entries of this chain together with their offset
+    /// in the file.
+    pub fn iter_with_offsets(&self) -> impl Iterator<Item = (EntryOffset, &PackEntry)> {
+        self.blocks.iter().flat_map(|(BlockOffset(offset), block)| {
+            block.entries().enumerate().map(move |(idx, entry)| {
+                (EntryOffset(offset + (PK2_FILE_ENTRY_SIZE * idx) as u64), entry)
+            })
+        })
+    }
+
     /// An iterator over the entries of this chain.
     pub fn entries_mut(&mut self) -> impl Iterator<Item = &mut PackEntry> {
         self.blocks.iter_mut().flat_map(|block| &mut block.1.entries)
@@ -80,13 +94,41 @@ impl PackBlockChain {
     /// Looks up the `directory` name in this [`PackBlockChain`], returning the
     /// offset of the ['PackBlockChain'] corresponding to the directory if
     /// successful.
-    pub fn find_block_chain_index_of(&self, directory: &str) -> ChainLookupResult<ChainIndex> {
+    pub fn find_block_chain_index_of(
+        &self,
+        directory: &str,
+        case: CaseSensitivity,
+    ) -> ChainLookupResult<ChainIndex> {
         self.entries()
-            .find(|entry| entry.name_eq_ignore_ascii_case(directory))
+            .find(|entry| entry.name_eq(directory, case))
             .ok_or(ChainLookupError::NotFound)?
             .as_non_empty()
             .and_then(NonEmptyEntry::directory_children_position)
-            .ok_or(ChainLookupError::ExpectedDirectory)
+            .ok_or(ChainLookupError::IsAFile)
+    }
+
+    /// Moves all entries to the front of the chain, keeping their order, so that all empty slots
+    /// are at the end. Returns the indices of the blocks that were changed in ascending order.
+    pub fn compact(&mut self) -> Vec<usize> {
+        let mut changed = Vec::new();
+        let mut next_slot = 0;
+        for idx in 0..self.num_entries() {
+            if self[idx].is_empty() {
+                continue;
+            }
+            if idx != next_slot {
+                let mut moved = PackEntry::default();
+                moved.take_contents_from(&mut self[idx]);
+                self[next_slot].take_contents_from(&mut moved);
+                for block in [next_slot, idx].map(|it| it / PK2_FILE_BLOCK_ENTRY_COUNT) {
+                    if !changed.contains(&block) {
+                        changed.push(block);
+                    }
+                }
+            }
+            next_slot += 1;
+        }
+        changed
     }
 
     pub fn sort(&mut self, scratch: &mut Vec<PackEntry>) {
@@ -117,7 +159,7 @@ impl ops::IndexMut<usize> for PackBlockChain {
     }
 }
 
-/// A collection of 20 [`PackEntry`]s.
+/// A collection of [`PK2_FILE_BLOCK_ENTRY_COUNT`] [`PackEntry`]s.
 #[derive(Default)]
 pub struct PackBlock {
     entries: [PackEntry; PK2_FILE_BLOCK_ENTRY_COUNT],
@@ -125,11 +167,11 @@ pub struct PackBlock {
 
 #[allow(dead_code)]
 impl PackBlock {
-    pub fn entries(&self) -> std::slice::Iter<PackEntry> {
+    pub fn entries(&self) -> std::slice::Iter<'_, PackEntry> {
         self.entries.iter()
     }
 
-    pub fn entries_mut(&mut self) -> std::slice::IterMut<PackEntry> {
+    pub fn entries_mut(&mut self) -> std::slice::IterMut<'_, PackEntry> {
         self.entries.iter_mut()
     }
 
@@ -140,19 +182,50 @@ impl PackBlock {
     pub fn get_mut(&mut self, entry: usize) -> Option<&mut PackEntry> {
         self.entries.get_mut(entry)
     }
+
+    /// Returns the number of non-empty entries in this block.
+    pub fn occupied(&self) -> usize {
+        self.entries.iter().filter(|entry| !entry.is_empty()).count()
+    }
+
+    /// Returns the indices of the entries other than the last one that link to a next block.
+    ///
+    /// Only the link of the last entry is followed, links elsewhere are either junk or indicate
+    /// that the block was written with a different number of entries per block.
+    pub fn stray_links(&self) -> impl Iterator<Item = usize> + '_ {
+        let (_, entries) = self.entries.split_last().unwrap();
+        entries.iter().enumerate().filter(|(_, entry)| entry.next_block().is_some()).map(|(i, _)| i)
+    }
 }
 
-impl RawIo for PackBlock {
-    fn from_reader<R: Read>(mut r: R) -> IoResult<Self> {
+impl PackBlock {
+    /// Reads a block, decoding the names of its entries with the given codec. If `preserve_raw`
+    /// is set, the bytes of the entries that are not interpreted are kept.
+    pub fn from_reader_with<R: Read>(
+        mut r: R,
+        codec: &NameCodec,
+        preserve_raw: bool,
+    ) -> IoResult<Self> {
         let mut entries: [PackEntry; PK2_FILE_BLOCK_ENTRY_COUNT] = Default::default();
         for entry in &mut entries {
-            *entry = PackEntry::from_reader(&mut r)?;
+            *entry = PackEntry::from_reader_with(&mut r, codec, preserve_raw)?;
         }
         Ok(PackBlock { entries })
     }
 
-    fn to_writer<W: Write>(&self, mut w: W) -> IoResult<()> {
-        self.entries.iter().try_for_each(|entry| entry.to_writer(&mut w))
+    /// Writes a block, encoding the names of its entries with the given codec.
+    pub fn to_writer_with<W: Write>(&self, mut w: W, codec: &NameCodec) -> IoResult<()> {
+        self.entries.iter().try_for_each(|entry| entry.to_writer_with(&mut w, codec))
+    }
+}
+
+impl RawIo for PackBlock {
+    fn from_reader<R: Read>(r: R) -> IoResult<Self> {
+        Self::from_reader_with(r, &NameCodec::default(), false)
+    }
+
+    fn to_writer<W: Write>(&self, w: W) -> IoResult<()> {
+        self.to_writer_with(w, &NameCodec::default())
     }
 }
 
@@ -168,3 +241,37 @@ impl ops::IndexMut<usize> for PackBlock {
         &mut self.entries[idx]
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroU64;
+
+    use crate::constants::{PK2_FILE_BLOCK_ENTRY_COUNT, PK2_FILE_ENTRY_SIZE};
+    use crate::data::block_chain::{PackBlock, PackBlockChain};
+    use crate::data::entry::PackEntry;
+    use crate::data::{BlockOffset, EntryOffset, StreamOffset};
+
+    #[test]
+    fn block_occupancy() {
+        let mut block = PackBlock::default();
+        assert_eq!(block.occupied(), 0);
+        block[3] = PackEntry::new_file("foo", StreamOffset(256), 0, None);
+        block[PK2_FILE_BLOCK_ENTRY_COUNT - 1] = PackEntry::new_empty(NonZeroU64::new(10000));
+        assert_eq!(block.occupied(), 1);
+    }
+
+    #[test]
+    fn iter_with_offsets() {
+        let mut chain = PackBlockChain::from_blocks(vec![(BlockOffset(256), PackBlock::default())]);
+        chain.push_and_link(BlockOffset(10000), PackBlock::default());
+        let offsets: Vec<_> = chain.iter_with_offsets().map(|(offset, _)| offset).collect();
+        assert_eq!(offsets.len(), chain.num_entries());
+        for (idx, offset) in offsets.into_iter().enumerate() {
+            assert_eq!(Some(offset), chain.stream_offset_for_entry(idx));
+        }
+        assert_eq!(
+            chain.iter_with_offsets().nth(PK2_FILE_BLOCK_ENTRY_COUNT + 1).map(|(offset, _)| offset),
+            Some(EntryOffset(10000 + PK2_FILE_ENTRY_SIZE as u64))
+        );
+    }
+}
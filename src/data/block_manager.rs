@@ -1,46 +1,109 @@
 use std::collections::{HashMap, HashSet};
 use std::io;
+use std::num::NonZeroU64;
+use std::sync::{Mutex, PoisonError};
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
 use std::path::{Component, Path};
 
 use crate::blowfish::Blowfish;
-use crate::constants::{PK2_FILE_BLOCK_ENTRY_COUNT, PK2_ROOT_BLOCK, PK2_ROOT_BLOCK_VIRTUAL};
+use crate::constants::{
+    PK2_FILE_BLOCK_ENTRY_COUNT, PK2_HEADER_SIZE, PK2_ROOT_BLOCK, PK2_ROOT_BLOCK_VIRTUAL,
+};
 use crate::data::block_chain::{PackBlock, PackBlockChain};
-use crate::data::entry::{NonEmptyEntry, PackEntry};
-use crate::data::{BlockOffset, ChainIndex};
-use crate::error::{ChainLookupError, ChainLookupResult, OpenResult};
+use crate::data::entry::{DirectoryOrFile, NonEmptyEntry, PackEntry};
+use crate::data::{
+    BlockOffset, CaseSensitivity, ChainIndex, NameCodec, NameEncoding, StreamOffset,
+};
+use crate::error::{ChainLookupError, ChainLookupResult, OpenError, OpenResult, ParseWarning};
+use crate::io::{Backing, ReadAhead};
 
 /// Simple BlockManager backed by a hashmap.
 pub struct BlockManager {
-    chains: HashMap<ChainIndex, PackBlockChain, NoHashHasherBuilder>,
+    chains: HashMap<ChainIndex, PackBlockChain, ChainHasherBuilder>,
+    case: CaseSensitivity,
+    codec: NameCodec,
+    truncated_blocks: Vec<BlockOffset>,
+    /// Maps paths of directories relative to the root to their chain, if enabled. Cleared on
+    /// every mutable access to the chains, as any of them may move or rename directories.
+    path_cache: Option<Mutex<HashMap<Box<str>, ChainIndex>>>,
 }
 
 impl BlockManager {
     /// Parses the complete index of a pk2 file
-    pub fn new<F: io::Read + io::Seek>(bf: Option<&Blowfish>, mut stream: F) -> OpenResult<Self> {
-        let mut chains = HashMap::with_capacity_and_hasher(32, NoHashHasherBuilder);
-        // used to prevent an infinite loop that can be caused by specific files
-        let mut visited_block_set = HashSet::with_capacity_and_hasher(32, NoHashHasherBuilder);
-        let mut offsets = vec![PK2_ROOT_BLOCK];
-        while let Some(offset) = offsets.pop() {
-            if chains.contains_key(&offset) {
-                // skip offsets that are being pointed to multiple times
-                continue;
-            }
-            let block_chain =
-                Self::read_chain_from_stream_at(&mut visited_block_set, bf, &mut stream, offset)?;
-            visited_block_set.clear();
+    pub fn new<F: Backing + ?Sized>(bf: Option<&Blowfish>, stream: &mut F) -> OpenResult<Self> {
+        Self::new_with(
+            bf,
+            stream,
+            NameCodec::default(),
+            false,
+            false,
+            &mut |_| Ok(()),
+            None,
+            None,
+            None,
+        )
+    }
 
-            // put all folder offsets of this chain into the stack to parse them next
-            offsets.extend(
-                block_chain
-                    .entries()
-                    .filter_map(PackEntry::as_non_empty)
-                    .filter(|d| d.is_normal_link())
-                    .filter_map(NonEmptyEntry::directory_children_position),
-            );
-            chains.insert(offset, block_chain);
-        }
-        let mut this = BlockManager { chains };
+    /// Parses the complete index of a pk2 file, calling `progress` with the total number of blocks
+    /// read so far after each block. Parsing is aborted with the error `progress` returns, if any.
+    ///
+    /// Entry names are decoded with `codec`. If `preserve_raw` is set, the bytes of the entries
+    /// that are not interpreted are kept to be written back as they were. If `lenient` is set,
+    /// blocks that are cut off by the end of the stream are zero-filled instead of erroring. If
+    /// `reuse` is given, its allocations are reused for the new index. If `warnings` is given,
+    /// recoverable anomalies are recorded in it. If `read_ahead` is given, the blocks are read
+    /// through a window of that many bytes which is refilled whenever a block outside of it is
+    /// needed.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with<F: Backing + ?Sized>(
+        bf: Option<&Blowfish>,
+        stream: &mut F,
+        codec: NameCodec,
+        preserve_raw: bool,
+        lenient: bool,
+        progress: &mut dyn FnMut(usize) -> io::Result<()>,
+        reuse: Option<BlockManager>,
+        warnings: Option<&mut Vec<ParseWarning>>,
+        read_ahead: Option<usize>,
+    ) -> OpenResult<Self> {
+        let (chains, mut truncated_blocks) = match reuse {
+            Some(BlockManager { mut chains, mut truncated_blocks, .. }) => {
+                chains.clear();
+                truncated_blocks.clear();
+                (chains, truncated_blocks)
+            }
+            None => {
+                (HashMap::with_capacity_and_hasher(32, ChainHasherBuilder::default()), Vec::new())
+            }
+        };
+        let stream = &mut match read_ahead {
+            Some(window) => ReadAhead::with_window(stream, window),
+            None => ReadAhead::new(stream),
+        };
+        Self::check_root_block(bf, stream, &codec)?;
+        // a truncated stream is expected to cut off data in lenient mode, so the size is no limit
+        let stream_len = if lenient { None } else { stream.storage_size().ok() };
+        let mut reader = ChainReader {
+            bf,
+            stream,
+            codec: &codec,
+            preserve_raw,
+            stream_len,
+            truncated_blocks: lenient.then_some(&mut truncated_blocks),
+            warnings,
+            progress,
+            blocks_read: 0,
+            visited_block_set: HashSet::with_capacity_and_hasher(32, ChainHasherBuilder::default()),
+        };
+        let chains = reader.read_chains(chains)?;
+        let mut this = BlockManager {
+            chains,
+            case: CaseSensitivity::default(),
+            codec,
+            truncated_blocks,
+            path_cache: None,
+        };
         this.insert_virtual_root();
         Ok(this)
     }
@@ -55,26 +118,240 @@ impl BlockManager {
         self.chains.insert(virtual_root.chain_index(), virtual_root);
     }
 
-    /// Reads a [`PackBlockChain`] from the given file at the specified offset.
-    fn read_chain_from_stream_at<F: io::Read + io::Seek>(
-        visited_block_set: &mut HashSet<BlockOffset, NoHashHasherBuilder>,
+    /// Checks that the block at [`PK2_ROOT_BLOCK`] is the first block of the root directory.
+    ///
+    /// Otherwise the file table does not start where it is expected to, for example because the
+    /// archive has additional padding after its header, and parsing it would only yield garbage.
+    /// Such a block either does not parse at all or has the root's `.` entry at a later index, as
+    /// the padding shifts the entries of the actual root block.
+    fn check_root_block<F: Backing + ?Sized>(
         bf: Option<&Blowfish>,
         stream: &mut F,
-        offset: ChainIndex,
-    ) -> OpenResult<PackBlockChain> {
-        let mut blocks = Vec::new();
-        let mut offset = offset.into();
+        codec: &NameCodec,
+    ) -> OpenResult<()> {
+        // truncation is reported by the actual parse, so this may read leniently
+        let root = PK2_ROOT_BLOCK.into();
+        let block = match crate::io::read_block_at_lenient(bf, stream, root, codec, false) {
+            Ok((block, _)) => block,
+            Err(OpenError::Io(e)) if e.kind() == io::ErrorKind::InvalidData => {
+                return Err(OpenError::InvalidRootBlock)
+            }
+            Err(e) => return Err(e),
+        };
+        Self::check_root_entries(&block)
+    }
 
-        while visited_block_set.insert(offset) {
-            let block = crate::io::read_block_at(bf, &mut *stream, offset)?;
-            let nc = block.entries().last().and_then(PackEntry::next_block);
-            blocks.push((offset, block));
-            match nc {
-                Some(nc) => offset = BlockOffset(nc.get()),
-                None => break,
+    /// Checks that the root's `.` entry, if any, is the first entry of `block`, see
+    /// [`BlockManager::check_root_block`].
+    fn check_root_entries(block: &PackBlock) -> OpenResult<()> {
+        // names are not checked, as they are meaningless if they are scrambled
+        let is_self_link = |entry: &PackEntry| {
+            entry.as_non_empty().and_then(NonEmptyEntry::directory_children_position)
+                == Some(PK2_ROOT_BLOCK)
+        };
+        match block.entries().position(is_self_link) {
+            Some(idx) if idx > 0 => Err(OpenError::InvalidRootBlock),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that the blocks of a chain don't all have a stray link in the same entry.
+    ///
+    /// A single stray link is most likely junk, but the same entry linking in every block of a
+    /// chain means the blocks hold a different number of entries.
+    fn check_block_layout(blocks: &[(BlockOffset, PackBlock)]) -> OpenResult<()> {
+        let Some(((_, first), rest)) = blocks.split_first() else { return Ok(()) };
+        let mut common_stray_links = first.stray_links().collect::<Vec<_>>();
+        for (_, block) in rest {
+            common_stray_links.retain(|&entry| block.stray_links().any(|it| it == entry));
+        }
+        if rest.is_empty() || common_stray_links.is_empty() {
+            Ok(())
+        } else {
+            Err(OpenError::UnsupportedBlockLayout)
+        }
+    }
+
+    /// Checks a chain restored from an index cache the way [`BlockManager::new_with`] checks the
+    /// chains it parses, so that a cache cannot smuggle in a file table that would be rejected.
+    ///
+    /// The links between the blocks have to match their order, except for the last block which
+    /// may link back into the chain like a cycle the parser stopped at.
+    fn check_cached_chain(
+        blocks: &[(BlockOffset, PackBlock)],
+        stream_len: Option<u64>,
+    ) -> OpenResult<()> {
+        for (idx, (offset, block)) in blocks.iter().enumerate() {
+            Self::check_entry_offsets(block, *offset, stream_len, None)?;
+            let next_block = block.entries().last().and_then(PackEntry::next_block);
+            let linked = match blocks.get(idx + 1) {
+                Some(&(BlockOffset(next), _)) => next_block.is_some_and(|it| it.get() == next),
+                None => next_block
+                    .is_none_or(|it| blocks.iter().any(|&(BlockOffset(o), _)| o == it.get())),
+            };
+            if !linked {
+                return Err(io::Error::from(io::ErrorKind::InvalidData).into());
             }
         }
-        Ok(PackBlockChain::from_blocks(blocks))
+        Self::check_block_layout(blocks)
+    }
+
+    /// Checks that the offsets stored in the entries of `block` lie after the header and, if
+    /// `stream_len` is known, before the end of the stream.
+    ///
+    /// Offsets outside of this range can only stem from corruption, like byte-swapped fields.
+    /// Following such a directory or next block offset would lead to reads of garbage or huge
+    /// allocations, so these are errors. File data that lies outside of the stream only affects
+    /// reads of that file, so it is recorded in `warnings` instead. Empty files are exempt, as
+    /// their data offset is meaningless.
+    fn check_entry_offsets(
+        block: &PackBlock,
+        offset: BlockOffset,
+        stream_len: Option<u64>,
+        warnings: Option<&mut Vec<ParseWarning>>,
+    ) -> OpenResult<()> {
+        let in_range =
+            |pos: u64| pos >= PK2_HEADER_SIZE as u64 && stream_len.is_none_or(|len| pos < len);
+        let mut invalid_data = Vec::new();
+        for (entry_idx, entry) in block.entries().enumerate() {
+            let children = match entry.as_non_empty().map(|entry| &entry.kind) {
+                Some(&DirectoryOrFile::Directory { pos_children: ChainIndex(pos) }) => Some(pos),
+                Some(&DirectoryOrFile::File { pos_data: StreamOffset(pos), size }) if size > 0 => {
+                    let end = pos.checked_add(size as u64);
+                    if !in_range(pos)
+                        || end.is_none_or(|end| stream_len.is_some_and(|len| end > len))
+                    {
+                        invalid_data.push(entry_idx);
+                    }
+                    None
+                }
+                _ => None,
+            };
+            // stray links of entries other than the last one are never followed
+            let next_block = entry
+                .next_block()
+                .map(NonZeroU64::get)
+                .filter(|_| entry_idx == PK2_FILE_BLOCK_ENTRY_COUNT - 1);
+            if !children.into_iter().chain(next_block).all(in_range) {
+                return Err(OpenError::InvalidEntryOffset { block: offset, entry: entry_idx });
+            }
+        }
+        if let Some(warnings) = warnings {
+            warnings.extend(
+                invalid_data
+                    .into_iter()
+                    .map(|entry| ParseWarning::InvalidDataOffset { block: offset, entry }),
+            );
+        }
+        Ok(())
+    }
+
+    /// Serializes the parsed index into `out` so that it can be restored with
+    /// [`BlockManager::from_cache_bytes`] without reading and decrypting the blocks again.
+    ///
+    /// Names are written with the codec of this index, so the cache has to be restored with the
+    /// same codec.
+    pub fn to_cache_bytes(&self, out: &mut Vec<u8>) {
+        let chains =
+            self.chains.values().filter(|chain| chain.chain_index() != PK2_ROOT_BLOCK_VIRTUAL);
+        out.write_u32::<LE>(chains.clone().count() as u32).unwrap();
+        for chain in chains {
+            out.write_u32::<LE>(chain.blocks().len() as u32).unwrap();
+            for (BlockOffset(offset), block) in chain.blocks() {
+                out.write_u64::<LE>(*offset).unwrap();
+                block.to_writer_with(&mut *out, &self.codec).unwrap();
+            }
+        }
+    }
+
+    /// Restores an index previously serialized by [`BlockManager::to_cache_bytes`], keeping the
+    /// uninterpreted bytes of the entries if `preserve_raw` is set.
+    ///
+    /// The restored chains are validated like parsed ones, with the offsets checked against
+    /// `stream_len` if known. Every directory has to point to one of the restored chains.
+    pub fn from_cache_bytes(
+        mut bytes: &[u8],
+        codec: NameCodec,
+        preserve_raw: bool,
+        stream_len: Option<u64>,
+    ) -> OpenResult<Self> {
+        let chain_count = bytes.read_u32::<LE>()?;
+        let mut chains =
+            HashMap::with_capacity_and_hasher(chain_count as usize, ChainHasherBuilder::default());
+        for _ in 0..chain_count {
+            let block_count = bytes.read_u32::<LE>()?;
+            if block_count == 0 {
+                return Err(io::Error::from(io::ErrorKind::InvalidData).into());
+            }
+            let blocks = (0..block_count)
+                .map(|_| {
+                    Ok((
+                        BlockOffset(bytes.read_u64::<LE>()?),
+                        PackBlock::from_reader_with(&mut bytes, &codec, preserve_raw)?,
+                    ))
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+            Self::check_cached_chain(&blocks, stream_len)?;
+            let chain = PackBlockChain::from_blocks(blocks);
+            chains.insert(chain.chain_index(), chain);
+        }
+        let root = chains.get(&PK2_ROOT_BLOCK).ok_or(OpenError::InvalidRootBlock)?;
+        Self::check_root_entries(&root.blocks()[0].1)?;
+        let dangling = chains.values().flat_map(PackBlockChain::entries).any(|entry| {
+            entry
+                .as_non_empty()
+                .filter(|entry| entry.is_normal_link())
+                .and_then(NonEmptyEntry::directory_children_position)
+                .is_some_and(|children| !chains.contains_key(&children))
+        });
+        if !bytes.is_empty() || dangling {
+            return Err(io::Error::from(io::ErrorKind::InvalidData).into());
+        }
+        let mut this = BlockManager {
+            chains,
+            case: CaseSensitivity::default(),
+            codec,
+            truncated_blocks: Vec::new(),
+            path_cache: None,
+        };
+        this.insert_virtual_root();
+        Ok(this)
+    }
+
+    /// The blocks that were cut off by the end of the stream when parsing leniently.
+    pub fn truncated_blocks(&self) -> &[BlockOffset] {
+        &self.truncated_blocks
+    }
+
+    pub fn case_sensitivity(&self) -> CaseSensitivity {
+        self.case
+    }
+
+    pub fn name_encoding(&self) -> NameEncoding {
+        self.codec.encoding
+    }
+
+    pub fn name_codec(&self) -> &NameCodec {
+        &self.codec
+    }
+
+    pub fn set_case_sensitivity(&mut self, case: CaseSensitivity) {
+        self.invalidate_path_cache();
+        self.case = case;
+    }
+
+    pub fn has_path_cache(&self) -> bool {
+        self.path_cache.is_some()
+    }
+
+    pub fn set_path_cache(&mut self, enabled: bool) {
+        self.path_cache = enabled.then(Mutex::default);
+    }
+
+    fn invalidate_path_cache(&mut self) {
+        if let Some(cache) = &mut self.path_cache {
+            cache.get_mut().unwrap_or_else(PoisonError::into_inner).clear();
+        }
     }
 
     pub fn get(&self, chain: ChainIndex) -> Option<&PackBlockChain> {
@@ -83,10 +360,12 @@ impl BlockManager {
 
     pub fn get_mut(&mut self, chain: ChainIndex) -> Option<&mut PackBlockChain> {
         assert_ne!(chain, PK2_ROOT_BLOCK_VIRTUAL);
+        self.invalidate_path_cache();
         self.chains.get_mut(&chain)
     }
 
     pub fn insert(&mut self, chain: ChainIndex, block: PackBlockChain) {
+        self.invalidate_path_cache();
         self.chains.insert(chain, block);
     }
 
@@ -121,42 +400,73 @@ impl BlockManager {
                 .ok_or(ChainLookupError::InvalidChainIndex)?
                 .entries()
                 .enumerate()
-                .find(|(_, entry)| entry.name_eq_ignore_ascii_case(name))
+                .find(|(_, entry)| entry.name_eq(name, self.case))
                 .ok_or(ChainLookupError::NotFound)
                 .map(|(idx, entry)| (parent_index, idx, entry))
         })
     }
 
-    pub fn resolve_path_to_entry_and_parent_mut(
-        &mut self,
+    /// Returns the component of the path at which resolving it from the given
+    /// chain fails, or the last component if only the final lookup failed.
+    pub fn failing_component<'path>(
+        &self,
         current_chain: ChainIndex,
-        path: &Path,
-    ) -> ChainLookupResult<(ChainIndex, usize, &mut PackEntry)> {
-        self.resolve_path_to_parent(current_chain, path).and_then(move |(parent_index, name)| {
-            self.chains
-                .get_mut(&parent_index)
-                .ok_or(ChainLookupError::InvalidChainIndex)?
-                .entries_mut()
-                .enumerate()
-                .find(|(_, entry)| entry.name_eq_ignore_ascii_case(name))
-                .ok_or(ChainLookupError::NotFound)
-                .map(|(idx, entry)| (parent_index, idx, entry))
-        })
+        path: &'path Path,
+    ) -> Option<&'path str> {
+        let mut components = path.components();
+        let last = components.next_back()?;
+        let mut chain = current_chain;
+        for component in components {
+            let name = component.as_os_str().to_str()?;
+            match self.chains.get(&chain).map(|it| it.find_block_chain_index_of(name, self.case)) {
+                Some(Ok(next)) => chain = next,
+                _ => return Some(name),
+            }
+        }
+        last.as_os_str().to_str()
     }
 
     /// Resolves a path to a [`PackBlockChain`] index starting from the given
     /// blockchain returning the index of the last blockchain.
+    ///
+    /// Paths resolved from the root are looked up in and added to the path cache, if enabled.
     pub fn resolve_path_to_block_chain_index_at(
         &self,
         current_chain: ChainIndex,
         path: &Path,
+    ) -> ChainLookupResult<ChainIndex> {
+        let cache = self.path_cache.as_ref().filter(|_| current_chain == PK2_ROOT_BLOCK);
+        let Some((cache, key)) = cache.zip(path.to_str()) else {
+            return self.resolve_path_uncached(current_chain, path);
+        };
+        let key = match self.case {
+            CaseSensitivity::Insensitive => key.to_ascii_lowercase().into_boxed_str(),
+            CaseSensitivity::Sensitive => key.into(),
+        };
+        if let Some(&chain) = cache.lock().unwrap_or_else(PoisonError::into_inner).get(&key) {
+            return Ok(chain);
+        }
+        let chain = self.resolve_path_uncached(current_chain, path)?;
+        cache.lock().unwrap_or_else(PoisonError::into_inner).insert(key, chain);
+        Ok(chain)
+    }
+
+    fn resolve_path_uncached(
+        &self,
+        current_chain: ChainIndex,
+        path: &Path,
     ) -> ChainLookupResult<ChainIndex> {
         path.components().try_fold(current_chain, |idx, component| {
+            // `.` is resolved without looking up the entry, as the root block of some archives
+            // lacks it
+            if component == Component::CurDir {
+                return Ok(idx);
+            }
             let comp = component.as_os_str().to_str().ok_or(ChainLookupError::InvalidPath)?;
             self.chains
                 .get(&idx)
                 .ok_or(ChainLookupError::InvalidChainIndex)?
-                .find_block_chain_index_of(comp)
+                .find_block_chain_index_of(comp, self.case)
         })
     }
 
@@ -177,7 +487,7 @@ impl BlockManager {
                 .chains
                 .get(&chain)
                 .ok_or(ChainLookupError::InvalidChainIndex)?
-                .find_block_chain_index_of(name)
+                .find_block_chain_index_of(name, self.case)
             {
                 Ok(i) => chain = i,
                 // lies outside of the archive
@@ -186,13 +496,13 @@ impl BlockManager {
                 }
                 // found a non-existent part, we are done here
                 Err(ChainLookupError::NotFound) => break,
-                Err(ChainLookupError::ExpectedDirectory) => {
+                Err(ChainLookupError::IsAFile) => {
                     return if components.count() == 1 {
                         // found a file name at the end of the path
                         // this means the path has been fully searched
                         Ok(None)
                     } else {
-                        Err(ChainLookupError::ExpectedDirectory)
+                        Err(ChainLookupError::IsAFile)
                     };
                 }
                 Err(_) => unreachable!(),
@@ -206,7 +516,24 @@ impl BlockManager {
         }
     }
 
+    /// Returns the offsets of all blocks of the file table.
+    pub fn block_offsets(&self) -> impl Iterator<Item = BlockOffset> + '_ {
+        self.chains
+            .values()
+            .filter(|chain| chain.chain_index() != PK2_ROOT_BLOCK_VIRTUAL)
+            .flat_map(|chain| chain.blocks().iter().map(|&(offset, _)| offset))
+    }
+
+    /// Returns the offsets of all blocks of the file table together with their number of
+    /// non-empty entries.
+    pub fn block_occupancy(&self) -> impl Iterator<Item = (BlockOffset, usize)> + '_ {
+        self.chains.values().filter(|chain| chain.chain_index() != PK2_ROOT_BLOCK_VIRTUAL).flat_map(
+            |chain| chain.blocks().iter().map(|(offset, block)| (*offset, block.occupied())),
+        )
+    }
+
     pub fn sort(&mut self) {
+        self.invalidate_path_cache();
         let scratch = &mut Vec::with_capacity(4 * PK2_FILE_BLOCK_ENTRY_COUNT);
         for chain in self.chains.values_mut() {
             chain.sort(scratch);
@@ -215,8 +542,140 @@ impl BlockManager {
     }
 }
 
+/// The state of parsing the chains of a file table, see [`BlockManager::new_with`].
+struct ChainReader<'a, F: ?Sized> {
+    bf: Option<&'a Blowfish>,
+    stream: &'a mut F,
+    codec: &'a NameCodec,
+    preserve_raw: bool,
+    /// The limit entry offsets are checked against with [`BlockManager::check_entry_offsets`].
+    stream_len: Option<u64>,
+    /// Blocks cut off by the end of the stream are zero-filled and recorded in here instead of
+    /// causing an error, if given.
+    truncated_blocks: Option<&'a mut Vec<BlockOffset>>,
+    warnings: Option<&'a mut Vec<ParseWarning>>,
+    /// Called with `blocks_read` after every block.
+    progress: &'a mut dyn FnMut(usize) -> io::Result<()>,
+    blocks_read: usize,
+    /// The blocks of the chain being read, used to prevent an infinite loop that can be caused by
+    /// specific files.
+    visited_block_set: HashSet<BlockOffset, ChainHasherBuilder>,
+}
+
+impl<F: Backing + ?Sized> ChainReader<'_, F> {
+    /// Reads all chains reachable from the root block into `chains`.
+    fn read_chains(
+        &mut self,
+        mut chains: HashMap<ChainIndex, PackBlockChain, ChainHasherBuilder>,
+    ) -> OpenResult<HashMap<ChainIndex, PackBlockChain, ChainHasherBuilder>> {
+        let mut offsets = vec![PK2_ROOT_BLOCK];
+        while let Some(offset) = offsets.pop() {
+            if chains.contains_key(&offset) {
+                // skip offsets that are being pointed to multiple times
+                if let Some(warnings) = self.warnings.as_deref_mut() {
+                    warnings.push(ParseWarning::SharedChain(offset));
+                }
+                continue;
+            }
+            let block_chain = self.read_chain_at(offset)?;
+
+            // put all folder offsets of this chain into the stack to parse them next
+            offsets.extend(
+                block_chain
+                    .entries()
+                    .filter_map(PackEntry::as_non_empty)
+                    .filter(|d| d.is_normal_link())
+                    .filter_map(NonEmptyEntry::directory_children_position),
+            );
+            chains.insert(offset, block_chain);
+        }
+        Ok(chains)
+    }
+
+    /// Reads a [`PackBlockChain`] from the stream at the specified offset.
+    fn read_chain_at(&mut self, offset: ChainIndex) -> OpenResult<PackBlockChain> {
+        let mut blocks = Vec::new();
+        let mut offset = offset.into();
+        self.visited_block_set.clear();
+
+        while self.visited_block_set.insert(offset) {
+            let (bf, codec, preserve_raw) = (self.bf, self.codec, self.preserve_raw);
+            let block = match self.truncated_blocks.as_deref_mut() {
+                Some(truncated_blocks) => {
+                    let (block, truncated) = crate::io::read_block_at_lenient(
+                        bf,
+                        &mut *self.stream,
+                        offset,
+                        codec,
+                        preserve_raw,
+                    )?;
+                    if truncated {
+                        truncated_blocks.push(offset);
+                    }
+                    block
+                }
+                None => {
+                    crate::io::read_block_at(bf, &mut *self.stream, offset, codec, preserve_raw)?
+                }
+            };
+            self.blocks_read += 1;
+            (self.progress)(self.blocks_read)?;
+            let warnings = &mut self.warnings;
+            BlockManager::check_entry_offsets(
+                &block,
+                offset,
+                self.stream_len,
+                warnings.as_deref_mut(),
+            )?;
+            if let Some(warnings) = warnings.as_deref_mut() {
+                warnings.extend(
+                    block
+                        .stray_links()
+                        .map(|entry| ParseWarning::StrayNextBlock { block: offset, entry }),
+                );
+            }
+            let nc = block.entries().last().and_then(PackEntry::next_block);
+            if let Some(warnings) = warnings.as_deref_mut() {
+                let invalid_names = block.entries().enumerate().filter(|(_, entry)| {
+                    entry.as_non_empty().is_some_and(NonEmptyEntry::is_name_lossy)
+                });
+                warnings.extend(
+                    invalid_names
+                        .map(|(entry, _)| ParseWarning::InvalidName { block: offset, entry }),
+                );
+            }
+            blocks.push((offset, block));
+            match nc {
+                Some(nc) => offset = BlockOffset(nc.get()),
+                None => break,
+            }
+            if self.visited_block_set.contains(&offset) {
+                if let Some(warnings) = warnings.as_deref_mut() {
+                    warnings.push(ParseWarning::BlockCycle(blocks.last().unwrap().0));
+                }
+            }
+        }
+        if self.truncated_blocks.is_none() {
+            BlockManager::check_block_layout(&blocks)?;
+        }
+        Ok(PackBlockChain::from_blocks(blocks))
+    }
+}
+
+/// The hasher used for maps keyed by block offsets.
+///
+/// The offsets are read from the archive, so with untrusted archives an attacker could pick them
+/// to collide in the identity hasher, degrading lookups to linear scans. The `hardened-hasher`
+/// feature swaps in the randomly seeded std hasher for such cases.
+#[cfg(not(feature = "hardened-hasher"))]
+type ChainHasherBuilder = NoHashHasherBuilder;
+#[cfg(feature = "hardened-hasher")]
+type ChainHasherBuilder = std::collections::hash_map::RandomState;
+
+#[cfg(not(feature = "hardened-hasher"))]
 #[derive(Default)]
 struct NoHashHasherBuilder;
+#[cfg(not(feature = "hardened-hasher"))]
 impl std::hash::BuildHasher for NoHashHasherBuilder {
     type Hasher = NoHashHasher;
     #[inline(always)]
@@ -225,7 +684,9 @@ impl std::hash::BuildHasher for NoHashHasherBuilder {
     }
 }
 
+#[cfg(not(feature = "hardened-hasher"))]
 struct NoHashHasher(u64);
+#[cfg(not(feature = "hardened-hasher"))]
 impl std::hash::Hasher for NoHashHasher {
     #[inline(always)]
     fn finish(&self) -> u64 {
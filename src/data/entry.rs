@@ -1,28 +1,66 @@
 use byteorder::{ReadBytesExt, WriteBytesExt, LE};
 
+use std::borrow::Cow;
 use std::io::{Read, Result as IoResult, Write};
 use std::mem;
 use std::num::NonZeroU64;
 use std::time::SystemTime;
 
 use crate::constants::{
-    RawPackFileEntry, PK2_CURRENT_DIR_IDENT, PK2_FILE_ENTRY_SIZE, PK2_PARENT_DIR_IDENT,
+    RawPackFileEntry, PK2_CURRENT_DIR_IDENT, PK2_FILE_ENTRY_SIZE, PK2_FILE_NAME_LEN,
+    PK2_PARENT_DIR_IDENT,
 };
-use crate::data::{BlockOffset, ChainIndex, StreamOffset};
+use crate::data::{
+    BlockOffset, CaseSensitivity, ChainIndex, NameCodec, NameEncoding, StreamOffset,
+};
+use crate::error::{ChainLookupError, ChainLookupResult};
 use crate::filetime::FILETIME;
 use crate::io::RawIo;
 
+/// Checks that the name fits into an entry's name field once encoded.
+pub fn check_name_len(name: &str, encoding: NameEncoding) -> ChainLookupResult<()> {
+    match encoding.encode(name).len() <= PK2_FILE_NAME_LEN {
+        true => Ok(()),
+        false => Err(ChainLookupError::NameTooLong),
+    }
+}
+
+/// The range of an entry's bytes holding its name.
+const NAME_RANGE: std::ops::Range<usize> = 1..1 + PK2_FILE_NAME_LEN;
+/// The range of an entry's bytes holding the access, creation and modification times.
+const TIMES_RANGE: std::ops::Range<usize> = NAME_RANGE.end..NAME_RANGE.end + 24;
+/// The range of an entry's bytes holding the children or data position.
+const POSITION_RANGE: std::ops::Range<usize> = TIMES_RANGE.end..TIMES_RANGE.end + 8;
+/// The range of an entry's bytes holding the file size.
+const SIZE_RANGE: std::ops::Range<usize> = POSITION_RANGE.end..POSITION_RANGE.end + 4;
+/// The range of an entry's bytes holding the next block offset.
+const NEXT_BLOCK_RANGE: std::ops::Range<usize> = PK2_FILE_ENTRY_SIZE - 10..PK2_FILE_ENTRY_SIZE - 2;
+/// The range of an entry's bytes holding the trailing padding.
+const PADDING_RANGE: std::ops::Range<usize> = PK2_FILE_ENTRY_SIZE - 2..PK2_FILE_ENTRY_SIZE;
+const _: () = assert!(SIZE_RANGE.end == NEXT_BLOCK_RANGE.start);
+
+/// Returns the bytes of a name field up to its nul terminator.
+fn until_nul(field: &[u8]) -> &[u8] {
+    &field[..field.iter().position(|&b| b == 0).unwrap_or(field.len())]
+}
+
 /// An entry of a [`PackBlock`].
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct PackEntry {
     pub(crate) entry: Option<NonEmptyEntry>,
     next_block: Option<NonZeroU64>,
+    /// The bytes this entry was read from, kept if requested when reading and any of the bytes
+    /// the crate doesn't interpret are non-zero so that writing the entry back reproduces them.
+    raw: Option<Box<[u8; PK2_FILE_ENTRY_SIZE]>>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct NonEmptyEntry {
     pub(crate) kind: DirectoryOrFile,
     name: Box<str>,
+    /// Whether the name field was not valid in the name encoding, so `name` had invalid bytes
+    /// replaced.
+    lossy: bool,
     pub(crate) access_time: FILETIME,
     pub(crate) create_time: FILETIME,
     pub(crate) modify_time: FILETIME,
@@ -33,6 +71,25 @@ impl NonEmptyEntry {
         &self.name
     }
 
+    /// Returns the name encoded in `encoding`, before any name transform is applied.
+    ///
+    /// Returns `None` for lossy names, as their stored bytes can't be recovered from the decoded
+    /// name. This only allocates if the encoded name differs from its UTF-8 form.
+    pub fn name_bytes(&self, encoding: NameEncoding) -> Option<Cow<'_, [u8]>> {
+        (!self.lossy).then(|| encoding.encode(&self.name))
+    }
+
+    /// Returns whether the name read from the archive was not valid in its name encoding, in which
+    /// case invalid bytes were replaced by U+FFFD.
+    pub fn is_name_lossy(&self) -> bool {
+        self.lossy
+    }
+
+    pub fn set_name(&mut self, name: impl Into<Box<str>>) {
+        self.name = name.into();
+        self.lossy = false;
+    }
+
     pub fn access_time(&self) -> Option<SystemTime> {
         self.access_time.into_systime()
     }
@@ -90,11 +147,13 @@ impl PackEntry {
             entry: Some(NonEmptyEntry {
                 kind: DirectoryOrFile::Directory { pos_children },
                 name: name.into(),
+                lossy: false,
                 access_time: now,
                 create_time: now,
                 modify_time: now,
             }),
             next_block,
+            raw: None,
         }
     }
 
@@ -109,16 +168,18 @@ impl PackEntry {
             entry: Some(NonEmptyEntry {
                 kind: DirectoryOrFile::File { pos_data, size },
                 name: name.into(),
+                lossy: false,
                 access_time: now,
                 create_time: now,
                 modify_time: now,
             }),
             next_block,
+            raw: None,
         }
     }
 
     pub fn new_empty(next_block: Option<NonZeroU64>) -> Self {
-        PackEntry { entry: None, next_block }
+        PackEntry { entry: None, next_block, raw: None }
     }
 
     pub fn as_non_empty(&self) -> Option<&NonEmptyEntry> {
@@ -137,6 +198,14 @@ impl PackEntry {
         matches!(self.entry, Some(NonEmptyEntry { kind: DirectoryOrFile::File { .. }, .. }))
     }
 
+    /// Moves the contents of `other` into this entry, leaving `other` empty. The next block
+    /// pointers of both entries stay in place as they belong to the entry's slot.
+    pub fn take_contents_from(&mut self, other: &mut PackEntry) {
+        let next_block = self.next_block;
+        *self = other.clear();
+        self.next_block = next_block;
+    }
+
     pub fn clear(&mut self) -> PackEntry {
         mem::replace(self, PackEntry::new_empty(self.next_block))
     }
@@ -153,8 +222,11 @@ impl PackEntry {
         Some(self.entry.as_ref()?.name())
     }
 
-    pub fn name_eq_ignore_ascii_case(&self, other: &str) -> bool {
-        self.name().map(|this| this.eq_ignore_ascii_case(other)).unwrap_or(false)
+    pub fn name_eq(&self, other: &str, case: CaseSensitivity) -> bool {
+        self.name().is_some_and(|this| match case {
+            CaseSensitivity::Insensitive => this.eq_ignore_ascii_case(other),
+            CaseSensitivity::Sensitive => this == other,
+        })
     }
 
     pub fn is_empty(&self) -> bool {
@@ -162,10 +234,18 @@ impl PackEntry {
     }
 }
 
-impl RawIo for PackEntry {
-    /// Reads an entry from the given Read instance always reading exactly
-    /// PK2_FILE_ENTRY_SIZE bytes.
-    fn from_reader<R: Read>(mut r: R) -> IoResult<Self> {
+impl PackEntry {
+    /// Returns whether any of the bytes of the raw entry that are not interpreted are non-zero.
+    fn has_uninterpreted_bytes(raw: &[u8; PK2_FILE_ENTRY_SIZE]) -> bool {
+        let non_zero = |bytes: &[u8]| bytes.iter().any(|&b| b != 0);
+        let name_tail = match raw[0] {
+            RawPackFileEntry::TY_EMPTY => &raw[1..NEXT_BLOCK_RANGE.start],
+            _ => &raw[NAME_RANGE][until_nul(&raw[NAME_RANGE]).len()..],
+        };
+        non_zero(name_tail) || non_zero(&raw[PADDING_RANGE])
+    }
+
+    fn read_fields<R: Read>(mut r: R, encoding: NameEncoding) -> IoResult<Self> {
         match r.read_u8()? {
             RawPackFileEntry::TY_EMPTY => {
                 r.read_exact(
@@ -179,15 +259,11 @@ impl RawIo for PackEntry {
                 Ok(PackEntry::new_empty(next_block))
             }
             ty @ (RawPackFileEntry::TY_DIRECTORY | RawPackFileEntry::TY_FILE) => {
-                let name = {
-                    let mut buf = [0; 81];
+                let (name, lossy) = {
+                    let mut buf = [0; PK2_FILE_NAME_LEN];
                     r.read_exact(&mut buf)?;
-                    let end = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
-                    #[cfg(feature = "euc-kr")]
-                    let name = encoding_rs::EUC_KR.decode_without_bom_handling(&buf[..end]).0;
-                    #[cfg(not(feature = "euc-kr"))]
-                    let name = String::from_utf8_lossy(&buf[..end]);
-                    name.into_owned().into_boxed_str()
+                    let (name, lossy) = encoding.decode(until_nul(&buf));
+                    (name.into_boxed_str(), lossy)
                 };
                 let access_time = FILETIME {
                     dwLowDateTime: r.read_u32::<LE>()?,
@@ -207,8 +283,10 @@ impl RawIo for PackEntry {
                 r.read_u16::<LE>()?; //padding
 
                 Ok(PackEntry {
+                    raw: None,
                     entry: Some(NonEmptyEntry {
                         name,
+                        lossy,
                         access_time,
                         create_time,
                         modify_time,
@@ -228,7 +306,7 @@ impl RawIo for PackEntry {
         }
     }
 
-    fn to_writer<W: Write>(&self, mut w: W) -> IoResult<()> {
+    fn write_fields<W: Write>(&self, mut w: W, encoding: NameEncoding) -> IoResult<()> {
         match &self.entry {
             None => {
                 w.write_all(
@@ -246,17 +324,15 @@ impl RawIo for PackEntry {
                 access_time,
                 create_time,
                 modify_time,
+                ..
             }) => {
                 w.write_u8(if self.is_directory() {
                     RawPackFileEntry::TY_DIRECTORY
                 } else {
                     RawPackFileEntry::TY_FILE
                 })?;
-                #[cfg(feature = "euc-kr")]
-                let mut encoded = encoding_rs::EUC_KR.encode(name).0.into_owned();
-                #[cfg(not(feature = "euc-kr"))]
-                let mut encoded = name.as_bytes().to_owned();
-                encoded.resize(81, 0);
+                let mut encoded = encoding.encode(name).into_owned();
+                encoded.resize(PK2_FILE_NAME_LEN, 0);
                 w.write_all(&encoded)?;
                 w.write_u32::<LE>(access_time.dwLowDateTime)?;
                 w.write_u32::<LE>(access_time.dwHighDateTime)?;
@@ -277,13 +353,95 @@ impl RawIo for PackEntry {
     }
 }
 
+impl PackEntry {
+    /// Returns the bytes this entry is stored as with the given codec, see
+    /// [`Pk2::raw_entry`](crate::Pk2::raw_entry) for their layout.
+    pub fn to_raw(&self, codec: &NameCodec) -> [u8; PK2_FILE_ENTRY_SIZE] {
+        let mut raw = [0; PK2_FILE_ENTRY_SIZE];
+        self.to_writer_with(&mut raw[..], codec).unwrap_or_else(|_| unreachable!());
+        raw
+    }
+
+    /// Parses an entry from the bytes it is stored as, preserving the bytes that are not
+    /// interpreted.
+    pub fn from_raw(raw: &[u8; PK2_FILE_ENTRY_SIZE], codec: &NameCodec) -> IoResult<Self> {
+        Self::from_reader_with(&raw[..], codec, true)
+    }
+
+    /// Reads an entry from the given Read instance always reading exactly
+    /// PK2_FILE_ENTRY_SIZE bytes, decoding its name with the given codec. If `preserve_raw` is
+    /// set, the bytes that are not interpreted are kept to be written back as they were.
+    pub fn from_reader_with<R: Read>(
+        mut r: R,
+        codec: &NameCodec,
+        preserve_raw: bool,
+    ) -> IoResult<Self> {
+        let mut raw = [0; PK2_FILE_ENTRY_SIZE];
+        r.read_exact(&mut raw)?;
+        if raw[0] != RawPackFileEntry::TY_EMPTY {
+            codec.untransform(&mut raw[NAME_RANGE]);
+        }
+        let mut entry = Self::read_fields(&raw[..], codec.encoding)?;
+        if preserve_raw && Self::has_uninterpreted_bytes(&raw) {
+            entry.raw = Some(Box::new(raw));
+        }
+        Ok(entry)
+    }
+
+    /// Writes the entry, encoding its name with the given codec and reproducing the uninterpreted
+    /// bytes it was read with. The raw name field is only kept if the entry has not been renamed.
+    pub fn to_writer_with<W: Write>(&self, mut w: W, codec: &NameCodec) -> IoResult<()> {
+        let mut buf = [0; PK2_FILE_ENTRY_SIZE];
+        self.write_fields(&mut buf[..], codec.encoding)?;
+        if let Some(raw) = &self.raw {
+            self.restore_raw(&mut buf, raw);
+        }
+        if self.entry.is_some() {
+            codec.transform(&mut buf[NAME_RANGE]);
+        }
+        w.write_all(&buf)
+    }
+}
+
+impl RawIo for PackEntry {
+    /// Reads an entry from the given Read instance always reading exactly
+    /// PK2_FILE_ENTRY_SIZE bytes.
+    fn from_reader<R: Read>(r: R) -> IoResult<Self> {
+        Self::from_reader_with(r, &NameCodec::default(), false)
+    }
+
+    /// Writes the entry, reproducing the uninterpreted bytes it was read with. The raw name field
+    /// is only kept if the entry has not been renamed.
+    fn to_writer<W: Write>(&self, w: W) -> IoResult<()> {
+        self.to_writer_with(w, &NameCodec::default())
+    }
+}
+
+impl PackEntry {
+    /// Copies the uninterpreted bytes of the entry's original bytes `raw` into `buf`.
+    fn restore_raw(&self, buf: &mut [u8; PK2_FILE_ENTRY_SIZE], raw: &[u8; PK2_FILE_ENTRY_SIZE]) {
+        if buf[0] == raw[0] {
+            match self.entry {
+                None => {
+                    buf[1..NEXT_BLOCK_RANGE.start].copy_from_slice(&raw[1..NEXT_BLOCK_RANGE.start])
+                }
+                Some(_) if until_nul(&buf[NAME_RANGE]) == until_nul(&raw[NAME_RANGE]) => {
+                    buf[NAME_RANGE].copy_from_slice(&raw[NAME_RANGE])
+                }
+                Some(_) => (),
+            }
+        }
+        buf[PADDING_RANGE].copy_from_slice(&raw[PADDING_RANGE]);
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::num::NonZeroU64;
 
-    use crate::constants::{RawPackFileEntry, PK2_FILE_ENTRY_SIZE};
+    use crate::constants::{RawPackFileEntry, PK2_FILE_ENTRY_SIZE, PK2_FILE_NAME_LEN};
     use crate::data::entry::{DirectoryOrFile, NonEmptyEntry, PackEntry};
-    use crate::data::{ChainIndex, StreamOffset};
+    use crate::data::{ChainIndex, NameCodec, StreamOffset};
     use crate::filetime::FILETIME;
     use crate::io::RawIo;
 
@@ -303,7 +461,7 @@ mod test {
     fn pack_entry_read_directory() {
         let mut entry = RawPackFileEntry {
             ty: RawPackFileEntry::TY_DIRECTORY,
-            name: [0; 81],
+            name: [0; PK2_FILE_NAME_LEN],
             access: FILETIME::default(),
             create: FILETIME::default(),
             modify: FILETIME::default(),
@@ -322,11 +480,13 @@ mod test {
                 entry: Some(NonEmptyEntry {
                     kind: DirectoryOrFile::Directory { pos_children: ChainIndex(12345) },
                     name: "foobar".into(),
+                    lossy: false,
                     access_time: FILETIME::default(),
                     create_time: FILETIME::default(),
                     modify_time: FILETIME::default(),
                 }),
-                next_block: NonZeroU64::new(63459)
+                next_block: NonZeroU64::new(63459),
+                raw: None,
             }
         );
     }
@@ -335,7 +495,7 @@ mod test {
     fn pack_entry_read_file() {
         let mut entry = RawPackFileEntry {
             ty: RawPackFileEntry::TY_FILE,
-            name: [0; 81],
+            name: [0; PK2_FILE_NAME_LEN],
             access: FILETIME::default(),
             create: FILETIME::default(),
             modify: FILETIME::default(),
@@ -354,12 +514,84 @@ mod test {
                 entry: Some(NonEmptyEntry {
                     kind: DirectoryOrFile::File { pos_data: StreamOffset(12345), size: 10000 },
                     name: "foobar".into(),
+                    lossy: false,
                     access_time: FILETIME::default(),
                     create_time: FILETIME::default(),
                     modify_time: FILETIME::default(),
                 }),
-                next_block: NonZeroU64::new(63459)
+                next_block: NonZeroU64::new(63459),
+                raw: None,
             }
         );
     }
+
+    #[test]
+    fn pack_entry_preserves_uninterpreted_bytes() {
+        let mut entry = RawPackFileEntry {
+            ty: RawPackFileEntry::TY_FILE,
+            name: [0; PK2_FILE_NAME_LEN],
+            access: FILETIME::default(),
+            create: FILETIME::default(),
+            modify: FILETIME::default(),
+            position: 12345,
+            size: 10000,
+            next_block: 0,
+            _padding: [0xAB, 0xCD],
+        };
+        entry.name[..7].copy_from_slice(b"foobar\0");
+        entry.name[50..53].copy_from_slice(b"xyz");
+        let raw = *bytemuck::cast_ref::<_, [u8; PK2_FILE_ENTRY_SIZE]>(&entry);
+        let mut written = Vec::new();
+        PackEntry::from_reader(&raw[..]).unwrap().to_writer(&mut written).unwrap();
+        assert_eq!(written[PK2_FILE_ENTRY_SIZE - 2..], [0, 0]);
+
+        let codec = NameCodec::default();
+        let mut parsed = PackEntry::from_reader_with(&raw[..], &codec, true).unwrap();
+        written.clear();
+        parsed.to_writer(&mut written).unwrap();
+        assert_eq!(written, raw);
+
+        parsed.as_non_empty_mut().unwrap().set_name("baz");
+        written.clear();
+        parsed.to_writer(&mut written).unwrap();
+        assert_eq!(&written[1..5], b"baz\0");
+        assert!(written[5..1 + PK2_FILE_NAME_LEN].iter().all(|&b| b == 0));
+        assert_eq!(written[PK2_FILE_ENTRY_SIZE - 2..], [0xAB, 0xCD]);
+
+        let mut raw = [0; PK2_FILE_ENTRY_SIZE];
+        raw[20] = 7;
+        let mut written = Vec::new();
+        PackEntry::from_raw(&raw, &codec).unwrap().to_writer(&mut written).unwrap();
+        assert_eq!(written, raw);
+    }
+
+    #[test]
+    fn pack_entry_read_empty_file_without_position() {
+        let mut buf = [0u8; PK2_FILE_ENTRY_SIZE];
+        buf[0] = RawPackFileEntry::TY_FILE;
+        buf[1..6].copy_from_slice(b"empty");
+        let entry = PackEntry::from_reader(&buf[..]).unwrap();
+        assert!(matches!(
+            entry.as_non_empty().unwrap().kind,
+            DirectoryOrFile::File { pos_data: StreamOffset(0), size: 0 }
+        ));
+    }
+
+    #[test]
+    fn pack_entry_read_lossy_name() {
+        let codec = NameCodec::from(crate::NameEncoding::Utf8);
+        let mut buf = [0u8; PK2_FILE_ENTRY_SIZE];
+        buf[0] = RawPackFileEntry::TY_FILE;
+        buf[1..4].copy_from_slice("\u{FFFD}".as_bytes());
+        let entry = PackEntry::from_reader_with(&buf[..], &codec, false).unwrap();
+        assert!(!entry.as_non_empty().unwrap().is_name_lossy());
+
+        buf[1..4].copy_from_slice(b"\xFFab");
+        let mut entry = PackEntry::from_reader_with(&buf[..], &codec, false).unwrap();
+        let entry = entry.as_non_empty_mut().unwrap();
+        assert_eq!(entry.name(), "\u{FFFD}ab");
+        assert!(entry.is_name_lossy());
+        entry.set_name("ab");
+        assert!(!entry.is_name_lossy());
+    }
 }
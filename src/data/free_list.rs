@@ -0,0 +1,83 @@
+use crate::data::StreamOffset;
+
+/// A list of regions in the stream that are no longer referenced by any file entry and can be
+/// reused for new file data.
+#[derive(Default)]
+pub struct FreeList {
+    /// Free regions sorted by their offset, adjacent regions are always merged.
+    regions: Vec<(StreamOffset, u32)>,
+}
+
+impl FreeList {
+    /// Marks the given region as free, merging it with adjacent free regions.
+    pub fn free(&mut self, StreamOffset(offset): StreamOffset, len: u32) {
+        if len == 0 {
+            return;
+        }
+        let idx = self.regions.partition_point(|&(StreamOffset(it), _)| it < offset);
+        self.regions.insert(idx, (StreamOffset(offset), len));
+        // merge with the following region
+        if let Some(&(StreamOffset(next), next_len)) = self.regions.get(idx + 1) {
+            if offset + len as u64 == next {
+                if let Some(merged) = len.checked_add(next_len) {
+                    self.regions[idx].1 = merged;
+                    self.regions.remove(idx + 1);
+                }
+            }
+        }
+        // merge with the preceding region
+        if let Some(prev) = idx.checked_sub(1) {
+            let (StreamOffset(prev_offset), prev_len) = self.regions[prev];
+            if prev_offset + prev_len as u64 == offset {
+                if let Some(merged) = prev_len.checked_add(self.regions[idx].1) {
+                    self.regions[prev].1 = merged;
+                    self.regions.remove(idx);
+                }
+            }
+        }
+    }
+
+    /// Takes `len` bytes from the smallest free region that is big enough to hold them.
+    pub fn allocate(&mut self, len: u32) -> Option<StreamOffset> {
+        let (idx, _) = self
+            .regions
+            .iter()
+            .enumerate()
+            .filter(|(_, &(_, region_len))| region_len >= len)
+            .min_by_key(|(_, &(_, region_len))| region_len)?;
+        let (StreamOffset(offset), region_len) = self.regions[idx];
+        if region_len == len {
+            self.regions.remove(idx);
+        } else {
+            self.regions[idx] = (StreamOffset(offset + len as u64), region_len - len);
+        }
+        Some(StreamOffset(offset))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FreeList;
+    use crate::data::StreamOffset;
+
+    #[test]
+    fn free_merges_adjacent_regions() {
+        let mut list = FreeList::default();
+        list.free(StreamOffset(10), 10);
+        list.free(StreamOffset(30), 10);
+        list.free(StreamOffset(20), 10);
+        assert_eq!(list.regions, [(StreamOffset(10), 30)]);
+    }
+
+    #[test]
+    fn allocate_best_fit() {
+        let mut list = FreeList::default();
+        list.free(StreamOffset(0), 100);
+        list.free(StreamOffset(200), 10);
+        assert_eq!(list.allocate(5), Some(StreamOffset(200)));
+        assert_eq!(list.allocate(5), Some(StreamOffset(205)));
+        assert_eq!(list.allocate(5), Some(StreamOffset(0)));
+        assert_eq!(list.allocate(200), None);
+        assert_eq!(list.regions, [(StreamOffset(5), 95)]);
+    }
+}
@@ -36,6 +36,13 @@ impl PackHeader {
         this
     }
 
+    /// The trailing bytes of the header that are not used by the format itself.
+    ///
+    /// Some archives store a build stamp in these.
+    pub fn reserved(&self) -> &[u8] {
+        &self.reserved
+    }
+
     /// Validate the signature of this header. Returns an error if the version
     /// or signature does not match.
     pub fn validate_sig(&self) -> OpenResult<()> {
@@ -57,6 +64,29 @@ impl PackHeader {
             Ok(())
         }
     }
+
+    /// Verifies that the given cipher matches the checksum of this header.
+    pub fn verify_cipher(&self, bf: &Blowfish) -> OpenResult<()> {
+        let mut checksum = *PK2_CHECKSUM;
+        bf.encrypt(&mut checksum);
+        self.verify(checksum)
+    }
+
+    /// Reads just the header from the reader and checks whether the key opens the archive.
+    ///
+    /// Unencrypted archives are opened by any key.
+    pub fn read_and_verify<R: Read>(r: R, key: &[u8]) -> OpenResult<bool> {
+        let header = Self::from_reader(r)?;
+        header.validate_sig()?;
+        if !header.encrypted {
+            return Ok(true);
+        }
+        match header.verify_cipher(&Blowfish::for_encrypted_archive(key)?) {
+            Ok(()) => Ok(true),
+            Err(OpenError::InvalidKey) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
 }
 
 impl RawIo for PackHeader {
@@ -1,15 +1,26 @@
 use std::{error, fmt, io};
 
+use crate::data::{BlockOffset, ChainIndex};
+
 pub use crate::blowfish::InvalidKey;
 
 pub type ChainLookupResult<T> = Result<T, ChainLookupError>;
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum ChainLookupError {
     NotFound,
     InvalidPath,
     InvalidChainIndex,
-    ExpectedDirectory,
-    ExpectedFile,
+    /// A directory was found where a file was expected.
+    IsADirectory,
+    /// A file was found where a directory was expected.
+    IsAFile,
+    /// The path is longer than the game client supports.
+    PathTooLong,
+    /// A path component does not fit into the name field of an entry.
+    NameTooLong,
+    /// The archive has been marked as read-only at runtime.
+    ReadOnly,
 }
 
 impl error::Error for ChainLookupError {}
@@ -26,20 +37,88 @@ impl From<ChainLookupError> for io::Error {
             ChainLookupError::NotFound => io::ErrorKind::NotFound,
             ChainLookupError::InvalidPath => io::ErrorKind::InvalidInput,
             ChainLookupError::InvalidChainIndex => io::ErrorKind::InvalidData,
-            ChainLookupError::ExpectedDirectory => io::ErrorKind::NotFound,
-            ChainLookupError::ExpectedFile => io::ErrorKind::NotFound,
+            ChainLookupError::IsADirectory => io::ErrorKind::IsADirectory,
+            ChainLookupError::IsAFile => io::ErrorKind::NotADirectory,
+            ChainLookupError::PathTooLong => io::ErrorKind::InvalidInput,
+            ChainLookupError::NameTooLong => io::ErrorKind::InvalidInput,
+            ChainLookupError::ReadOnly => io::ErrorKind::PermissionDenied,
         }
         .into()
     }
 }
 
+/// A [`ChainLookupError`] annotated with the path component the lookup failed at.
+///
+/// This is the error carried by the [`io::Error`]s returned from path based archive operations.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PathLookupError {
+    error: ChainLookupError,
+    component: Option<Box<str>>,
+}
+
+impl PathLookupError {
+    pub(crate) fn new(error: ChainLookupError, component: Option<&str>) -> Self {
+        PathLookupError { error, component: component.map(Into::into) }
+    }
+
+    /// The underlying lookup error.
+    pub fn error(&self) -> ChainLookupError {
+        self.error
+    }
+
+    /// The path component the lookup failed at, if known.
+    pub fn component(&self) -> Option<&str> {
+        self.component.as_deref()
+    }
+}
+
+impl error::Error for PathLookupError {}
+impl fmt::Display for PathLookupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.component {
+            Some(component) => write!(f, "{} at path component `{}`", self.error, component),
+            None => fmt::Display::fmt(&self.error, f),
+        }
+    }
+}
+
+impl From<ChainLookupError> for PathLookupError {
+    #[inline]
+    fn from(error: ChainLookupError) -> Self {
+        PathLookupError { error, component: None }
+    }
+}
+
+impl From<PathLookupError> for io::Error {
+    #[inline]
+    fn from(this: PathLookupError) -> Self {
+        io::Error::new(io::Error::from(this.error).kind(), this)
+    }
+}
+
 pub type OpenResult<T> = std::result::Result<T, OpenError>;
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum OpenError {
+    /// The archive is encrypted but no key was given.
+    MissingKey,
+    /// The key is malformed or does not decrypt the archive.
     InvalidKey,
     CorruptedFile,
     UnsupportedVersion,
+    /// The same entry other than the last one links to another block in every block of a chain,
+    /// which means the archive uses a different number of entries per block than this crate.
+    UnsupportedBlockLayout,
+    /// The file table does not start with the root directory right after the header, for example
+    /// because the archive has additional padding in between.
+    InvalidRootBlock,
+    /// The directory or next block offset of the entry at index `entry` of the block at `block`
+    /// points before the end of the header or past the end of the archive.
+    InvalidEntryOffset {
+        block: BlockOffset,
+        entry: usize,
+    },
     Io(io::Error),
 }
 
@@ -49,6 +128,21 @@ impl fmt::Display for OpenError {
         match self {
             OpenError::CorruptedFile => write!(f, "archive is invalid or corrupted"),
             OpenError::UnsupportedVersion => write!(f, "archive version is not supported"),
+            OpenError::UnsupportedBlockLayout => write!(
+                f,
+                "archive blocks do not consist of {} entries",
+                crate::constants::PK2_FILE_BLOCK_ENTRY_COUNT
+            ),
+            OpenError::InvalidRootBlock => write!(
+                f,
+                "archive has no root directory block at offset {}",
+                crate::constants::PK2_ROOT_BLOCK.0
+            ),
+            OpenError::InvalidEntryOffset { block, entry } => write!(
+                f,
+                "entry {entry} of the block at offset {block} points outside of the archive"
+            ),
+            OpenError::MissingKey => write!(f, "archive is encrypted but no key was given"),
             OpenError::InvalidKey => write!(f, "blowfish key was invalid"),
             OpenError::Io(e) => fmt::Display::fmt(e, f),
         }
@@ -68,3 +162,48 @@ impl From<InvalidKey> for OpenError {
         OpenError::InvalidKey
     }
 }
+
+/// A recoverable anomaly found while parsing the file table of an archive, reported through
+/// [`OpenOptions::warnings`](crate::OpenOptions::warnings).
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseWarning {
+    /// Multiple directories point to the block chain at this offset, it is only parsed once.
+    SharedChain(ChainIndex),
+    /// The block at this offset links back to a block of its own chain, the chain ends with it.
+    BlockCycle(BlockOffset),
+    /// The name of the entry at index `entry` of the block at `block` is not valid in the name
+    /// encoding, invalid bytes are replaced by U+FFFD.
+    InvalidName { block: BlockOffset, entry: usize },
+    /// The entry at index `entry` of the block at `block` links to a next block although it is not
+    /// the last entry of the block, the link is ignored.
+    StrayNextBlock { block: BlockOffset, entry: usize },
+    /// The data of the file entry at index `entry` of the block at `block` starts before the end of
+    /// the header or extends past the end of the archive, so it can't be read correctly.
+    InvalidDataOffset { block: BlockOffset, entry: usize },
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseWarning::SharedChain(ChainIndex(offset)) => {
+                write!(f, "block chain at {offset:#x} is linked by multiple directories")
+            }
+            ParseWarning::BlockCycle(BlockOffset(offset)) => {
+                write!(f, "block at {offset:#x} links back into its own chain")
+            }
+            ParseWarning::InvalidName { block: BlockOffset(offset), entry } => {
+                write!(f, "entry {entry} of block at {offset:#x} has an invalid name")
+            }
+            ParseWarning::StrayNextBlock { block: BlockOffset(offset), entry } => {
+                write!(f, "entry {entry} of block at {offset:#x} links to a next block")
+            }
+            ParseWarning::InvalidDataOffset { block: BlockOffset(offset), entry } => {
+                write!(
+                    f,
+                    "data of entry {entry} of block at {offset:#x} lies outside of the archive"
+                )
+            }
+        }
+    }
+}
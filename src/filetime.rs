@@ -1,9 +1,13 @@
 //! Windows `FILETIME`
+use std::fmt;
 use std::time::{Duration, SystemTime};
 
 /// Windows `FILETIME`
+///
+/// The raw fields are kept for byte-exact work, the [`Debug`](fmt::Debug) output shows the
+/// combined tick count and the time it refers to instead.
 #[allow(non_snake_case, clippy::upper_case_acronyms)]
-#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[derive(Clone, Copy, Default, Eq, PartialEq)]
 pub struct FILETIME {
     pub dwLowDateTime: u32,
     pub dwHighDateTime: u32,
@@ -12,15 +16,31 @@ pub struct FILETIME {
 impl FILETIME {
     const MS_EPOCH: u64 = 11_6444_7360_0000_0000;
 
+    /// Returns the current system time as a `FILETIME`.
+    ///
+    /// Newly created entries have all of their times initialized to this.
     #[inline]
     pub fn now() -> Self {
         SystemTime::now().into()
     }
 
+    /// Returns the combined tick count, the number of 100 nanosecond intervals since
+    /// 1601-01-01.
+    #[inline]
+    pub fn ticks(self) -> u64 {
+        ((self.dwHighDateTime as u64) << 32) | self.dwLowDateTime as u64
+    }
+
+    /// Creates a `FILETIME` from a combined tick count.
+    #[inline]
+    pub fn from_ticks(ticks: u64) -> Self {
+        FILETIME { dwLowDateTime: ticks as u32, dwHighDateTime: (ticks >> 32) as u32 }
+    }
+
+    /// Converts this into a [`SystemTime`], returning `None` if the time lies before the unix
+    /// epoch.
     pub fn into_systime(self) -> Option<SystemTime> {
-        let FILETIME { dwLowDateTime: low, dwHighDateTime: high } = self;
-        let ftime = ((high as u64) << 32) | low as u64;
-        let nanos = (ftime.checked_sub(Self::MS_EPOCH)?) * 100;
+        let nanos = (self.ticks().checked_sub(Self::MS_EPOCH)?) * 100;
         Some(SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos))
     }
 }
@@ -29,10 +49,76 @@ impl From<SystemTime> for FILETIME {
     fn from(time: SystemTime) -> Self {
         let duration = time.duration_since(SystemTime::UNIX_EPOCH).unwrap();
         let ftime = (duration.as_nanos() / 100) as u64 + Self::MS_EPOCH;
-        FILETIME { dwLowDateTime: ftime as u32, dwHighDateTime: (ftime >> 32) as u32 }
+        FILETIME::from_ticks(ftime)
+    }
+}
+
+impl fmt::Debug for FILETIME {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("FILETIME");
+        s.field("ticks", &self.ticks());
+        if let Some(ticks) = self.ticks().checked_sub(Self::MS_EPOCH) {
+            s.field("time", &format_args!("{}", Rfc3339(ticks)));
+        }
+        s.finish()
+    }
+}
+
+/// Formats ticks since the unix epoch as an RFC 3339 UTC timestamp.
+struct Rfc3339(u64);
+
+impl fmt::Display for Rfc3339 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const TICKS_PER_SEC: u64 = 10_000_000;
+        let (secs, frac) = (self.0 / TICKS_PER_SEC, self.0 % TICKS_PER_SEC);
+        let (days, secs_of_day) = (secs / 86400, secs % 86400);
+        // civil date from days since the unix epoch, see
+        // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let doe = z % 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + (month <= 2) as u64;
+        write!(
+            f,
+            "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{frac:07}Z",
+            secs_of_day / 3600,
+            secs_of_day / 60 % 60,
+            secs_of_day % 60
+        )
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for FILETIME {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.ticks())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for FILETIME {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u64::deserialize(deserializer).map(FILETIME::from_ticks)
     }
 }
 
+#[test]
+fn test_debug() {
+    let unix_epoch = FILETIME::from_ticks(FILETIME::MS_EPOCH);
+    assert_eq!(
+        format!("{unix_epoch:?}"),
+        "FILETIME { ticks: 116444736000000000, time: 1970-01-01T00:00:00.0000000Z }"
+    );
+    let time = FILETIME::from_ticks(FILETIME::MS_EPOCH + 9_517_824_000_000_001);
+    assert!(format!("{time:?}").ends_with("time: 2000-02-29T00:00:00.0000001Z }"));
+    assert_eq!(format!("{:?}", FILETIME::default()), "FILETIME { ticks: 0 }");
+}
+
 #[test]
 fn test_convert_roundtrip() {
     let now = SystemTime::now();
@@ -1,94 +1,604 @@
 //! General io for reading/writing from/to buffers.
 
+use std::collections::{BTreeMap, HashMap};
 use std::io::{self, SeekFrom};
 
 use crate::blowfish::Blowfish;
 use crate::constants::{
-    PK2_CURRENT_DIR_IDENT, PK2_FILE_BLOCK_SIZE, PK2_FILE_ENTRY_SIZE, PK2_PARENT_DIR_IDENT,
+    PK2_CURRENT_DIR_IDENT, PK2_FILE_BLOCK_SIZE, PK2_FILE_ENTRY_SIZE, PK2_HEADER_SIZE,
+    PK2_PARENT_DIR_IDENT,
 };
 use crate::data::block_chain::{PackBlock, PackBlockChain};
 use crate::data::entry::PackEntry;
-use crate::data::{BlockOffset, ChainIndex, EntryOffset, StreamOffset};
+use crate::data::header::PackHeader;
+use crate::data::{BlockOffset, ChainIndex, EntryOffset, NameCodec, StreamOffset};
 use crate::error::OpenResult;
 
-/// Read a block at a given offset.
-pub fn read_block_at<F: io::Seek + io::Read>(
+/// Positional read access to the storage backing an archive.
+///
+/// This is implemented for every [`Read`](io::Read) + [`Seek`](io::Seek) type. Custom
+/// implementations can be used to spread an archive over multiple underlying files by routing
+/// reads depending on their offset.
+pub trait Backing {
+    /// Reads bytes starting at the given offset into `buf`, returning the number of bytes read.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Reads exactly `buf.len()` bytes starting at the given offset.
+    fn read_exact_at(&mut self, mut offset: u64, mut buf: &mut [u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_at(offset, buf) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the size of the storage in bytes.
+    ///
+    /// This is only used for analyzing the layout of an archive, so the default implementation
+    /// returns an [`Unsupported`](io::ErrorKind::Unsupported) error.
+    fn storage_size(&mut self) -> io::Result<u64> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Storage that holds the whole archive in memory, which allows borrowing file data from it
+/// directly with [`Pk2::file_slice`](crate::Pk2::file_slice).
+///
+/// This trait is sealed, as the returned bytes have to stay in place and unchanged while the
+/// storage is only read from.
+pub trait AsBytes: Backing + sealed::Sealed {
+    /// Returns all bytes of the storage.
+    fn as_bytes(&self) -> &[u8];
+}
+
+impl AsBytes for io::Cursor<Vec<u8>> {
+    fn as_bytes(&self) -> &[u8] {
+        self.get_ref()
+    }
+}
+
+impl AsBytes for io::Cursor<&[u8]> {
+    fn as_bytes(&self) -> &[u8] {
+        self.get_ref()
+    }
+}
+
+impl<B: AsBytes + io::Read + io::Seek> AsBytes for crate::ReadOnly<B> {
+    fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+    impl Sealed for std::io::Cursor<Vec<u8>> {}
+    impl Sealed for std::io::Cursor<&[u8]> {}
+    impl<B: Sealed> Sealed for crate::ReadOnly<B> {}
+}
+
+/// Positional write access to the storage backing an archive.
+///
+/// This is implemented for every [`Write`](io::Write) + [`Seek`](io::Seek) type.
+pub trait BackingMut {
+    /// Writes all of `data` starting at the given offset.
+    fn write_all_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()>;
+
+    /// The offset at which appended data will be placed.
+    fn end(&mut self) -> io::Result<u64>;
+
+    /// Writes `data` to the end of the storage, returning the offset it was written at.
+    fn append(&mut self, data: &[u8]) -> io::Result<u64> {
+        let end = self.end()?;
+        self.write_all_at(end, data)?;
+        Ok(end)
+    }
+}
+
+impl<T: io::Read + io::Seek> Backing for T {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.read(buf)
+    }
+
+    fn read_exact_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.read_exact(buf)
+    }
+
+    fn storage_size(&mut self) -> io::Result<u64> {
+        self.seek(SeekFrom::End(0))
+    }
+}
+
+impl<T: io::Write + io::Seek> BackingMut for T {
+    fn write_all_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.write_all(data)
+    }
+
+    fn end(&mut self) -> io::Result<u64> {
+        self.seek(SeekFrom::End(0))
+    }
+}
+
+/// An IO wrapper type that only exposes read and seek operations.
+pub struct ReadOnly<B>(pub B);
+impl<B: io::Read> io::Read for ReadOnly<B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+impl<B: io::Seek> io::Seek for ReadOnly<B> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+/// An IO wrapper type that treats the given offset of the inner stream as its start.
+///
+/// This allows opening archives embedded in other files, like installers, without extracting
+/// them first.
+pub struct Offset<B> {
+    inner: B,
+    base: u64,
+}
+
+impl<B: io::Seek> Offset<B> {
+    /// Wraps the stream, making `base` its logical start.
+    pub fn new(mut inner: B, base: u64) -> io::Result<Self> {
+        inner.seek(io::SeekFrom::Start(base))?;
+        Ok(Offset { inner, base })
+    }
+}
+
+impl<B> Offset<B> {
+    /// The offset of the inner stream the logical stream starts at.
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    /// Returns the inner stream.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl<B: io::Read> io::Read for Offset<B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+impl<B: io::Write> io::Write for Offset<B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+impl<B: io::Seek> io::Seek for Offset<B> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        // compute the target first so that invalid seeks leave the inner stream where it is
+        let (origin, delta) = match pos {
+            SeekFrom::Start(pos) => (Some(0), pos as i128),
+            SeekFrom::Current(delta) => {
+                (self.inner.stream_position()?.checked_sub(self.base), delta as i128)
+            }
+            SeekFrom::End(delta) => {
+                let current = self.inner.stream_position()?;
+                let end = self.inner.seek(SeekFrom::End(0))?;
+                self.inner.seek(SeekFrom::Start(current))?;
+                (end.checked_sub(self.base), delta as i128)
+            }
+        };
+        let pos = origin.and_then(|origin| u64::try_from(origin as i128 + delta).ok()).ok_or_else(
+            || {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "seeked before the start of the embedded stream",
+                )
+            },
+        )?;
+        let inner_pos = self.base.checked_add(pos).ok_or(io::ErrorKind::InvalidInput)?;
+        self.inner.seek(SeekFrom::Start(inner_pos))?;
+        Ok(pos)
+    }
+}
+
+/// A transformation applied to all bytes of an archive as they are read from and written to its
+/// storage, used by [`Transformed`].
+///
+/// This allows supporting archives that are obfuscated as a whole on top of the block table
+/// encryption, like XORing every byte. The offset of the bytes in the storage is passed along so
+/// that position dependent schemes can be implemented.
+pub trait Transform {
+    /// Reverts the transformation of bytes read at the given offset in place.
+    fn decode(&self, offset: u64, data: &mut [u8]);
+
+    /// Applies the transformation to bytes about to be written at the given offset in place.
+    fn encode(&self, offset: u64, data: &mut [u8]);
+}
+
+/// A [`Backing`] wrapper that applies a [`Transform`] to everything read and written.
+pub struct Transformed<B, T> {
+    inner: B,
+    transform: T,
+}
+
+impl<B, T> Transformed<B, T> {
+    /// Wraps the storage, applying `transform` to all reads and writes.
+    pub fn new(inner: B, transform: T) -> Self {
+        Transformed { inner, transform }
+    }
+
+    /// Returns the inner storage.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl<B: Backing, T: Transform> Backing for Transformed<B, T> {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read_at(offset, buf)?;
+        self.transform.decode(offset, &mut buf[..n]);
+        Ok(n)
+    }
+
+    fn read_exact_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.inner.read_exact_at(offset, buf)?;
+        self.transform.decode(offset, buf);
+        Ok(())
+    }
+
+    fn storage_size(&mut self) -> io::Result<u64> {
+        self.inner.storage_size()
+    }
+}
+
+impl<B: BackingMut, T: Transform> BackingMut for Transformed<B, T> {
+    fn write_all_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let mut data = data.to_vec();
+        self.transform.encode(offset, &mut data);
+        self.inner.write_all_at(offset, &data)
+    }
+
+    fn end(&mut self) -> io::Result<u64> {
+        self.inner.end()
+    }
+}
+
+/// Hit and miss counts of a [`BlockCache`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// A [`Backing`] wrapper that keeps the most recently read pages of the storage in memory.
+///
+/// The storage is split into pages of [`Pk2::BLOCK_SIZE`](crate::Pk2::BLOCK_SIZE) bytes, keyed by
+/// their offset. Once the capacity is reached, the least recently used page is evicted. Writes are
+/// passed through to the storage, dropping the pages they touch.
+pub struct BlockCache<B> {
+    inner: B,
+    capacity: usize,
+    pages: HashMap<u64, (u64, Box<[u8]>)>,
+    /// The pages ordered by when they were last used.
+    lru: BTreeMap<u64, u64>,
+    clock: u64,
+    stats: CacheStats,
+}
+
+impl<B> BlockCache<B> {
+    const PAGE_SIZE: u64 = PK2_FILE_BLOCK_SIZE as u64;
+
+    /// Wraps the storage, keeping up to `capacity` pages in memory.
+    pub fn new(inner: B, capacity: usize) -> Self {
+        BlockCache {
+            inner,
+            capacity,
+            pages: HashMap::new(),
+            lru: BTreeMap::new(),
+            clock: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Returns the number of reads that were served from and missed the cache.
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Returns the inner storage.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+
+    fn invalidate(&mut self, offset: u64, len: usize) {
+        let first = offset / Self::PAGE_SIZE;
+        let last = (offset + len as u64).div_ceil(Self::PAGE_SIZE);
+        for page in first..last {
+            if let Some((used, _)) = self.pages.remove(&(page * Self::PAGE_SIZE)) {
+                self.lru.remove(&used);
+            }
+        }
+    }
+}
+
+impl<B: Backing> BlockCache<B> {
+    fn page(&mut self, page: u64) -> io::Result<&[u8]> {
+        self.clock += 1;
+        let used = self.clock;
+        if let Some((last_used, _)) = self.pages.get(&page) {
+            self.stats.hits += 1;
+            self.lru.remove(last_used);
+        } else {
+            self.stats.misses += 1;
+            let mut buf = vec![0; Self::PAGE_SIZE as usize];
+            let mut len = 0;
+            while len < buf.len() {
+                match self.inner.read_at(page + len as u64, &mut buf[len..]) {
+                    Ok(0) => break,
+                    Ok(n) => len += n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                    Err(e) => return Err(e),
+                }
+            }
+            buf.truncate(len);
+            if self.pages.len() >= self.capacity.max(1) {
+                if let Some((_, evicted)) = self.lru.pop_first() {
+                    self.pages.remove(&evicted);
+                }
+            }
+            self.pages.insert(page, (used, buf.into_boxed_slice()));
+        }
+        self.lru.insert(used, page);
+        let (last_used, data) = self.pages.get_mut(&page).unwrap();
+        *last_used = used;
+        Ok(data)
+    }
+}
+
+impl<B: Backing> Backing for BlockCache<B> {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let page_offset = (offset % Self::PAGE_SIZE) as usize;
+        let page = self.page(offset - page_offset as u64)?;
+        let available = page.get(page_offset..).unwrap_or_default();
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        Ok(n)
+    }
+
+    fn storage_size(&mut self) -> io::Result<u64> {
+        self.inner.storage_size()
+    }
+}
+
+impl<B: BackingMut> BackingMut for BlockCache<B> {
+    fn write_all_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        self.invalidate(offset, data.len());
+        self.inner.write_all_at(offset, data)
+    }
+
+    fn end(&mut self) -> io::Result<u64> {
+        self.inner.end()
+    }
+}
+
+/// A [`Backing`] wrapper that coalesces sequential reads by reading a window of several blocks
+/// at once, serving following reads that fall into the window from memory.
+///
+/// Used when parsing the file table, whose blocks are often stored back to back.
+pub struct ReadAhead<'a, F: ?Sized> {
+    stream: &'a mut F,
+    buf: Vec<u8>,
+    start: u64,
+    /// The end of the last read that was passed through to the stream.
+    last_end: Option<u64>,
+    /// The number of bytes read at once.
+    window: usize,
+    /// Whether reads outside of the window refill it even if they don't continue the previous
+    /// read.
+    always_fill: bool,
+}
+
+impl<'a, F: Backing + ?Sized> ReadAhead<'a, F> {
+    const WINDOW: usize = 16 * PK2_FILE_BLOCK_SIZE;
+    /// The biggest window [`ReadAhead::with_window`] allocates.
+    const MAX_WINDOW: usize = 64 << 20;
+
+    pub fn new(stream: &'a mut F) -> Self {
+        ReadAhead {
+            stream,
+            buf: Vec::new(),
+            start: 0,
+            last_end: None,
+            window: Self::WINDOW,
+            always_fill: false,
+        }
+    }
+
+    /// Creates a read-ahead that reads `window` bytes, but at least one block and at most 64 MiB,
+    /// whenever a read falls outside of the current window, regardless of whether the read is
+    /// sequential.
+    pub fn with_window(stream: &'a mut F, window: usize) -> Self {
+        ReadAhead {
+            window: window.clamp(PK2_FILE_BLOCK_SIZE, Self::MAX_WINDOW),
+            always_fill: true,
+            ..Self::new(stream)
+        }
+    }
+
+    fn fill(&mut self, offset: u64) -> io::Result<()> {
+        self.buf.resize(self.window, 0);
+        let mut len = 0;
+        while len < self.buf.len() {
+            match self.stream.read_at(offset + len as u64, &mut self.buf[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    self.buf.clear();
+                    return Err(e);
+                }
+            }
+        }
+        self.buf.truncate(len);
+        self.start = offset;
+        Ok(())
+    }
+}
+
+impl<F: Backing + ?Sized> Backing for ReadAhead<'_, F> {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let buffered = self.start..self.start + self.buf.len() as u64;
+        if !buffered.contains(&offset) {
+            if !self.always_fill && self.last_end != Some(offset) {
+                // random access, reading ahead would most likely be wasted
+                let n = self.stream.read_at(offset, buf)?;
+                self.last_end = Some(offset + n as u64);
+                return Ok(n);
+            }
+            self.fill(offset)?;
+        }
+        let available = &self.buf[(offset - self.start) as usize..];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.last_end = Some(offset + n as u64);
+        Ok(n)
+    }
+
+    fn storage_size(&mut self) -> io::Result<u64> {
+        self.stream.storage_size()
+    }
+}
+
+/// Read the archive header at the start of the stream.
+pub fn read_header<F: Backing + ?Sized>(stream: &mut F) -> io::Result<PackHeader> {
+    let mut buf = [0; PK2_HEADER_SIZE];
+    stream.read_exact_at(0, &mut buf)?;
+    PackHeader::from_reader(&buf[..])
+}
+
+/// Write the archive header to the start of the stream.
+pub fn write_header<F: BackingMut + ?Sized>(stream: &mut F, header: &PackHeader) -> io::Result<()> {
+    let mut buf = [0; PK2_HEADER_SIZE];
+    header.to_writer(&mut buf[..])?;
+    stream.write_all_at(0, &buf)
+}
+
+/// Read a block at a given offset, keeping the uninterpreted bytes of its entries if
+/// `preserve_raw` is set.
+pub fn read_block_at<F: Backing + ?Sized>(
     bf: Option<&Blowfish>,
-    mut stream: F,
+    stream: &mut F,
     BlockOffset(offset): BlockOffset,
+    codec: &NameCodec,
+    preserve_raw: bool,
 ) -> OpenResult<PackBlock> {
     let mut buf = [0; PK2_FILE_BLOCK_SIZE];
-    stream.seek(SeekFrom::Start(offset))?;
-    stream.read_exact(&mut buf)?;
+    stream.read_exact_at(offset, &mut buf)?;
     if let Some(bf) = bf {
         bf.decrypt(&mut buf);
     }
-    PackBlock::from_reader(&buf[..]).map_err(Into::into)
+    PackBlock::from_reader_with(&buf[..], codec, preserve_raw).map_err(Into::into)
 }
 
-pub fn read_exact_at<F: io::Seek + io::Read>(
-    mut stream: F,
+/// Read a block at a given offset, tolerating the stream ending within the block.
+///
+/// Entries that have not been read completely are replaced by empty ones. Returns whether the
+/// block was truncated.
+pub fn read_block_at_lenient<F: Backing + ?Sized>(
+    bf: Option<&Blowfish>,
+    stream: &mut F,
+    BlockOffset(offset): BlockOffset,
+    codec: &NameCodec,
+    preserve_raw: bool,
+) -> OpenResult<(PackBlock, bool)> {
+    let mut buf = [0; PK2_FILE_BLOCK_SIZE];
+    let mut len = 0;
+    while len < buf.len() {
+        match stream.read_at(offset + len as u64, &mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let complete = len - len % PK2_FILE_ENTRY_SIZE;
+    if let Some(bf) = bf {
+        bf.decrypt(&mut buf[..complete]);
+    }
+    buf[complete..].fill(0);
+    let block = PackBlock::from_reader_with(&buf[..], codec, preserve_raw)?;
+    Ok((block, len < buf.len()))
+}
+
+pub fn read_exact_at<F: Backing + ?Sized>(
+    stream: &mut F,
     StreamOffset(offset): StreamOffset,
     buf: &mut [u8],
 ) -> io::Result<()> {
-    stream.seek(SeekFrom::Start(offset))?;
-    stream.read_exact(buf)
+    stream.read_exact_at(offset, buf)
 }
 
-pub fn read_at<F: io::Seek + io::Read>(
-    mut stream: F,
+pub fn read_at<F: Backing + ?Sized>(
+    stream: &mut F,
     StreamOffset(offset): StreamOffset,
     buf: &mut [u8],
 ) -> io::Result<usize> {
-    stream.seek(SeekFrom::Start(offset))?;
-    stream.read(buf)
-}
-
-fn stream_len<F: io::Seek>(mut stream: F) -> io::Result<u64> {
-    stream.seek(SeekFrom::End(0))
+    stream.read_at(offset, buf)
 }
 
-/// Write/Update a block at the given block offset in the file.
-pub fn write_block<F: io::Seek + io::Write>(
+/// Write/Update a block at the given block offset in the file, encoding the names of its entries
+/// with the given codec.
+pub fn write_block<F: BackingMut + ?Sized>(
     bf: Option<&Blowfish>,
-    mut stream: F,
+    stream: &mut F,
     BlockOffset(offset): BlockOffset,
     block: &PackBlock,
+    codec: &NameCodec,
 ) -> io::Result<()> {
     let mut buf = [0; PK2_FILE_BLOCK_SIZE];
-    block.to_writer(&mut buf[..])?;
+    block.to_writer_with(&mut buf[..], codec)?;
     if let Some(bf) = bf {
         bf.encrypt(&mut buf);
     }
-    stream.seek(SeekFrom::Start(offset))?;
-    stream.write_all(&buf)?;
-    Ok(())
+    stream.write_all_at(offset, &buf)
 }
 
-/// Write/Update an entry at the given entry offset in the file.
-pub fn write_entry_at<F: io::Seek + io::Write>(
+/// Write/Update an entry at the given entry offset in the file, encoding its name with the given
+/// codec.
+pub fn write_entry_at<F: BackingMut + ?Sized>(
     bf: Option<&Blowfish>,
-    mut stream: F,
+    stream: &mut F,
     EntryOffset(offset): EntryOffset,
     entry: &PackEntry,
+    codec: &NameCodec,
 ) -> io::Result<()> {
     let mut buf = [0; PK2_FILE_ENTRY_SIZE];
-    entry.to_writer(&mut buf[..])?;
+    entry.to_writer_with(&mut buf[..], codec)?;
     if let Some(bf) = bf {
         bf.encrypt(&mut buf);
     }
-    stream.seek(SeekFrom::Start(offset))?;
-    stream.write_all(&buf)?;
-    Ok(())
+    stream.write_all_at(offset, &buf)
 }
 
 /// Write/Update a chain's entry at the given chain offset and entry index in
 /// the file.
-pub fn write_chain_entry<F: io::Seek + io::Write>(
+pub fn write_chain_entry<F: BackingMut + ?Sized>(
     bf: Option<&Blowfish>,
-    stream: F,
+    stream: &mut F,
     chain: &PackBlockChain,
     entry_index: usize,
+    codec: &NameCodec,
 ) -> io::Result<()> {
     debug_assert!(chain.contains_entry_index(entry_index));
     write_entry_at(
@@ -96,41 +606,70 @@ pub fn write_chain_entry<F: io::Seek + io::Write>(
         stream,
         chain.stream_offset_for_entry(entry_index).unwrap(),
         &chain[entry_index],
+        codec,
     )
 }
 
 /// Write data to the end of the file returning the offset of the written
 /// data in the file.
-pub fn append_data<F: io::Seek + io::Write>(
-    mut stream: F,
+///
+/// The data is written exactly at the current end of the stream, so it can never overlap the
+/// existing contents of the archive.
+pub fn append_data<F: BackingMut + ?Sized>(
+    stream: &mut F,
     data: &[u8],
 ) -> io::Result<StreamOffset> {
-    let stream_end = stream_len(&mut stream)?;
-    stream.write_all(data)?;
-    Ok(StreamOffset(stream_end))
+    let end = end_for_append(stream, data.len())?;
+    stream.write_all_at(end, data)?;
+    Ok(StreamOffset(end))
+}
+
+/// Returns the end of the stream, checking that `len` more bytes can be appended to it without
+/// overflowing the offsets of the archive.
+fn end_for_append<F: BackingMut + ?Sized>(stream: &mut F, len: usize) -> io::Result<u64> {
+    let end = stream.end()?;
+    match end.checked_add(len as u64) {
+        Some(_) => Ok(end),
+        None => Err(io::Error::new(
+            io::ErrorKind::StorageFull,
+            "the archive can not grow past u64::MAX bytes",
+        )),
+    }
 }
 
 /// Write raw data at the given offset into the buffer.
-pub fn write_data_at<F: io::Seek + io::Write>(
-    mut stream: F,
+///
+/// The data has to fit into the `region_len` bytes at `offset`, which in turn have to lie within
+/// the stream, otherwise an error is returned as the region is not owned by the data's file.
+pub fn write_data_at<F: BackingMut + ?Sized>(
+    stream: &mut F,
     StreamOffset(offset): StreamOffset,
     data: &[u8],
+    region_len: u32,
 ) -> io::Result<()> {
-    stream.seek(SeekFrom::Start(offset))?;
-    stream.write_all(data)
+    let stream_end = stream.end()?;
+    let region_end = offset.checked_add(region_len as u64);
+    if data.len() > region_len as usize || region_end.is_none_or(|end| end > stream_end) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "file data does not fit into its region of the archive",
+        ));
+    }
+    stream.write_all_at(offset, data)
 }
 
 /// Create a new [`PackBlockChain`] at the end of the buffer and update the
 /// corresponding entry in the chain.
-pub fn allocate_new_block_chain<F: io::Seek + io::Write>(
+pub fn allocate_new_block_chain<F: BackingMut + ?Sized>(
     blowfish: Option<&Blowfish>,
-    mut stream: F,
+    stream: &mut F,
     current_chain: &mut PackBlockChain,
     dir_name: &str,
+    codec: &NameCodec,
     chain_entry_idx: usize,
 ) -> io::Result<PackBlockChain> {
     debug_assert!(current_chain.contains_entry_index(chain_entry_idx));
-    let new_chain_offset = stream_len(&mut stream).map(ChainIndex)?;
+    let new_chain_offset = end_for_append(stream, PK2_FILE_BLOCK_SIZE).map(ChainIndex)?;
 
     let entry = &mut current_chain[chain_entry_idx];
     debug_assert!(entry.is_empty());
@@ -139,22 +678,23 @@ pub fn allocate_new_block_chain<F: io::Seek + io::Write>(
     let mut block = PackBlock::default();
     block[0] = PackEntry::new_directory(PK2_CURRENT_DIR_IDENT, new_chain_offset, None);
     block[1] = PackEntry::new_directory(PK2_PARENT_DIR_IDENT, current_chain.chain_index(), None);
-    write_block(blowfish, &mut stream, new_chain_offset.into(), &block)?;
+    write_block(blowfish, stream, new_chain_offset.into(), &block, codec)?;
 
     let offset = current_chain.stream_offset_for_entry(chain_entry_idx).unwrap();
 
-    write_entry_at(blowfish, stream, offset, &current_chain[chain_entry_idx])?;
+    write_entry_at(blowfish, stream, offset, &current_chain[chain_entry_idx], codec)?;
     Ok(PackBlockChain::from_blocks(vec![(new_chain_offset.into(), block)]))
 }
 
 /// Create a new empty [`PackBlock`] at the end of the buffer.
-pub fn allocate_empty_block<F: io::Seek + io::Write>(
+pub fn allocate_empty_block<F: BackingMut + ?Sized>(
     bf: Option<&Blowfish>,
-    mut stream: F,
+    stream: &mut F,
 ) -> io::Result<(BlockOffset, PackBlock)> {
-    let offset = stream_len(&mut stream).map(BlockOffset)?;
+    let offset = end_for_append(stream, PK2_FILE_BLOCK_SIZE).map(BlockOffset)?;
     let block = PackBlock::default();
-    write_block(bf, stream, offset, &block).and(Ok((offset, block)))
+    // an empty block has no names to encode
+    write_block(bf, stream, offset, &block, &NameCodec::default()).and(Ok((offset, block)))
 }
 
 pub trait RawIo: Sized {
@@ -13,8 +13,13 @@
 //! # Features
 //!
 //! - `euc-kr`: enabled by default, adds `encoding_rs` as a dependency which changes string reading
-//!             and writing to use the `euc-kr` encoding which is required for the original game
-//!             archives.
+//!   and writing to use the `euc-kr` encoding which is required for the original game archives.
+//! - `tar`: adds `tar` as a dependency which enables exporting archive contents as a tar stream via
+//!   `Pk2::export_tar`.
+//! - `hardened-hasher`: hashes block offsets with a randomly seeded hasher instead of using them as
+//!   their own hash, for opening untrusted archives whose offsets could be chosen to collide.
+//! - `serde`: adds `serde` as a dependency which implements `Serialize` and `Deserialize` for
+//!   `FILETIME`, representing it as its combined tick count.
 mod blowfish;
 mod constants;
 mod data;
@@ -22,24 +27,25 @@ mod filetime;
 mod io;
 
 mod api;
-pub use self::api::fs::{DirEntry, Directory, File, FileMut};
-pub use self::api::Pk2;
+pub use self::api::builder::Pk2Builder;
+pub use self::api::extract::{glob_match, ExtractOptions, ExtractStats, OnConflict};
+pub use self::api::fs::{
+    DirEntry, DirEntryInfo, Directory, DirectoryMut, EntryKind, EntrySlot, File, FileMut, Files,
+};
+pub use self::api::{normalize_path, OpenOptions, Pk2, WalkAction};
+pub use self::blowfish::Blowfish;
+pub use self::data::{
+    BlockOffset, CaseSensitivity, ChainIndex, NameEncoding, NameTransform, StreamOffset,
+};
+pub use self::filetime::FILETIME;
+pub use self::io::{
+    AsBytes, Backing, BackingMut, BlockCache, CacheStats, Offset, ReadOnly, Transform, Transformed,
+};
 
 mod error;
-pub use self::error::{ChainLookupError, ChainLookupResult, InvalidKey, OpenError};
-
-/// An IO wrapper type that only exposes read and seek operations.
-pub struct ReadOnly<B>(pub B);
-impl<B: std::io::Read> std::io::Read for ReadOnly<B> {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.0.read(buf)
-    }
-}
-impl<B: std::io::Seek> std::io::Seek for ReadOnly<B> {
-    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
-        self.0.seek(pos)
-    }
-}
+pub use self::error::{
+    ChainLookupError, ChainLookupResult, InvalidKey, OpenError, ParseWarning, PathLookupError,
+};
 
 /// A type that allows mutable access to its inner value via interior mutability.
 pub trait Lock<T> {
@@ -65,6 +71,8 @@ pub trait LockChoice {
 macro_rules! gen_type_aliases {
     ($lock:ident) => {
         pub type Pk2<Buffer = std::fs::File> = crate::api::Pk2<Buffer, $lock>;
+        pub type Pk2Builder<Buffer = std::fs::File> =
+            crate::api::builder::Pk2Builder<Buffer, $lock>;
 
         pub type File<'pk2, Buffer = std::fs::File> = crate::api::fs::File<'pk2, Buffer, $lock>;
         pub type FileMut<'pk2, Buffer = std::fs::File> =
@@ -73,6 +81,9 @@ macro_rules! gen_type_aliases {
             crate::api::fs::DirEntry<'pk2, Buffer, $lock>;
         pub type Directory<'pk2, Buffer = std::fs::File> =
             crate::api::fs::Directory<'pk2, Buffer, $lock>;
+        pub type DirectoryMut<'pk2, Buffer = std::fs::File> =
+            crate::api::fs::DirectoryMut<'pk2, Buffer, $lock>;
+        pub type Files<'pk2, Buffer = std::fs::File> = crate::api::fs::Files<'pk2, Buffer, $lock>;
         /// Read-only versions of the API types.
         pub mod readonly {
             pub type Pk2<Buffer = std::fs::File> = super::Pk2<crate::ReadOnly<Buffer>>;
@@ -85,6 +96,8 @@ macro_rules! gen_type_aliases {
                 super::DirEntry<'pk2, crate::ReadOnly<Buffer>>;
             pub type Directory<'pk2, Buffer = std::fs::File> =
                 super::Directory<'pk2, crate::ReadOnly<Buffer>>;
+            pub type Files<'pk2, Buffer = std::fs::File> =
+                super::Files<'pk2, crate::ReadOnly<Buffer>>;
         }
     };
 }
@@ -94,6 +107,9 @@ pub mod sync {
     use std::sync::Mutex;
 
     /// A lock that uses a [`std::sync::Mutex`] to provide interior mutability.
+    ///
+    /// Archives using this lock are `Send` and `Sync` as long as their buffer is `Send`, so they
+    /// can be shared across threads to read files concurrently.
     pub enum Lock {}
     impl super::LockChoice for Lock {
         type Lock<T> = Mutex<T>;